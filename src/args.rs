@@ -1,8 +1,13 @@
-use std::env;
 use std::path::PathBuf;
 
 use clap::Parser;
 
+use pngme::ChunkPosition;
+use pngme::bundle::ATTACHMENT_CHUNK_TYPE;
+use pngme::commands::{EmbedMethod, InputEncoding, OutputFormat};
+use pngme::compression::Compression;
+use pngme::strip::DEFAULT_PRESET;
+
 #[derive(Debug, Parser)]
 #[command(author, version, about, long_about = None)]
 pub struct Commands {
@@ -17,21 +22,70 @@ pub enum PngMeArgs {
     Decode(DecodeArgs),
     Remove(RemoveArgs),
     Print(PrintArgs),
+    Info(InfoArgs),
+    Check(CheckArgs),
+    Keygen(KeygenArgs),
+    Verify(VerifyArgs),
+    Seal(SealArgs),
+    Unseal(UnsealArgs),
+    Attach(AttachArgs),
+    Extract(ExtractArgs),
+    Ls(LsArgs),
+    Capacity(CapacityArgs),
+    Scan(ScanArgs),
+    Analyze(AnalyzeArgs),
+    Strip(StripArgs),
 }
 
-/// add a secret message at the end of a PNG file, after EOF chunk
+/// add a secret message to a PNG file, by default right before the IEND chunk
 #[derive(Debug, Parser, PartialEq, Clone)]
 #[command(version, about, long_about=None)]
 pub struct EncodeArgs {
     // file path for PNG file
     #[arg(short='f', long, value_name="PNG_PATH", value_hint=clap::ValueHint::DirPath)]
     pub input_path : PathBuf,
-    // type of the new messages´ chunk
+    // type of the new messages´ chunk; only binds the signature for `--method lsb`
     #[arg(short='c', long, value_name="CHUNK_TYPE")]
-    pub chunk_type : String,
-    // message to encode in PNG file
+    pub chunk_type : Option<String>,
+    // hide the message in a chunk of its own, or in the least significant bits of the pixels
+    #[arg(long, value_enum, default_value_t=EmbedMethod::default())]
+    pub method : EmbedMethod,
+    // pixel sample bits replaced by `--method lsb` (default 1)
+    #[arg(long, value_name="BITS", value_parser=clap::value_parser!(u8).range(1..=2))]
+    pub lsb_bits : Option<u8>,
+    // scatter the bits of `--method lsb` across pixels chosen by the passphrase (prompted for, or taken from PNGME_PASSPHRASE)
+    #[arg(long, action)]
+    pub scatter : bool,
+    // message to encode in PNG file; read from stdin if neither this nor a message file is given
     #[arg(short='m', long, value_name="MESSAGE")]
-    pub message : String,
+    pub message : Option<String>,
+    // read the message from a file instead, keeping it out of the shell history
+    #[arg(short='M', long, value_name="MESSAGE_PATH", value_hint=clap::ValueHint::FilePath, conflicts_with="message")]
+    pub message_file : Option<PathBuf>,
+    // how the message is encoded; `hex` and `base64` allow embedding arbitrary binary data
+    #[arg(short='e', long, value_enum, default_value_t=InputEncoding::default())]
+    pub input_encoding : InputEncoding,
+    // where to place the message chunk; `end` appends after IEND
+    #[arg(short='p', long, value_enum, default_value_t=ChunkPosition::default())]
+    pub position : ChunkPosition,
+    // allow critical or public chunk types for the message chunk
+    #[arg(long, action)]
+    pub force : bool,
+    // compress the message; decode decompresses it transparently
+    #[arg(short='z', long, value_enum, default_value_t=Compression::default())]
+    pub compress : Compression,
+    // encrypt the message with a passphrase (prompted for, or taken from PNGME_PASSPHRASE)
+    #[arg(long, action)]
+    pub encrypt : bool,
+    // encrypt the message to an age public key (`age1...`); repeat for several recipients
+    #[arg(short='r', long="recipient", value_name="RECIPIENT", conflicts_with="encrypt")]
+    pub recipients : Vec<String>,
+    // sign the message with an Ed25519 signing key file, as created by `keygen --signing`
+    #[arg(long, value_name="SIGN_KEY_PATH", value_hint=clap::ValueHint::FilePath)]
+    pub sign_key : Option<PathBuf>,
+    // split the message across several chunks of at most this many data bytes (default 1 MiB)
+    #[arg(long, value_name="BYTES")]
+    pub max_chunk_size : Option<usize>,
     // optional: new file path for output PNG
     #[arg(short='o', long, value_name="NEW_FILEPATH", value_hint=clap::ValueHint::DirPath)]
     pub output_file : Option<PathBuf>,
@@ -44,15 +98,39 @@ pub struct DecodeArgs {
     // file path of PNG file
     #[arg(short='f', long, value_name="PNG_PATH", value_hint=clap::ValueHint::DirPath)]
     pub input_path: PathBuf,
-    // type of the new messages´ chunk
+    // type of the new messages´ chunk; only checks the signature for `--method lsb`
     #[arg(short='c', long, value_name="CHUNK_TYPE")]
-    pub chunk_type : String,
+    pub chunk_type : Option<String>,
+    // look for the message in chunks, or in the least significant bits of the pixels
+    #[arg(long, value_enum, default_value_t=EmbedMethod::default())]
+    pub method : EmbedMethod,
+    // gather the bits of `--method lsb` from the pixels chosen by the passphrase (prompted for, or taken from PNGME_PASSPHRASE)
+    #[arg(long, action)]
+    pub scatter : bool,
     // iterate ALL entries and decode all matching chunk_types
     #[arg(short='m', long, action)]
     pub multiple_chunks : bool,
+    // how to render the message; `raw` prints the payload bytes only, without chunk details
+    #[arg(short='F', long, value_enum, default_value_t=OutputFormat::default())]
+    pub format : OutputFormat,
+    // decrypt the message with a passphrase (prompted for, or taken from PNGME_PASSPHRASE)
+    #[arg(long, action)]
+    pub decrypt : bool,
+    // decrypt the message with the identities from an age identity file
+    #[arg(short='i', long, value_name="IDENTITY_PATH", value_hint=clap::ValueHint::FilePath, conflicts_with="decrypt")]
+    pub identity : Option<PathBuf>,
+    // report signatures by the public keys in this file as trusted
+    #[arg(short='t', long, value_name="TRUSTED_KEYS_PATH", value_hint=clap::ValueHint::FilePath)]
+    pub trusted_keys : Option<PathBuf>,
+    // refuse to decompress a message to more than this many bytes (default 64 MiB)
+    #[arg(long, value_name="BYTES")]
+    pub max_size : Option<usize>,
+    // optional: write the message(s) to a file instead of stdout
+    #[arg(long, value_name="OUT_PATH", value_hint=clap::ValueHint::FilePath)]
+    pub out : Option<PathBuf>,
 }
 
-/// remove the first chunk matching matching the specified chunk type
+/// remove the first chunk matching the specified chunk type, or all of them
 #[derive(Debug, Parser, PartialEq, Clone)]
 #[command(version, about, long_about=None)]
 pub struct RemoveArgs {
@@ -77,4 +155,229 @@ pub struct PrintArgs {
     // file path for PNG file
     #[arg(short='f', long, value_name="PNG_PATH", value_hint=clap::ValueHint::DirPath)]
     pub input_path: PathBuf,
-}
\ No newline at end of file
+    // report signatures by the public keys in this file as trusted
+    #[arg(short='t', long, value_name="TRUSTED_KEYS_PATH", value_hint=clap::ValueHint::FilePath)]
+    pub trusted_keys : Option<PathBuf>,
+}
+
+/// print the image header and basic chunk statistics of a PNG file
+#[derive(Debug, Parser, PartialEq, Clone)]
+#[command(version, about, long_about=None)]
+pub struct InfoArgs {
+    // file path for PNG file
+    #[arg(short='f', long, value_name="PNG_PATH", value_hint=clap::ValueHint::DirPath)]
+    pub input_path: PathBuf,
+}
+
+/// validate chunk ordering and multiplicity against the PNG spec
+#[derive(Debug, Parser, PartialEq, Clone)]
+#[command(version, about, long_about=None)]
+pub struct CheckArgs {
+    // file path for PNG file
+    #[arg(short='f', long, value_name="PNG_PATH", value_hint=clap::ValueHint::DirPath)]
+    pub input_path: PathBuf,
+}
+
+/// generate an identity (key pair) to receive public-key encrypted messages, or a key to sign messages
+#[derive(Debug, Parser, PartialEq, Clone)]
+#[command(version, about, long_about=None)]
+pub struct KeygenArgs {
+    // optional: file to write the identity or signing key to; printed to stdout otherwise
+    #[arg(short='o', long, value_name="KEY_PATH", value_hint=clap::ValueHint::FilePath)]
+    pub output_file : Option<PathBuf>,
+    // generate an Ed25519 signing key instead of an age identity
+    #[arg(long, action)]
+    pub signing : bool,
+}
+
+/// check the signatures of message chunks against a trusted-keys file
+#[derive(Debug, Parser, PartialEq, Clone)]
+#[command(version, about, long_about=None)]
+pub struct VerifyArgs {
+    // file path for PNG file
+    #[arg(short='f', long, value_name="PNG_PATH", value_hint=clap::ValueHint::DirPath)]
+    pub input_path: PathBuf,
+    // optional: only check chunks of this type; all private ancillary chunks otherwise
+    #[arg(short='c', long, value_name="CHUNK_TYPE")]
+    pub chunk_type : Option<String>,
+    // file with one `<base64 public key> <name>` per line
+    #[arg(short='t', long, value_name="TRUSTED_KEYS_PATH", value_hint=clap::ValueHint::FilePath)]
+    pub trusted_keys : PathBuf,
+}
+
+/// seal a PNG file with a signed digest of all its chunks, to detect later changes
+#[derive(Debug, Parser, PartialEq, Clone)]
+#[command(version, about, long_about=None)]
+pub struct SealArgs {
+    // file path for PNG file
+    #[arg(short='f', long, value_name="PNG_PATH", value_hint=clap::ValueHint::DirPath)]
+    pub input_path: PathBuf,
+    // Ed25519 signing key file, as created by `keygen --signing`
+    #[arg(long, value_name="SIGN_KEY_PATH", value_hint=clap::ValueHint::FilePath)]
+    pub sign_key : PathBuf,
+    // optional: new file path for output PNG
+    #[arg(short='o', long, value_name="NEW_FILEPATH", value_hint=clap::ValueHint::DirPath)]
+    pub output_file : Option<PathBuf>,
+}
+
+/// check a sealed PNG file for chunks added, removed or modified since sealing, and remove the seal
+#[derive(Debug, Parser, PartialEq, Clone)]
+#[command(version, about, long_about=None)]
+pub struct UnsealArgs {
+    // file path for PNG file
+    #[arg(short='f', long, value_name="PNG_PATH", value_hint=clap::ValueHint::DirPath)]
+    pub input_path: PathBuf,
+    // only report changes, keep the seal and leave the file untouched
+    #[arg(long, action)]
+    pub verify : bool,
+    // remove the seal even if it is untrusted or the image changed since sealing
+    #[arg(long, action, conflicts_with="verify")]
+    pub force : bool,
+    // report seals by the public keys in this file as trusted
+    #[arg(short='t', long, value_name="TRUSTED_KEYS_PATH", value_hint=clap::ValueHint::FilePath)]
+    pub trusted_keys : Option<PathBuf>,
+    // optional: new file path for output PNG
+    #[arg(short='o', long, value_name="NEW_FILEPATH", value_hint=clap::ValueHint::DirPath, conflicts_with="verify")]
+    pub output_file : Option<PathBuf>,
+}
+
+/// attach whole files to a PNG file, with names, sizes, permissions and checksums
+#[derive(Debug, Parser, PartialEq, Clone)]
+#[command(version, about, long_about=None)]
+pub struct AttachArgs {
+    // file path for PNG file
+    #[arg(short='f', long, value_name="PNG_PATH", value_hint=clap::ValueHint::DirPath)]
+    pub input_path: PathBuf,
+    // files to attach; stored under their file names, without directories
+    #[arg(value_name="FILE", value_hint=clap::ValueHint::FilePath, required=true)]
+    pub files : Vec<PathBuf>,
+    // type of the chunk(s) holding the files
+    #[arg(short='c', long, value_name="CHUNK_TYPE", default_value=ATTACHMENT_CHUNK_TYPE)]
+    pub chunk_type : String,
+    // compress the files
+    #[arg(short='z', long, value_enum, default_value_t=Compression::default())]
+    pub compress : Compression,
+    // encrypt the files with a passphrase (prompted for, or taken from PNGME_PASSPHRASE)
+    #[arg(long, action)]
+    pub encrypt : bool,
+    // encrypt the files to an age public key (`age1...`); repeat for several recipients
+    #[arg(short='r', long="recipient", value_name="RECIPIENT", conflicts_with="encrypt")]
+    pub recipients : Vec<String>,
+    // sign the files with an Ed25519 signing key file, as created by `keygen --signing`
+    #[arg(long, value_name="SIGN_KEY_PATH", value_hint=clap::ValueHint::FilePath)]
+    pub sign_key : Option<PathBuf>,
+    // optional: new file path for output PNG
+    #[arg(short='o', long, value_name="NEW_FILEPATH", value_hint=clap::ValueHint::DirPath)]
+    pub output_file : Option<PathBuf>,
+}
+
+/// extract the files attached to a PNG file into a directory
+#[derive(Debug, Parser, PartialEq, Clone)]
+#[command(version, about, long_about=None)]
+pub struct ExtractArgs {
+    // file path for PNG file
+    #[arg(short='f', long, value_name="PNG_PATH", value_hint=clap::ValueHint::DirPath)]
+    pub input_path: PathBuf,
+    // directory to extract the files to; created if missing
+    #[arg(short='d', long, value_name="DIR", value_hint=clap::ValueHint::DirPath)]
+    pub dir : PathBuf,
+    // type of the chunk(s) holding the files
+    #[arg(short='c', long, value_name="CHUNK_TYPE", default_value=ATTACHMENT_CHUNK_TYPE)]
+    pub chunk_type : String,
+    // decrypt the files with a passphrase (prompted for, or taken from PNGME_PASSPHRASE)
+    #[arg(long, action)]
+    pub decrypt : bool,
+    // decrypt the files with the identities from an age identity file
+    #[arg(short='i', long, value_name="IDENTITY_PATH", value_hint=clap::ValueHint::FilePath, conflicts_with="decrypt")]
+    pub identity : Option<PathBuf>,
+    // report signatures by the public keys in this file as trusted
+    #[arg(short='t', long, value_name="TRUSTED_KEYS_PATH", value_hint=clap::ValueHint::FilePath)]
+    pub trusted_keys : Option<PathBuf>,
+    // replace existing files
+    #[arg(long, action)]
+    pub force : bool,
+}
+
+/// list the files attached to a PNG file
+#[derive(Debug, Parser, PartialEq, Clone)]
+#[command(version, about, long_about=None)]
+pub struct LsArgs {
+    // file path for PNG file
+    #[arg(short='f', long, value_name="PNG_PATH", value_hint=clap::ValueHint::DirPath)]
+    pub input_path: PathBuf,
+    // type of the chunk(s) holding the files
+    #[arg(short='c', long, value_name="CHUNK_TYPE", default_value=ATTACHMENT_CHUNK_TYPE)]
+    pub chunk_type : String,
+    // decrypt the files with a passphrase (prompted for, or taken from PNGME_PASSPHRASE)
+    #[arg(long, action)]
+    pub decrypt : bool,
+    // decrypt the files with the identities from an age identity file
+    #[arg(short='i', long, value_name="IDENTITY_PATH", value_hint=clap::ValueHint::FilePath, conflicts_with="decrypt")]
+    pub identity : Option<PathBuf>,
+    // report signatures by the public keys in this file as trusted
+    #[arg(short='t', long, value_name="TRUSTED_KEYS_PATH", value_hint=clap::ValueHint::FilePath)]
+    pub trusted_keys : Option<PathBuf>,
+}
+
+/// estimate how large a message a PNG file can hide, for every embedding method
+#[derive(Debug, Parser, PartialEq, Clone)]
+#[command(version, about, long_about=None)]
+pub struct CapacityArgs {
+    // file path for PNG file
+    #[arg(short='f', long, value_name="PNG_PATH", value_hint=clap::ValueHint::DirPath)]
+    pub input_path: PathBuf,
+    // account for encryption with a passphrase
+    #[arg(long, action)]
+    pub encrypt : bool,
+    // account for encryption to this many age recipients
+    #[arg(short='r', long, value_name="COUNT", default_value_t=0, conflicts_with="encrypt")]
+    pub recipients : usize,
+    // account for a signature
+    #[arg(long, action)]
+    pub sign : bool,
+    // account for the file name recorded when the message is read from a file
+    #[arg(long, value_name="FILENAME")]
+    pub filename : Option<String>,
+}
+
+/// look for hidden data in a PNG file, or in every PNG file of a directory tree
+#[derive(Debug, Parser, PartialEq, Clone)]
+#[command(version, about, long_about=None)]
+pub struct ScanArgs {
+    // file path for PNG file, or a directory to scan recursively
+    #[arg(short='f', long, value_name="PATH", value_hint=clap::ValueHint::AnyPath)]
+    pub input_path: PathBuf,
+    // only report files scoring at least this much, from 0 to 100
+    #[arg(long, value_name="SCORE", default_value_t=1, value_parser=clap::value_parser!(u8).range(0..=100))]
+    pub min_score : u8,
+}
+
+/// look for messages hidden in the least significant bits of the pixels, by statistical analysis
+#[derive(Debug, Parser, PartialEq, Clone)]
+#[command(version, about, long_about=None)]
+pub struct AnalyzeArgs {
+    // file path for PNG file
+    #[arg(short='f', long, value_name="PNG_PATH", value_hint=clap::ValueHint::DirPath)]
+    pub input_path: PathBuf,
+    // optional: write the LSB plane to a new PNG file for visual inspection
+    #[arg(short='o', long, value_name="LSB_PLANE_PATH", value_hint=clap::ValueHint::FilePath)]
+    pub lsb_plane : Option<PathBuf>,
+    // export the LSB plane of this channel only (0 for red or gray), instead of all color channels
+    #[arg(long, value_name="CHANNEL", requires="lsb_plane")]
+    pub channel : Option<usize>,
+}
+
+/// remove text, EXIF data, timestamps, hidden messages and any other chunk not needed to display a PNG file
+#[derive(Debug, Parser, PartialEq, Clone)]
+#[command(version, about, long_about=None)]
+pub struct StripArgs {
+    // file path for PNG file
+    #[arg(short='f', long, value_name="PNG_PATH", value_hint=clap::ValueHint::DirPath)]
+    pub input_path: PathBuf,
+    // ancillary chunks to keep: chunk types, or the presets `color` (color space and transparency) and `none`
+    #[arg(short='k', long, value_name="KEEP", value_delimiter=',', default_value=DEFAULT_PRESET)]
+    pub keep : Vec<String>,
+    // optional: new file path for output PNG
+    #[arg(short='o', long, value_name="NEW_FILEPATH", value_hint=clap::ValueHint::DirPath)]
+    pub output_file : Option<PathBuf>,
+}
@@ -0,0 +1,229 @@
+use std::collections::HashSet;
+use std::path::{Component, Path, PathBuf};
+
+use sha2::{Digest, Sha256};
+
+use anyhow::{anyhow, Result};
+
+use crate::error as PngMeError;
+
+/*
+    Bundles of whole files, attached to a PNG as a single message.
+
+        magic "PMBN"         4 bytes
+        version              1 byte
+        entry count          4 bytes, big endian
+        per entry:
+            name length      2 bytes, big endian
+            name             UTF-8
+            mode             4 bytes, big endian: unix permission bits
+            size             8 bytes, big endian
+            checksum        32 bytes: SHA-256 of the file contents
+            contents         `size` bytes
+
+    Names are plain file names without any directory part, unique within the bundle. Bundles come from untrusted images,
+    so extraction refuses every name that is absolute, contains a separator or refers to a
+    parent directory, instead of trying to sanitize it.
+*/
+
+/// default chunk type for attached files: ancillary, private, safe to copy
+pub const ATTACHMENT_CHUNK_TYPE: &str = "atCh";
+/// identifies file bundles
+pub const MAGIC: [u8; 4] = *b"PMBN";
+/// current version of the bundle format
+pub const VERSION: u8 = 1;
+
+const HEADER_LENGTH: usize = 4 + 1 + 4;
+const CHECKSUM_LENGTH: usize = 32;
+// permission bits applied on extraction; setuid, setgid and sticky bits are never restored
+const PERMISSION_BITS: u32 = 0o777;
+
+/// A single file inside a bundle
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BundleEntry {
+    pub name: String,
+    /// unix permission bits of the original file
+    pub mode: u32,
+    pub contents: Vec<u8>,
+}
+
+impl BundleEntry {
+    pub fn size(&self) -> u64 {
+        self.contents.len() as u64
+    }
+
+    /// SHA-256 of the file contents
+    pub fn checksum(&self) -> [u8; CHECKSUM_LENGTH] {
+        Sha256::digest(&self.contents).into()
+    }
+
+    /// permission bits to restore on extraction
+    pub fn permissions(&self) -> u32 {
+        self.mode & PERMISSION_BITS
+    }
+
+    /// the path to extract the file to, relative to the target directory;
+    /// fails with `BundleError::UnsafeName` for anything but a plain file name
+    pub fn safe_path(&self) -> Result<PathBuf> {
+        let path = Path::new(&self.name);
+        let mut components = path.components();
+        match (components.next(), components.next()) {
+            (Some(Component::Normal(name)), None)
+                if !self.name.contains(['/', '\\', '\0']) && name == path.as_os_str() => Ok(PathBuf::from(name)),
+            _ => Err(anyhow!(PngMeError::BundleError::UnsafeName(self.name.clone()))),
+        }
+    }
+}
+
+/// check whether a message is a file bundle
+pub fn is_bundle(data: &[u8]) -> bool {
+    data.starts_with(&MAGIC)
+}
+
+/// serialize file entries into a bundle; fails for unsafe or duplicate names
+pub fn to_bytes(entries: &[BundleEntry]) -> Result<Vec<u8>> {
+    let count = u32::try_from(entries.len())
+        .map_err(|_| PngMeError::BundleError::InvalidBundle(format!("too many files: {}", entries.len())))?;
+    let mut bundle: Vec<u8> = MAGIC
+        .iter()
+        .chain([VERSION].iter())
+        .chain(count.to_be_bytes().iter())
+        .copied()
+        .collect();
+    let mut names = HashSet::new();
+    for entry in entries {
+        entry.safe_path()?;
+        if !names.insert(entry.name.as_str()) {
+            return Err(anyhow!(PngMeError::BundleError::DuplicateName(entry.name.clone())));
+        }
+        let name_length = u16::try_from(entry.name.len())
+            .map_err(|_| PngMeError::BundleError::InvalidBundle(format!("file name too long: {}", entry.name)))?;
+        bundle.extend(name_length.to_be_bytes());
+        bundle.extend(entry.name.as_bytes());
+        bundle.extend(entry.mode.to_be_bytes());
+        bundle.extend(entry.size().to_be_bytes());
+        bundle.extend(entry.checksum());
+        bundle.extend(&entry.contents);
+    }
+    Ok(bundle)
+}
+
+// split `length` bytes off the front of the remaining bundle data
+fn take<'a>(data: &mut &'a [u8], length: usize) -> Result<&'a [u8]> {
+    if data.len() < length {
+        return Err(anyhow!(PngMeError::BundleError::InvalidBundle(format!(
+            "expected {} more bytes, found {}",
+            length,
+            data.len()
+        ))));
+    }
+    let (taken, rest) = data.split_at(length);
+    *data = rest;
+    Ok(taken)
+}
+
+/// parse a bundle, checking the size and checksum of every file;
+/// names are not checked here, use `BundleEntry::safe_path` before writing files
+pub fn parse(bundle: &[u8]) -> Result<Vec<BundleEntry>> {
+    if !is_bundle(bundle) || bundle.len() < HEADER_LENGTH {
+        return Err(anyhow!(PngMeError::BundleError::InvalidBundle(String::from("missing bundle header"))));
+    }
+    if bundle[4] != VERSION {
+        return Err(anyhow!(PngMeError::BundleError::UnsupportedVersion(bundle[4])));
+    }
+    let count = u32::from_be_bytes([bundle[5], bundle[6], bundle[7], bundle[8]]);
+    let mut data = &bundle[HEADER_LENGTH..];
+    let mut entries = Vec::new();
+    for _ in 0..count {
+        let name_length = u16::from_be_bytes(take(&mut data, 2)?.try_into()?) as usize;
+        let name = String::from_utf8(take(&mut data, name_length)?.to_vec())
+            .map_err(|_| PngMeError::BundleError::InvalidBundle(String::from("file name is not valid UTF-8")))?;
+        let mode = u32::from_be_bytes(take(&mut data, 4)?.try_into()?);
+        let size = u64::from_be_bytes(take(&mut data, 8)?.try_into()?);
+        let checksum = take(&mut data, CHECKSUM_LENGTH)?;
+        let size = usize::try_from(size)
+            .map_err(|_| PngMeError::BundleError::InvalidBundle(format!("{} is too large", name)))?;
+        let entry = BundleEntry { name, mode, contents: take(&mut data, size)?.to_vec() };
+        if entry.checksum() != checksum {
+            return Err(anyhow!(PngMeError::BundleError::ChecksumMismatch(entry.name)));
+        }
+        entries.push(entry);
+    }
+    if !data.is_empty() {
+        return Err(anyhow!(PngMeError::BundleError::InvalidBundle(format!("{} trailing bytes", data.len()))));
+    }
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(name: &str, contents: &[u8]) -> BundleEntry {
+        BundleEntry { name: name.to_string(), mode: 0o100644, contents: contents.to_vec() }
+    }
+
+    // bundle with arbitrary names, bypassing the checks of `to_bytes`
+    fn crafted_bundle(name: &str) -> Vec<u8> {
+        let mut bundle = to_bytes(&[entry("placeholder", b"evil")]).unwrap();
+        let start = HEADER_LENGTH + 2;
+        bundle.splice(start..start + "placeholder".len(), name.bytes());
+        bundle[HEADER_LENGTH..start].copy_from_slice(&(name.len() as u16).to_be_bytes());
+        bundle
+    }
+
+    #[test]
+    fn test_bundle_roundtrip() {
+        let entries = vec![entry("config.toml", b"[server]\nport = 8080\n"), entry("empty", b""), entry("blob.bin", &[0, 255, 7])];
+        let bundle = to_bytes(&entries).unwrap();
+        assert!(is_bundle(&bundle));
+        assert_eq!(parse(&bundle).unwrap(), entries);
+        assert_eq!(entries[0].permissions(), 0o644);
+    }
+
+    #[test]
+    fn test_checksum_mismatch() {
+        let mut bundle = to_bytes(&[entry("notes.txt", b"remember the milk")]).unwrap();
+        let last = bundle.len() - 1;
+        bundle[last] ^= 1;
+        assert!(matches!(
+            parse(&bundle).unwrap_err().downcast_ref::<PngMeError::BundleError>(),
+            Some(PngMeError::BundleError::ChecksumMismatch(name)) if name == "notes.txt"
+        ));
+    }
+
+    #[test]
+    fn test_truncated_bundle() {
+        let bundle = to_bytes(&[entry("notes.txt", b"remember the milk")]).unwrap();
+        assert!(matches!(
+            parse(&bundle[..bundle.len() - 3]).unwrap_err().downcast_ref::<PngMeError::BundleError>(),
+            Some(PngMeError::BundleError::InvalidBundle(_))
+        ));
+    }
+
+    #[test]
+    fn test_safe_names() {
+        assert_eq!(entry("notes.txt", b"").safe_path().unwrap(), PathBuf::from("notes.txt"));
+        assert!(entry(".hidden", b"").safe_path().is_ok());
+        for name in ["../escape", "/etc/passwd", "..", ".", "", "dir/file", "dir\\file", "a\0b", "./notes.txt"] {
+            assert!(entry(name, b"").safe_path().is_err(), "{:?} should be refused", name);
+        }
+    }
+
+    #[test]
+    fn test_unsafe_names_are_parsed_but_refused() {
+        let entries = parse(&crafted_bundle("../../.bashrc")).unwrap();
+        assert_eq!(entries[0].name, "../../.bashrc");
+        assert!(entries[0].safe_path().is_err());
+        assert!(to_bytes(&entries).is_err());
+    }
+
+    #[test]
+    fn test_duplicate_names() {
+        let entries = [entry("notes.txt", b"first"), entry("notes.txt", b"second")];
+        assert!(matches!(
+            to_bytes(&entries).unwrap_err().downcast_ref::<PngMeError::BundleError>(),
+            Some(PngMeError::BundleError::DuplicateName(name)) if name == "notes.txt"
+        ));
+    }
+}
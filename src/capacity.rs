@@ -0,0 +1,229 @@
+use std::fmt;
+
+use anyhow::Result;
+
+use crate::chunk::Chunk;
+use crate::crypto;
+use crate::envelope;
+use crate::ihdr::ColorType;
+use crate::lsb;
+use crate::png::Png;
+use crate::signature;
+
+/*
+    Estimates of how large a message a PNG can carry, for every way of hiding it that suits the image.
+
+    Each carrier holds a number of raw bytes, taken from the image header and the chunks present.
+    Messages are wrapped before they are stored: in an envelope, optionally encrypted and signed,
+    so the largest message is the raw capacity minus these layers. Compression is not accounted for,
+    as its effect depends on the message; it usually makes room for more.
+
+    Text chunks, alpha-only LSB and the palette carriers are estimates for comparison only,
+    `encode` cannot write messages that way.
+*/
+
+/// keyword of the tEXt chunk assumed to carry base64 encoded messages
+pub const TEXT_KEYWORD: &str = "Comment";
+/*
+    Binary age files (https://age-encryption.org/v1) hold a version line, a stanza per X25519 recipient,
+    a "grease" stanza of random length, the header MAC line, a nonce, and the payload in chunks of 64 KiB,
+    each with an authentication tag. Grease stanzas take at most 55 bytes for the first line and
+    135 bytes for the body, so these lengths are an upper bound.
+*/
+const AGE_FIXED_LENGTH: usize = 22 + (55 + 135) + 48 + 16;
+const AGE_STANZA_LENGTH: usize = 54 + 44;
+const AGE_CHUNK_LENGTH: usize = 64 << 10;
+const AGE_TAG_LENGTH: usize = 16;
+
+/// A way of hiding a message in a PNG
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Carrier {
+    /// private ancillary chunks, split into fragments as needed
+    Chunk,
+    /// base64 text in a single tEXt chunk
+    TextChunk,
+    /// the lowest bits of every sample
+    Lsb(u8),
+    /// the lowest bit of the alpha samples only
+    AlphaLsb,
+    /// the lowest bit of every palette color component
+    PaletteLsb,
+    /// the order of the palette entries, with the image data remapped to match
+    PaletteOrder,
+}
+
+impl Carrier {
+    /// `encode` can hide messages this way
+    pub fn is_supported(&self) -> bool {
+        matches!(self, Carrier::Chunk | Carrier::Lsb(_))
+    }
+}
+
+impl fmt::Display for Carrier {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Carrier::Chunk => write!(f, "chunk"),
+            Carrier::TextChunk => write!(f, "text chunk"),
+            Carrier::Lsb(bits_per_sample) => write!(f, "lsb, {} bit(s) per sample", bits_per_sample),
+            Carrier::AlphaLsb => write!(f, "lsb, alpha only"),
+            Carrier::PaletteLsb => write!(f, "palette lsb"),
+            Carrier::PaletteOrder => write!(f, "palette order"),
+        }
+    }
+}
+
+/// The layers wrapped around a message before it is stored
+#[derive(Debug, Clone, Default)]
+pub struct Overhead {
+    /// encrypted with a passphrase
+    pub passphrase: bool,
+    /// encrypted to this many recipients; 0 for none
+    pub recipients: usize,
+    /// signed
+    pub signed: bool,
+    /// length of the filename recorded in the envelope
+    pub filename_length: usize,
+}
+
+impl Overhead {
+    /// bytes the layers add to a message of `length` bytes, at most
+    pub fn bytes(&self, length: usize) -> usize {
+        let encryption = if self.passphrase {
+            crypto::HEADER_LENGTH + crypto::TAG_LENGTH
+        } else if self.recipients > 0 {
+            let tags = length.div_ceil(AGE_CHUNK_LENGTH).max(1);
+            AGE_FIXED_LENGTH + self.recipients * AGE_STANZA_LENGTH + tags * AGE_TAG_LENGTH
+        } else {
+            0
+        };
+        let signature = if self.signed { signature::HEADER_LENGTH } else { 0 };
+        envelope::HEADER_LENGTH + self.filename_length + encryption + signature
+    }
+
+    /// the longest message that still fits into `available` bytes once wrapped
+    pub fn max_message_length(&self, available: usize) -> usize {
+        let room = available.saturating_sub(self.bytes(0));
+        if self.passphrase || self.recipients == 0 || room == 0 {
+            return room;
+        }
+        // age adds a tag for every chunk after the first, full chunks take the room of a chunk and a tag
+        let full_chunks = (room - 1) / (AGE_CHUNK_LENGTH + AGE_TAG_LENGTH);
+        (room - full_chunks * AGE_TAG_LENGTH).min((full_chunks + 1) * AGE_CHUNK_LENGTH)
+    }
+}
+
+/// How much one carrier can hold
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Capacity {
+    pub carrier: Carrier,
+    /// bytes the carrier holds, all layers included; `None` if unlimited
+    pub raw: Option<usize>,
+    /// the longest message that fits once wrapped; `None` if unlimited
+    pub message: Option<usize>,
+}
+
+// base-2 logarithm of n!, i.e. the bits that choosing one of n! orders can encode
+fn log2_factorial(n: usize) -> f64 {
+    (2..=n).map(|k| (k as f64).log2()).sum()
+}
+
+// the raw capacity of every carrier suitable for the image
+fn raw_capacities(png: &Png) -> Result<Vec<(Carrier, Option<usize>)>> {
+    let ihdr = png.ihdr()?;
+    let text = (Chunk::MAX_LENGTH as usize - TEXT_KEYWORD.len() - 1) / 4 * 3;
+    let mut capacities = vec![(Carrier::Chunk, None), (Carrier::TextChunk, Some(text))];
+    if lsb::check_carrier(&ihdr).is_ok() {
+        for bits_per_sample in 1..=lsb::MAX_BITS_PER_SAMPLE {
+            capacities.push((Carrier::Lsb(bits_per_sample), Some(lsb::capacity(&ihdr, bits_per_sample)?)));
+        }
+        if matches!(ihdr.color_type(), ColorType::GrayscaleAlpha | ColorType::Rgba) {
+            let pixels = (ihdr.width() as usize).saturating_mul(ihdr.height() as usize);
+            capacities.push((Carrier::AlphaLsb, Some(pixels.saturating_sub(lsb::HEADER_LENGTH * 8) / 8)));
+        }
+    }
+    if let Some(palette) = png.chunk_by_type("PLTE") {
+        let entries = palette.length() as usize / 3;
+        capacities.push((Carrier::PaletteLsb, Some(entries * 3 / 8)));
+        capacities.push((Carrier::PaletteOrder, Some((log2_factorial(entries) / 8.0) as usize)));
+    }
+    Ok(capacities)
+}
+
+/// estimate the capacity of every carrier suitable for a PNG, for messages wrapped as described by `overhead`
+pub fn estimate(png: &Png, overhead: &Overhead) -> Result<Vec<Capacity>> {
+    let capacities = raw_capacities(png)?
+        .into_iter()
+        .map(|(carrier, raw)| {
+            let message = raw.map(|raw| overhead.max_message_length(raw));
+            Capacity { carrier, raw, message }
+        })
+        .collect();
+    Ok(capacities)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::envelope::{ContentType, Envelope, EnvelopeFlags, EnvelopeHeader};
+    use crate::recipients;
+    use crate::test_utils::{chunk, sample_png};
+
+    fn capacity_of(capacities: &[Capacity], carrier: Carrier) -> Option<&Capacity> {
+        capacities.iter().find(|capacity| capacity.carrier == carrier)
+    }
+
+    #[test]
+    fn test_sample_image_capacities() {
+        let png = sample_png();
+        let capacities = estimate(&png, &Overhead::default()).unwrap();
+        let samples = 727 * 684 * 4;
+
+        assert_eq!(capacity_of(&capacities, Carrier::Chunk).unwrap().message, None);
+        let lsb = capacity_of(&capacities, Carrier::Lsb(1)).unwrap();
+        assert_eq!(lsb.raw, Some((samples - 80) / 8));
+        assert_eq!(lsb.message, Some((samples - 80) / 8 - envelope::HEADER_LENGTH));
+        assert_eq!(capacity_of(&capacities, Carrier::Lsb(2)).unwrap().raw, Some((samples - 80) * 2 / 8));
+        assert_eq!(capacity_of(&capacities, Carrier::AlphaLsb).unwrap().raw, Some((727 * 684 - 80) / 8));
+        // RGBA has no palette
+        assert!(capacity_of(&capacities, Carrier::PaletteOrder).is_none());
+    }
+
+    #[test]
+    fn test_palette_capacities() {
+        let ihdr: Vec<u8> = [16u32.to_be_bytes(), 16u32.to_be_bytes()].concat().into_iter().chain([8, 3, 0, 0, 0]).collect();
+        let png = Png::from_chunks(vec![chunk("IHDR", ihdr), chunk("PLTE", vec![0; 256 * 3]), chunk("IEND", Vec::new())]);
+        let capacities = estimate(&png, &Overhead::default()).unwrap();
+        assert!(capacity_of(&capacities, Carrier::Lsb(1)).is_none());
+        assert_eq!(capacity_of(&capacities, Carrier::PaletteLsb).unwrap().raw, Some(96));
+        // log2(256!) is about 1684 bits
+        assert_eq!(capacity_of(&capacities, Carrier::PaletteOrder).unwrap().raw, Some(210));
+    }
+
+    #[test]
+    fn test_overhead_bounds_encoded_messages() {
+        let keys: Vec<String> = (0..2).map(|_| recipients::generate_keypair().recipient).collect();
+        let overhead = Overhead { recipients: 2, ..Default::default() };
+        for length in [0, 1, 1000, AGE_CHUNK_LENGTH, AGE_CHUNK_LENGTH + 1, 3 * AGE_CHUNK_LENGTH + 7] {
+            let message = vec![7u8; length];
+            let body = recipients::encrypt(&message, &keys).unwrap();
+            let flags = EnvelopeFlags { recipients: true, ..Default::default() };
+            let payload = Envelope { header: EnvelopeHeader::new(flags, ContentType::Binary, None), body }.to_bytes().unwrap();
+            // exact but for the random grease stanza
+            let actual = payload.len() - length;
+            assert!(actual <= overhead.bytes(length) && actual + 55 + 135 >= overhead.bytes(length), "message of {} bytes", length);
+        }
+        let body = crypto::encrypt(b"secret", "pw").unwrap();
+        let overhead = Overhead { passphrase: true, signed: true, ..Default::default() };
+        assert_eq!(overhead.bytes(6), envelope::HEADER_LENGTH + body.len() - 6 + signature::HEADER_LENGTH);
+    }
+
+    #[test]
+    fn test_max_message_length_fits() {
+        let overhead = Overhead { recipients: 1, ..Default::default() };
+        for available in [0, 100, 600, AGE_CHUNK_LENGTH + 600, AGE_CHUNK_LENGTH * 5 + 600] {
+            let length = overhead.max_message_length(available);
+            assert!(length == 0 || length + overhead.bytes(length) <= available);
+            assert!(length + 1 + overhead.bytes(length + 1) > available);
+        }
+    }
+}
@@ -1,22 +1,47 @@
 use std::convert::TryFrom;
 use std::fmt;
-// use std::io::{BufReader, Bytes, Read};
+use std::io::{ErrorKind, Read, Write};
 use std::str;
 
 use crc;
 use anyhow::{anyhow, Result};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
 
 use crate::chunk_type::ChunkType;
 use crate::error as PngMeError;
 
 // set up utility function for CRC
-pub fn calculate_crc_ieee_checksum(chunk_data: &Vec<u8>) -> u32 {
+pub fn calculate_crc_ieee_checksum(chunk_data: &[u8]) -> u32 {
     let crc_algorithm = crc::Crc::<u32>::new(&crc::CRC_32_ISO_HDLC);
     let mut digest = crc_algorithm.digest();
-    digest.update(&chunk_data);
+    digest.update(chunk_data);
     digest.finalize()
 }
 
+// CRC over chunk type & data, without copying both into one buffer first
+fn calculate_chunk_crc(chunk_type: &ChunkType, data: &[u8]) -> u32 {
+    let crc_algorithm = crc::Crc::<u32>::new(&crc::CRC_32_ISO_HDLC);
+    let mut digest = crc_algorithm.digest();
+    digest.update(&chunk_type.bytes());
+    digest.update(data);
+    digest.finalize()
+}
+
+// fill `buffer` as far as the reader allows; returns the number of bytes read, which is
+// only less than `buffer.len()` if the reader is exhausted
+fn read_up_to<R: Read>(reader: &mut R, buffer: &mut [u8]) -> Result<usize> {
+    let mut filled = 0;
+    while filled < buffer.len() {
+        match reader.read(&mut buffer[filled..]) {
+            Ok(0) => break,
+            Ok(read) => filled += read,
+            Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+            Err(err) => return Err(anyhow!(err)),
+        }
+    }
+    Ok(filled)
+}
+
 #[derive(Clone, Debug)]
 pub struct Chunk {
     length: u32,
@@ -26,16 +51,15 @@ pub struct Chunk {
 }
 
 impl Chunk {
+    /// The PNG spec limits the data length of a single chunk to 2^31-1 bytes
+    pub const MAX_LENGTH: u32 = (1 << 31) - 1;
+    /// Length, chunk type and CRC add 12 bytes of metadata to every chunk
+    pub const METADATA_LENGTH: usize = 12;
+
     // constructor
     pub fn new(chunk_type:ChunkType, data: Vec<u8>) -> Chunk {
         let length = data.len() as u32;
-        let chunk_data: Vec<u8> = chunk_type
-            .bytes()
-            .iter()
-            .chain(data.iter())
-            .copied()
-            .collect();
-        let crc = calculate_crc_ieee_checksum(&chunk_data);
+        let crc = calculate_chunk_crc(&chunk_type, &data);
 
         Self {
             length,
@@ -72,63 +96,88 @@ impl Chunk {
         }
     }
 
-    // Returns this Chunk as a byte sequence as described by PNG spec
-    // The following data is included in this byte sequence in order: 
-    //  1. Length of the data *(4 bytes)*
-    //  2. Chunk type *(4 bytes)*
-    //  3. The data itself *(`length` bytes)*
-    //  4. The CRC of the chunk type and data *(4 bytes)*
-    pub fn as_bytes(&self) -> Vec<u8> {
-        self.length
-            .to_be_bytes()
-            .iter()
-            .chain(self.chunk_type.bytes().iter())
-            .chain(self.data.iter())
-            .chain(self.crc.to_be_bytes().iter())
-            .copied()
-            .collect::<Vec<u8>>()
-    }
-
-
-}
-
-impl TryFrom<&[u8]> for Chunk {
-    type Error = anyhow::Error;
-    // create a Chunk from a list of bytes
-    fn try_from(bytes: &[u8]) -> Result<Self> {
+    /// create a Chunk from the beginning of a list of bytes; any bytes following the chunk are ignored.
+    /// `offset` is the position of `bytes` within the enclosing file, used to locate parsing errors.
+    /// Never panics: truncated input and oversized length fields are reported as errors.
+    pub(crate) fn from_bytes_at(bytes: &[u8], offset: usize) -> Result<Chunk> {
         // throw if input is shorter than necessary metadata length
-        if bytes.len() < 12 {
-            return Err(anyhow!(PngMeError::ChunkError::InputTooSmall(bytes.len().try_into().unwrap())));
+        if bytes.len() < Chunk::METADATA_LENGTH {
+            return Err(anyhow!(PngMeError::ChunkError::Truncated(offset, Chunk::METADATA_LENGTH, bytes.len())));
         }
 
         // get first 4 bytes corresponding to chunks' data length
         let (data_length, bytes) = bytes.split_at(4);
         let length = u32::from_be_bytes(data_length.try_into()?);
+        if length > Chunk::MAX_LENGTH {
+            return Err(anyhow!(PngMeError::ChunkError::LengthTooLarge(offset, length)));
+        }
 
         // get next 4 bytes corresponding to chunks' type
         let (chunk_type_bytes, bytes) = bytes.split_at(4);
         let chunk_type_bytes: [u8; 4] = chunk_type_bytes.try_into()?;
         let chunk_type = ChunkType::try_from(chunk_type_bytes)?;
 
-        if !chunk_type.is_valid() {
-            return Err(anyhow!(PngMeError::ChunkTypeError::InvalidChunkType));
-        }
-
         // get chunks' data & crc from remaining bytes
-        // length refers to chunks' data length
+        // length refers to chunks' data length; 4 more bytes are needed for the crc
+        let total_length = length as usize + Chunk::METADATA_LENGTH;
+        if bytes.len() < length as usize + 4 {
+            return Err(anyhow!(PngMeError::ChunkError::Truncated(offset, total_length, bytes.len() + 8)));
+        }
         let (data, bytes) = bytes.split_at(length as usize);
-        let (crc,_) = bytes.split_at(4);
+        let (crc, _) = bytes.split_at(4);
 
-        let data: Vec<u8> = data.try_into()?;
+        let data: Vec<u8> = data.to_vec();
         let crc = u32::from_be_bytes(crc.try_into()?);
 
+        Chunk::verified(length, chunk_type, data, crc)
+    }
+
+    /// read the next Chunk from a reader, consuming exactly the bytes of that chunk.
+    /// Returns `None` if the reader is exhausted right at a chunk boundary.
+    /// `offset` is the position of the reader within the enclosing file, used to locate parsing errors.
+    pub(crate) fn read_from<R: Read>(reader: &mut R, offset: usize) -> Result<Option<Chunk>> {
+        // get first 8 bytes corresponding to chunks' data length & type
+        let mut metadata = [0u8; 8];
+        let read = read_up_to(reader, &mut metadata)?;
+        if read == 0 {
+            return Ok(None);
+        }
+        if read < metadata.len() {
+            return Err(anyhow!(PngMeError::ChunkError::Truncated(offset, Chunk::METADATA_LENGTH, read)));
+        }
+
+        let (data_length, chunk_type_bytes) = metadata.split_at(4);
+        let length = u32::from_be_bytes(data_length.try_into()?);
+        if length > Chunk::MAX_LENGTH {
+            return Err(anyhow!(PngMeError::ChunkError::LengthTooLarge(offset, length)));
+        }
+        let chunk_type = ChunkType::try_from(<[u8; 4]>::try_from(chunk_type_bytes)?)?;
+
+        // grow the buffer while reading instead of trusting the length field for the allocation
+        let total_length = length as usize + Chunk::METADATA_LENGTH;
+        let mut data = Vec::new();
+        reader.by_ref().take(length as u64).read_to_end(&mut data)?;
+        if data.len() < length as usize {
+            return Err(anyhow!(PngMeError::ChunkError::Truncated(offset, total_length, data.len() + 8)));
+        }
+
+        let mut crc = [0u8; 4];
+        let read = read_up_to(reader, &mut crc)?;
+        if read < crc.len() {
+            return Err(anyhow!(PngMeError::ChunkError::Truncated(offset, total_length, data.len() + 8 + read)));
+        }
+
+        Chunk::verified(length, chunk_type, data, u32::from_be_bytes(crc)).map(Some)
+    }
+
+    // assemble a parsed Chunk, making sure the stored crc matches chunk type & data
+    fn verified(length: u32, chunk_type: ChunkType, data: Vec<u8>, crc: u32) -> Result<Chunk> {
+        if !chunk_type.is_valid() {
+            return Err(anyhow!(PngMeError::ChunkTypeError::InvalidChunkType));
+        }
+
         // calculate crc fresh from chunks' type & data
-        let chunk_data: Vec<u8> = chunk_type.bytes()
-            .iter()
-            .chain(data.iter())
-            .copied()
-            .collect();
-        let actual_crc = calculate_crc_ieee_checksum(&chunk_data);
+        let actual_crc = calculate_chunk_crc(&chunk_type, &data);
         let expected_crc = crc;
         if actual_crc != expected_crc {
             return Err(anyhow!(PngMeError::ChunkError::InvalidCrc(expected_crc, actual_crc)));
@@ -136,14 +185,65 @@ impl TryFrom<&[u8]> for Chunk {
 
         // all went well, return the fresh new Chunk
         Ok(
-            Chunk { 
-                length, 
-                chunk_type, 
-                data, 
+            Chunk {
+                length,
+                chunk_type,
+                data,
                 crc,
             }
         )
+    }
+
+    /// write this Chunk as a byte sequence as described by PNG spec (see `as_bytes`) without copying it first
+    pub fn write_to<W: Write>(&self, mut writer: W) -> Result<()> {
+        writer.write_all(&self.length.to_be_bytes())?;
+        writer.write_all(&self.chunk_type.bytes())?;
+        writer.write_all(&self.data)?;
+        writer.write_all(&self.crc.to_be_bytes())?;
+        Ok(())
+    }
+
+    // Returns data stored in this Chunk as a `String`,
+    // replacing invalid UTF-8 sequences with U+FFFD instead of failing
+    pub fn data_as_string_lossy(&self) -> String {
+        String::from_utf8_lossy(&self.data).to_string()
+    }
+
+    // Returns data stored in this Chunk as lowercase hexadecimal digits
+    pub fn data_as_hex(&self) -> String {
+        hex::encode(&self.data)
+    }
 
+    // Returns data stored in this Chunk encoded as standard, padded base64
+    pub fn data_as_base64(&self) -> String {
+        BASE64.encode(&self.data)
+    }
+
+    // Returns this Chunk as a byte sequence as described by PNG spec
+    // The following data is included in this byte sequence in order: 
+    //  1. Length of the data *(4 bytes)*
+    //  2. Chunk type *(4 bytes)*
+    //  3. The data itself *(`length` bytes)*
+    //  4. The CRC of the chunk type and data *(4 bytes)*
+    pub fn as_bytes(&self) -> Vec<u8> {
+        self.length
+            .to_be_bytes()
+            .iter()
+            .chain(self.chunk_type.bytes().iter())
+            .chain(self.data.iter())
+            .chain(self.crc.to_be_bytes().iter())
+            .copied()
+            .collect::<Vec<u8>>()
+    }
+
+
+}
+
+impl TryFrom<&[u8]> for Chunk {
+    type Error = anyhow::Error;
+    // create a Chunk from a list of bytes
+    fn try_from(bytes: &[u8]) -> Result<Self> {
+        Chunk::from_bytes_at(bytes, 0)
     }
 }
 
@@ -161,6 +261,7 @@ impl fmt::Display for Chunk {
 mod tests {
     use super::*;
     use crate::chunk_type::ChunkType;
+    use crate::test_utils::chunk;
     use std::str::FromStr;
 
     fn testing_chunk() -> Chunk {
@@ -210,6 +311,15 @@ mod tests {
         assert_eq!(chunk_string, expected_chunk_string);
     }
 
+    #[test]
+    fn test_chunk_data_conversions() {
+        let chunk = chunk("RuSt", vec![0x68, 0x69, 0xff, 0x00]);
+        assert!(chunk.data_as_string().is_err());
+        assert_eq!(chunk.data_as_string_lossy(), "hi\u{fffd}\u{0}");
+        assert_eq!(chunk.data_as_hex(), "6869ff00");
+        assert_eq!(chunk.data_as_base64(), "aGn/AA==");
+    }
+
     #[test]
     fn test_chunk_crc() {
         let chunk = testing_chunk();
@@ -264,6 +374,78 @@ mod tests {
         assert!(chunk.is_err());
     }
 
+    #[test]
+    fn test_truncated_chunk_from_bytes() {
+        let chunk_bytes = testing_chunk().as_bytes();
+        for end in 0..chunk_bytes.len() {
+            let chunk = Chunk::try_from(&chunk_bytes[..end]);
+            assert!(chunk.is_err());
+        }
+        let err = Chunk::from_bytes_at(&chunk_bytes[..20], 33).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<PngMeError::ChunkError>(),
+            Some(PngMeError::ChunkError::Truncated(33, 54, 20))
+        ));
+    }
+
+    #[test]
+    fn test_oversized_chunk_length() {
+        #[rustfmt::skip]
+        let chunk_bytes = [
+            255, 255, 255, 255, // length
+            82, 117, 83, 116,   // Chunk Type
+            1, 2, 3, 4,         // CRC
+        ];
+        let err = Chunk::try_from(chunk_bytes.as_ref()).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<PngMeError::ChunkError>(),
+            Some(PngMeError::ChunkError::LengthTooLarge(0, 0xFFFFFFFF))
+        ));
+    }
+
+    #[test]
+    fn test_non_ascii_chunk_type_from_bytes() {
+        #[rustfmt::skip]
+        let chunk_bytes = [
+            0, 0, 0, 0,         // length
+            255, 254, 253, 252, // Chunk Type (not even UTF-8)
+            1, 2, 3, 4,         // CRC
+        ];
+        assert!(Chunk::try_from(chunk_bytes.as_ref()).is_err());
+    }
+
+    #[test]
+    fn test_read_chunk_from_reader() {
+        let chunk_bytes: Vec<u8> = testing_chunk()
+            .as_bytes()
+            .into_iter()
+            .chain(testing_chunk().as_bytes())
+            .collect();
+        let mut reader = chunk_bytes.as_slice();
+
+        let first = Chunk::read_from(&mut reader, 0).unwrap().unwrap();
+        let second = Chunk::read_from(&mut reader, 54).unwrap().unwrap();
+        assert_eq!(first.as_bytes(), second.as_bytes());
+        assert!(Chunk::read_from(&mut reader, 108).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_read_truncated_chunk_from_reader() {
+        let chunk_bytes = testing_chunk().as_bytes();
+        for end in 1..chunk_bytes.len() {
+            let mut reader = &chunk_bytes[..end];
+            assert!(Chunk::read_from(&mut reader, 0).is_err());
+        }
+    }
+
+    #[test]
+    fn test_write_chunk_to_writer() {
+        let chunk = testing_chunk();
+        let mut written = Vec::new();
+        chunk.write_to(&mut written).unwrap();
+        assert_eq!(written, chunk.as_bytes());
+    }
+
     #[test]
     fn test_chunk_trait_impls() {
         let data_length: u32 = 42;
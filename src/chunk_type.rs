@@ -1,7 +1,7 @@
 use std::fmt;
 use std::string::String;
 use std::str::FromStr;
-use std::{convert::TryFrom, u8};
+use std::convert::TryFrom;
 
 use anyhow::{anyhow, Result};
 use crate::error as PngMeError;
@@ -18,49 +18,59 @@ impl ChunkType {
 
     pub fn is_critical(&self) -> bool {
         // critical: beginning with capital char
-        let first_byte: &[u8] = self.data[0..1].as_bytes();
+        let first_byte: &[u8] = &self.data.as_bytes()[0..1];
         first_byte[0].is_ascii_uppercase()
     }
 
     pub fn is_public(&self) -> bool {
-        let second_byte: &[u8] = self.data[1..2].as_bytes();
+        let second_byte: &[u8] = &self.data.as_bytes()[1..2];
         second_byte[0].is_ascii_uppercase()
     }
 
     pub fn is_reserved_bit_valid(&self) -> bool {
-        let third_byte: &[u8] = self.data[2..3].as_bytes();
+        let third_byte: &[u8] = &self.data.as_bytes()[2..3];
         third_byte[0].is_ascii_uppercase()
     }
 
     pub fn is_safe_to_copy(&self) -> bool {
-        let fourth_byte: &[u8] = self.data[3..4].as_bytes();
+        let fourth_byte: &[u8] = &self.data.as_bytes()[3..4];
         fourth_byte[0].is_ascii_lowercase()
     }
 
     pub fn is_valid_byte(ascii_val_to_check: u8) -> bool {
-        let mut valid_bytes: Vec<u8> = (b'A'..b'[').collect();
-        let mut more_valid_bytes: Vec<u8> = (b'a'..b'{').collect();
-        valid_bytes.append(&mut more_valid_bytes);
-        valid_bytes.contains(&ascii_val_to_check)
-        // b'A' <= ascii_val_to_check <= b'Z' || (ascii_val >= b'a' && ascii_val <= b'z')
+        ascii_val_to_check.is_ascii_alphabetic()
     }
 
     pub fn is_valid(&self) -> bool {
-        let data_as_bytes: &[u8] = self.data.as_bytes();
-        let mut flag = 0;
-        for ascii_val in data_as_bytes {
-            if ChunkType::is_valid_byte(ascii_val.to_owned()) {
-                continue;
-            } else {
-                flag = 1;
-                break;
-            }
+        ChunkType::validate(&self.bytes()).is_ok()
+    }
+
+    /// check whether this chunk type can safely carry a hidden message:
+    /// critical chunks make the PNG undecodable for every viewer not knowing them,
+    /// and public chunk types are reserved for chunks registered with the PNG spec
+    pub fn check_message_safe(&self) -> Result<()> {
+        if self.is_critical() {
+            return Err(anyhow!(PngMeError::ChunkTypeError::CriticalChunkType(self.to_string())));
         }
-        if flag == 1 || !self.is_reserved_bit_valid() {
-            false
-        } else {
-            true
+        if self.is_public() {
+            return Err(anyhow!(PngMeError::ChunkTypeError::PublicChunkType(self.to_string())));
         }
+        Ok(())
+    }
+
+    // single source of truth for the rules every chunk type has to follow, no matter how it is constructed
+    fn validate(bytes: &[u8]) -> std::result::Result<(), PngMeError::ChunkTypeError> {
+        if bytes.len() != 4 {
+            return Err(PngMeError::ChunkTypeError::InvalidLength(bytes.len()));
+        }
+        if !bytes.iter().all(|byte| ChunkType::is_valid_byte(*byte)) {
+            return Err(PngMeError::ChunkTypeError::InvalidBytes(String::from_utf8_lossy(bytes).to_string()));
+        }
+        // the reserved bit is the case of the third letter, which MUST be uppercase
+        if !bytes[2].is_ascii_uppercase() {
+            return Err(PngMeError::ChunkTypeError::ReservedBitInvalid(String::from_utf8_lossy(bytes).to_string()));
+        }
+        Ok(())
     }
 }
 
@@ -68,21 +78,10 @@ impl TryFrom<[u8;4]> for ChunkType {
     type Error = anyhow::Error;
 
     fn try_from(bytes: [u8;4]) -> Result<Self> {
-        for ascii_val_to_check in bytes {
-            if ChunkType::is_valid_byte(ascii_val_to_check) {
-                continue;
-            } else {
-                return Err(anyhow!(PngMeError::ChunkTypeError::InvalidBytes(String::from_utf8(bytes.to_vec()).unwrap())));
-            }
-        }
-        let string:String = String::from_utf8(bytes[..].into()).unwrap();
-        let chunk = ChunkType { data: string };
-
-        if chunk.is_valid() {
-            Ok(chunk)
-        } else {
-            Err(anyhow!(PngMeError::ChunkTypeError::InvalidChunkType))
-        }
+        ChunkType::validate(&bytes)?;
+        // validated bytes are ASCII letters and therefore valid UTF-8
+        let data = String::from_utf8_lossy(&bytes).to_string();
+        Ok(ChunkType { data })
     }
 }
 
@@ -96,19 +95,8 @@ impl FromStr for ChunkType {
     type Err = anyhow::Error;
 
     fn from_str(s: &str) -> Result<Self> {
-        if s.len() == 4 {
-            let chars = s.as_bytes();
-            for char in chars {
-                if !ChunkType::is_valid_byte(*char) {
-                    return Err(anyhow!("Invalid input String: {}", s));
-                }
-            }
-            let chunk: String = s.to_string();
-            let result = ChunkType { data: chunk };
-            Ok(result)
-        } else {
-            Err(anyhow!("Expected 4 bytes, received {} instead", s.len()))
-        }
+        ChunkType::validate(s.as_bytes())?;
+        Ok(ChunkType { data: s.to_string() })
     }
 }
 
@@ -164,8 +152,12 @@ mod tests {
 
     #[test]
     pub fn test_chunk_type_is_reserved_bit_invalid() {
-        let chunk = ChunkType::from_str("Rust").unwrap();
-        assert!(!chunk.is_reserved_bit_valid());
+        let chunk = ChunkType::from_str("Rust");
+        assert!(matches!(
+            chunk.unwrap_err().downcast_ref::<PngMeError::ChunkTypeError>(),
+            Some(PngMeError::ChunkTypeError::ReservedBitInvalid(_))
+        ));
+        assert!(ChunkType::try_from(*b"Rust").is_err());
     }
 
     #[test]
@@ -188,11 +180,37 @@ mod tests {
 
     #[test]
     pub fn test_invalid_chunk_is_invalid() {
-        let chunk_a = ChunkType::from_str("Rust").unwrap();
-        assert!(!chunk_a.is_valid());
+        let chunk_a = ChunkType::from_str("Rust");
+        assert!(chunk_a.is_err());
 
         let chunk_b = ChunkType::from_str("Ru1t");
         assert!(chunk_b.is_err());
+
+        let chunk_c = ChunkType::from_str("RuStY");
+        assert!(chunk_c.is_err());
+    }
+
+    #[test]
+    pub fn test_non_ascii_chunk_type_is_invalid() {
+        assert!(ChunkType::from_str("RüSt").is_err());
+        assert!(ChunkType::try_from([82, 255, 83, 116]).is_err());
+    }
+
+    #[test]
+    pub fn test_chunk_type_is_message_safe() {
+        assert!(ChunkType::from_str("ruSt").unwrap().check_message_safe().is_ok());
+
+        let critical = ChunkType::from_str("RuSt").unwrap().check_message_safe();
+        assert!(matches!(
+            critical.unwrap_err().downcast_ref::<PngMeError::ChunkTypeError>(),
+            Some(PngMeError::ChunkTypeError::CriticalChunkType(_))
+        ));
+
+        let public = ChunkType::from_str("tEXt").unwrap().check_message_safe();
+        assert!(matches!(
+            public.unwrap_err().downcast_ref::<PngMeError::ChunkTypeError>(),
+            Some(PngMeError::ChunkTypeError::PublicChunkType(_))
+        ));
     }
 
     #[test]
@@ -1,92 +1,1372 @@
-/* 
-    If you're writing a function that reads from a file, 
-    there's a nice way to accept the file's path as a parameter 
-    using the AsRef trait. 
-    Your function signature will look something like 
-    #   fn from_file<P: AsRef<Path>>(path: P). 
+/*
+    If you're writing a function that reads from a file,
+    there's a nice way to accept the file's path as a parameter
+    using the AsRef trait.
+    Your function signature will look something like
+    #   fn from_file<P: AsRef<Path>>(path: P).
 */
-use std::fs;
-use std::{convert::TryFrom, str::FromStr};
+use std::collections::HashSet;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
 
-use crate::{args, chunk_type};
-pub use crate::{
-    args::{DecodeArgs, EncodeArgs, PrintArgs, RemoveArgs, PngMeArgs},
+use crate::{
+    bundle::{self, BundleEntry},
+    capacity::{self, Capacity, Overhead},
     chunk::Chunk,
+    compression::{self, Compression},
+    crypto,
+    envelope::{self, ContentType, Envelope, EnvelopeFlags, EnvelopeHeader},
+    fragment,
+    error::{BundleError, CryptoError, FragmentError, FsIoError, LsbError, PNGError, SealError},
     chunk_type::ChunkType,
-    error::FsIoError,
-    png::Png,
+    ihdr::Ihdr,
+    lsb::{self, ScatterKey},
+    pixels,
+    png::{ChunkPosition, Png},
+    recipients::{self, Keypair},
+    scan::{self, ScanReport},
+    seal::{self, SealReport},
+    signature::{self, SignatureStatus, SigningKeypair, TrustedKeys},
+    steganalysis::{self, ChannelAnalysis},
+    strip,
+    validator::{self, Violation},
 };
 
-
 use anyhow::{anyhow, Result};
-use clap::Subcommand;
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
 
-/* 
+/*
     Steganography is the practice of concealing information within another message or physical object to avoid detection.
     Steganography can be used to hide virtually any type of digital content, including text, image, video, or audio content.
     That hidden data is then extracted at its destination.
 
-    A common approach is LSB (least significant bit) steganography. In the context of PNG images, instead of singular bits, 
-    the least significant byte in the PNG file is changed to store information, undetectable to the human eye.
-    Computers, however, can extract the information easily.
+    A common approach is LSB (least significant bit) steganography: the lowest bits of the pixel samples are replaced
+    by the bits of the message, a change undetectable to the human eye. pngme does so with `EmbedMethod::Lsb`, see `lsb`.
+    By default, messages are stored in chunks of their own instead, which leaves the image untouched,
+    but is visible to any tool listing the chunks.
 */
 
-/// encode a message into a PNG file and save the results, optionally to a new file
-pub fn encode(args: EncodeArgs) -> Result<()> {
-    let mut png = Png::read_file(args.input_path.clone())?;
+/// Where in a PNG a message is hidden
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum EmbedMethod {
+    /// in an ancillary chunk of its own
+    #[default]
+    Chunk,
+    /// in the least significant bits of the pixel samples; the chunk type only binds signatures
+    Lsb,
+}
+
+/// Settings for `encode` beyond the message itself
+#[derive(Debug, Clone, Default)]
+pub struct EncodeOptions {
+    /// where to place the message chunk
+    pub position: ChunkPosition,
+    /// allow critical or public chunk types for the message chunk
+    pub force: bool,
+    /// compress the message before encrypting it
+    pub compression: Compression,
+    /// encrypt the message with this passphrase
+    pub passphrase: Option<String>,
+    /// encrypt the message to these `age1...` public keys
+    pub recipients: Vec<String>,
+    /// sign the (encrypted) message with the Ed25519 key from this file
+    pub sign_key: Option<PathBuf>,
+    /// name of the file the message was read from, recorded in the envelope
+    pub filename: Option<String>,
+    /// split messages into several chunks of at most this many data bytes;
+    /// `fragment::DEFAULT_MAX_CHUNK_LENGTH` if not set
+    pub max_chunk_length: Option<usize>,
+    /// hide the message in a chunk, or in the pixels
+    pub method: EmbedMethod,
+    /// pixel samples bits replaced by `EmbedMethod::Lsb`; `lsb::DEFAULT_BITS_PER_SAMPLE` if not set
+    pub bits_per_sample: Option<u8>,
+    /// scatter the bits of `EmbedMethod::Lsb` across samples chosen with a key derived from this passphrase
+    pub scatter_passphrase: Option<String>,
+}
+
+// read a key or identity file
+fn read_key_file(path: &Path) -> Result<String> {
+    fs::read_to_string(path)
+        .map_err(|err| anyhow!(FsIoError::UnableToReadFileError(err.to_string())))
+}
+
+// read the trusted public keys, if a trusted-keys file is given; no key is trusted otherwise
+fn read_trusted_keys(path: Option<&Path>) -> Result<TrustedKeys> {
+    match path {
+        Some(path) => TrustedKeys::parse(&read_key_file(path)?),
+        None => Ok(TrustedKeys::default()),
+    }
+}
+
+/// encode a message into a PNG file and save the results, optionally to a new file;
+/// returns the newly added message chunks, several if the message was split into fragments,
+/// or the rewritten IDAT chunks for `EmbedMethod::Lsb`.
+/// Critical and public chunk types are refused, unless `options.force` is set.
+pub fn encode(
+    input_path: &Path,
+    chunk_type: &str,
+    message: &[u8],
+    options: &EncodeOptions,
+    output_path: Option<&Path>,
+) -> Result<Vec<Chunk>> {
+    encode_message(input_path, chunk_type, message, ContentType::detect(message), options, output_path)
+}
+
+// encode a message of the given content type, see `encode`
+fn encode_message(
+    input_path: &Path,
+    chunk_type: &str,
+    message: &[u8],
+    content_type: ContentType,
+    options: &EncodeOptions,
+    output_path: Option<&Path>,
+) -> Result<Vec<Chunk>> {
+    if options.scatter_passphrase.is_some() && options.method != EmbedMethod::Lsb {
+        return Err(anyhow!(LsbError::ScatterWithoutLsb));
+    }
+    let chunk_type = ChunkType::from_str(chunk_type)?;
+    if !options.force {
+        chunk_type.check_message_safe()?;
+    }
+
+    let mut png = Png::read_file(input_path)?;
     // make the output either a specified optional path, or the original input path
-    let output_path = &args.output_file.unwrap_or_else(|| args.input_path.clone());
-    
-    /*  
-        Chunk::append will add the secret message at the very end of the PNG file, even after the IEND chunk.
-        This roughly equates to an interpretation of "least significant bit", with normal PNG decoders not picking up the message.
+    let output_path = output_path.unwrap_or(input_path);
+
+    // compress first: encrypted data does not compress; the envelope records the algorithm
+    let body = compression::compress_stream(message, options.compression)?;
+    let body = match (&options.passphrase, options.recipients.is_empty()) {
+        (Some(_), false) => return Err(anyhow!(CryptoError::MixedPassphraseAndRecipients)),
+        (Some(passphrase), true) => crypto::encrypt(&body, passphrase)?,
+        (None, false) => recipients::encrypt(&body, &options.recipients)?,
+        (None, true) => body,
+    };
+    let flags = EnvelopeFlags {
+        compression: options.compression,
+        passphrase: options.passphrase.is_some(),
+        recipients: !options.recipients.is_empty(),
+    };
+    let payload = Envelope {
+        header: EnvelopeHeader::new(flags, content_type, options.filename.clone()),
+        body,
+    }.to_bytes()?;
+    // sign last, so the signature can be checked without decrypting
+    let payload = match &options.sign_key {
+        Some(sign_key_path) => {
+            let signing_key = signature::parse_signing_key(&read_key_file(sign_key_path)?)?;
+            signature::sign(&chunk_type, &payload, &signing_key)
+        },
+        None => payload,
+    };
+
+    if options.method == EmbedMethod::Lsb {
+        // the image is altered, all chunks but IDAT stay as they are
+        let mut pixels = pixels::decode(&png, pixels::DEFAULT_MAX_IMAGE_DATA_LENGTH)?;
+        let bits_per_sample = options.bits_per_sample.unwrap_or(lsb::DEFAULT_BITS_PER_SAMPLE);
+        match &options.scatter_passphrase {
+            Some(passphrase) => {
+                let key = ScatterKey::derive(passphrase, pixels.ihdr())?;
+                lsb::embed_scattered(&mut pixels, &payload, bits_per_sample, &key)?;
+            },
+            None => lsb::embed(&mut pixels, &payload, bits_per_sample)?,
+        }
+        let chunks = pixels::write_image_data(&mut png, &pixels)?;
+        png.write_file(output_path)?;
+        return Ok(chunks);
+    }
+
+    /*
+        The secret message is stored in an ancillary chunk; by default it is inserted right before IEND,
+        so the file stays fully conformant and normal PNG decoders simply skip the message.
+        ChunkPosition::End keeps the original behaviour of appending after IEND.
+        Large messages are split across consecutive chunks of the same type.
         The contained image is not altered.
     */
-    let chunk = Chunk::new(
-        ChunkType::from_str(args.chunk_type.as_str()).unwrap(),
-        args.message.as_bytes().to_vec(),
-    );
-    png.append_chunk(chunk);
+    let max_chunk_length = options.max_chunk_length.unwrap_or(fragment::DEFAULT_MAX_CHUNK_LENGTH);
+    let chunks: Vec<Chunk> = fragment::split(&payload, max_chunk_length)?
+        .into_iter()
+        .map(|data| Chunk::new(chunk_type.clone(), data))
+        .collect();
+    png.insert_chunks_at(options.position, chunks.clone())?;
     png.write_file(output_path)?;
-    Ok(())
+    Ok(chunks)
+}
+
+/// Settings for `decode`
+#[derive(Debug, Clone, Default)]
+pub struct DecodeOptions {
+    /// return all matching message chunks instead of only the first one
+    pub multiple_chunks: bool,
+    /// decrypt the messages with this passphrase
+    pub passphrase: Option<String>,
+    /// decrypt the messages with the identities from this file
+    pub identity_file: Option<PathBuf>,
+    /// report signatures by the public keys in this file as trusted
+    pub trusted_keys: Option<PathBuf>,
+    /// refuse to decompress messages to more bytes than this;
+    /// `compression::DEFAULT_MAX_DECOMPRESSED_LENGTH` if not set
+    pub max_decompressed_length: Option<usize>,
+    /// look for messages in chunks, or in the pixels
+    pub method: EmbedMethod,
+    /// gather the bits of `EmbedMethod::Lsb` from the samples chosen with a key derived from this passphrase
+    pub scatter_passphrase: Option<String>,
+}
+
+/// A message found in a PNG file, with the outcome of checking its signature
+#[derive(Debug, Clone)]
+pub struct DecodedMessage {
+    /// index of the (first) chunk holding the message in the PNG
+    pub index: usize,
+    /// indices of all chunks holding the message; more than one for fragmented messages,
+    /// all IDAT chunks for messages hidden in the pixels
+    pub chunk_indices: Vec<usize>,
+    /// the fragments of the message were not stored in sequence order
+    pub reordered: bool,
+    pub chunk: Chunk,
+    pub signature: SignatureStatus,
+    /// envelope header of the message; `None` for raw messages written by older versions of pngme
+    pub envelope: Option<EnvelopeHeader>,
+    /// why the chunks do not form a complete message; only reported by `verify`, `decode` fails instead
+    pub damage: Option<FragmentError>,
+}
+
+/// search for hidden messages in a PNG file; return the first matching message,
+/// or all of them if `options.multiple_chunks` is set. Fragmented messages are reassembled.
+/// With `EmbedMethod::Lsb`, the single message hidden in the pixels is returned, under the given chunk type.
+/// The returned chunks carry the messages without signature, decrypted if a passphrase or identity file is given,
+/// and decompressed.
+pub fn decode(input_path: &Path, chunk_type: &str, options: &DecodeOptions) -> Result<Vec<DecodedMessage>> {
+    if options.scatter_passphrase.is_some() && options.method != EmbedMethod::Lsb {
+        return Err(anyhow!(LsbError::ScatterWithoutLsb));
+    }
+    let png = Png::read_file(input_path)?;
+    let identities = match &options.identity_file {
+        Some(identity_path) => Some(read_key_file(identity_path)?),
+        None => None,
+    };
+    let trusted_keys = read_trusted_keys(options.trusted_keys.as_deref())?;
+    let max_decompressed_length = options.max_decompressed_length
+        .unwrap_or(compression::DEFAULT_MAX_DECOMPRESSED_LENGTH);
+    let limit = if options.multiple_chunks { usize::MAX } else { 1 };
+    let chunk_type = ChunkType::from_str(chunk_type)?;
+    let messages = match options.method {
+        EmbedMethod::Chunk => fragment::reassemble_messages(png.chunks_by_type(&chunk_type.to_string())),
+        EmbedMethod::Lsb => {
+            let pixels = pixels::decode(&png, pixels::DEFAULT_MAX_IMAGE_DATA_LENGTH)?;
+            vec![
+                fragment::Reassembled {
+                    chunk_indices: png.chunks_by_type("IDAT").map(|(index, _)| index).collect(),
+                    payload: match &options.scatter_passphrase {
+                        Some(passphrase) => lsb::extract_scattered(&pixels, &ScatterKey::derive(passphrase, pixels.ihdr())?)?,
+                        None => lsb::extract(&pixels)?,
+                    },
+                    reordered: false,
+                    damage: None,
+                }
+            ]
+        },
+    };
+    // only the messages returned need to be complete
+    messages
+        .into_iter()
+        .take(limit)
+        .map(|reassembled| {
+            let reassembled = reassembled.complete()?;
+            let signature = signature::verify(&chunk_type, &reassembled.payload, &trusted_keys);
+            let payload = signature::payload(&reassembled.payload);
+            let (header, message) = if envelope::is_envelope(payload) {
+                let Envelope { header, body } = Envelope::parse(payload)?;
+                let message = open_body(&body, Some(header.flags), options, identities.as_deref(), max_decompressed_length)?;
+                (Some(header), message)
+            } else {
+                (None, open_body(payload, None, options, identities.as_deref(), max_decompressed_length)?)
+            };
+            Ok(
+                DecodedMessage {
+                    index: reassembled.chunk_indices[0],
+                    chunk_indices: reassembled.chunk_indices,
+                    reordered: reassembled.reordered,
+                    chunk: Chunk::new(chunk_type.clone(), message),
+                    signature,
+                    envelope: header,
+                    damage: None,
+                }
+            )
+        })
+        .collect()
+}
+
+// undo encryption and compression of a message body, as recorded in the envelope flags;
+// legacy raw messages carry no flags and are neither encrypted nor compressed
+fn open_body(
+    body: &[u8],
+    flags: Option<EnvelopeFlags>,
+    options: &DecodeOptions,
+    identities: Option<&str>,
+    max_decompressed_length: usize,
+) -> Result<Vec<u8>> {
+    if let Some(flags) = flags {
+        let passphrase_mismatch = options.passphrase.is_some() && !flags.passphrase;
+        let identity_mismatch = options.passphrase.is_none() && identities.is_some() && !flags.recipients;
+        if passphrase_mismatch || identity_mismatch {
+            return Err(anyhow!(CryptoError::NotEncrypted));
+        }
+        if flags.is_encrypted() && options.passphrase.is_none() && identities.is_none() {
+            // without a key, the encrypted body is all there is to show
+            return Ok(body.to_vec());
+        }
+    }
+    let message = match (&options.passphrase, identities) {
+        (Some(passphrase), _) => crypto::decrypt(body, passphrase)?,
+        (None, Some(identities)) => recipients::decrypt(body, identities)?,
+        (None, None) => body.to_vec(),
+    };
+    match flags {
+        Some(flags) => compression::decompress_stream(&message, flags.compression, max_decompressed_length),
+        None => Ok(message),
+    }
+}
+
+/// check the signatures of the messages in a PNG file against the keys in a trusted-keys file;
+/// all private ancillary chunks are checked, unless a chunk type is given.
+/// The returned chunks hold the messages as stored in the PNG, reassembled from fragments;
+/// incomplete messages are reported with their damage instead of failing the whole file.
+pub fn verify(input_path: &Path, chunk_type: Option<&str>, trusted_keys: Option<&Path>) -> Result<Vec<DecodedMessage>> {
+    let png = Png::read_file(input_path)?;
+    Ok(verify_messages(&png, chunk_type, &read_trusted_keys(trusted_keys)?))
+}
+
+// the messages of a PNG as reported by `verify`
+fn verify_messages(png: &Png, chunk_type: Option<&str>, trusted_keys: &TrustedKeys) -> Vec<DecodedMessage> {
+    let message_chunks = png.chunks()
+        .iter()
+        .enumerate()
+        .filter(|(_, chunk)| match chunk_type {
+            Some(chunk_type) => chunk.chunk_type().to_string() == chunk_type,
+            None => chunk.chunk_type().check_message_safe().is_ok(),
+        });
+    fragment::reassemble_messages(message_chunks)
+        .into_iter()
+        .map(|reassembled| {
+            let index = reassembled.chunk_indices[0];
+            let chunk_type = png.chunks()[index].chunk_type();
+            let payload = signature::payload(&reassembled.payload);
+            DecodedMessage {
+                index,
+                signature: signature::verify(chunk_type, &reassembled.payload, trusted_keys),
+                envelope: Envelope::parse(payload).ok().map(|envelope| envelope.header),
+                chunk: Chunk::new(chunk_type.clone(), reassembled.payload),
+                chunk_indices: reassembled.chunk_indices,
+                reordered: reassembled.reordered,
+                damage: reassembled.damage,
+            }
+        })
+        .collect()
+}
+
+/// attach whole files to a PNG file as a single bundle message, read from `files`;
+/// returns the attached entries. The bundle is compressed, encrypted and signed according to `options`.
+pub fn attach(
+    input_path: &Path,
+    chunk_type: &str,
+    files: &[PathBuf],
+    options: &EncodeOptions,
+    output_path: Option<&Path>,
+) -> Result<Vec<BundleEntry>> {
+    let entries = files
+        .iter()
+        .map(|file_path| {
+            let name = file_path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .ok_or_else(|| FsIoError::UnableToReadFileError(format!("not a file name: {}", file_path.display())))?;
+            let metadata = fs::metadata(file_path)
+                .map_err(|err| FsIoError::UnableToReadFileError(format!("{}: {}", file_path.display(), err)))?;
+            #[cfg(unix)]
+            let mode = std::os::unix::fs::PermissionsExt::mode(&metadata.permissions());
+            #[cfg(not(unix))]
+            let mode = if metadata.permissions().readonly() { 0o444 } else { 0o644 };
+            let contents = fs::read(file_path)
+                .map_err(|err| FsIoError::UnableToReadFileError(format!("{}: {}", file_path.display(), err)))?;
+            Ok(BundleEntry { name: name.to_string(), mode, contents })
+        })
+        .collect::<Result<Vec<BundleEntry>>>()?;
+    let bundle = bundle::to_bytes(&entries)?;
+    encode_message(input_path, chunk_type, &bundle, ContentType::Bundle, options, output_path)?;
+    Ok(entries)
+}
+
+/// The files of a bundle message found in a PNG file
+#[derive(Debug, Clone)]
+pub struct DecodedBundle {
+    /// index of the (first) chunk holding the bundle in the PNG
+    pub index: usize,
+    pub signature: SignatureStatus,
+    pub entries: Vec<BundleEntry>,
+}
+
+/// list the files attached to a PNG file, in all bundle messages of a chunk type;
+/// other messages of the same chunk type are skipped
+pub fn ls(input_path: &Path, chunk_type: &str, options: &DecodeOptions) -> Result<Vec<DecodedBundle>> {
+    let options = DecodeOptions { multiple_chunks: true, ..options.clone() };
+    decode(input_path, chunk_type, &options)?
+        .into_iter()
+        .filter(|message| message.envelope.as_ref().is_some_and(|header| header.content_type == ContentType::Bundle))
+        .map(|message| {
+            Ok(
+                DecodedBundle {
+                    index: message.index,
+                    entries: bundle::parse(message.chunk.data())?,
+                    signature: message.signature,
+                }
+            )
+        })
+        .collect()
+}
+
+/// extract all files attached to a PNG file into a directory; returns the paths written.
+/// Nothing is written if any file name is unsafe or used twice. Existing files are only replaced if `force` is set.
+pub fn extract(input_path: &Path, chunk_type: &str, options: &DecodeOptions, dir: &Path, force: bool) -> Result<Vec<PathBuf>> {
+    let bundles = ls(input_path, chunk_type, options)?;
+    // check every name before anything is written; two files of the same name would overwrite each other
+    let mut paths = HashSet::new();
+    let entries: Vec<(PathBuf, &BundleEntry)> = bundles
+        .iter()
+        .flat_map(|bundle| bundle.entries.iter())
+        .map(|entry| {
+            let path = dir.join(entry.safe_path()?);
+            if !paths.insert(path.clone()) {
+                return Err(anyhow!(BundleError::DuplicateName(entry.name.clone())));
+            }
+            Ok((path, entry))
+        })
+        .collect::<Result<Vec<(PathBuf, &BundleEntry)>>>()?;
+
+    fs::create_dir_all(dir).map_err(|err| FsIoError::UnableToCreateFileError(err.to_string()))?;
+    entries
+        .into_iter()
+        .map(|(path, entry)| {
+            if force && fs::symlink_metadata(&path).is_ok() {
+                fs::remove_file(&path)
+                    .map_err(|err| FsIoError::UnableToCreateFileError(format!("{}: {}", path.display(), err)))?;
+            }
+            let mut file = OpenOptions::new();
+            // never follow or overwrite whatever is already there
+            file.write(true).create_new(true);
+            #[cfg(unix)]
+            {
+                use std::os::unix::fs::OpenOptionsExt;
+                file.mode(entry.permissions());
+            }
+            file.open(&path)
+                .and_then(|mut file| file.write_all(&entry.contents))
+                .map_err(|err| FsIoError::UnableToCreateFileError(format!("{}: {}", path.display(), err)))?;
+            Ok(path)
+        })
+        .collect()
+}
+
+/// seal a PNG file against later changes: store a seal chunk signed with the key from `sign_key`,
+/// right before IEND, replacing any previous seal; returns the seal chunk
+pub fn seal(input_path: &Path, sign_key: &Path, output_path: Option<&Path>) -> Result<Chunk> {
+    let mut png = Png::read_file(input_path)?;
+    let signing_key = signature::parse_signing_key(&read_key_file(sign_key)?)?;
+    if png.chunk_by_type(seal::SEAL_CHUNK_TYPE).is_some() {
+        png.remove_chunks(seal::SEAL_CHUNK_TYPE)?;
+    }
+    let seal_chunk = seal::seal(&png, &signing_key)?;
+    png.insert_chunk_at(ChunkPosition::BeforeIend, seal_chunk.clone())?;
+    // make the output either a specified optional path, or the original input path
+    png.write_file(output_path.unwrap_or(input_path))?;
+    Ok(seal_chunk)
+}
+
+/// check a sealed PNG file against its seal; reports the signer and every chunk added, removed or modified since sealing
+pub fn verify_seal(input_path: &Path, trusted_keys: Option<&Path>) -> Result<SealReport> {
+    let png = Png::read_file(input_path)?;
+    seal::verify(&png, &read_trusted_keys(trusted_keys)?)
+}
+
+/// check a sealed PNG file against its seal like `verify_seal`, then remove the seal chunk and save the PNG;
+/// refuses unless the seal is intact, or `force` is set
+pub fn unseal(input_path: &Path, trusted_keys: Option<&Path>, force: bool, output_path: Option<&Path>) -> Result<SealReport> {
+    let mut png = Png::read_file(input_path)?;
+    let report = seal::verify(&png, &read_trusted_keys(trusted_keys)?)?;
+    if !force && !report.is_intact() {
+        let reason = if report.signature.is_trusted() {
+            format!("{} change(s) since sealing", report.changes.len())
+        } else {
+            format!("seal is {}", report.signature)
+        };
+        return Err(anyhow!(SealError::NotIntact(reason)));
+    }
+    png.remove_chunk(seal::SEAL_CHUNK_TYPE)?;
+    // make the output either a specified optional path, or the original input path
+    png.write_file(output_path.unwrap_or(input_path))?;
+    Ok(report)
+}
+
+// write a secret key to a new file; an existing file is never overwritten
+fn write_key_file(output_path: &Path, contents: &str) -> Result<()> {
+    let mut file = OpenOptions::new();
+    file.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        // the key is a secret, keep it private to the current user
+        file.mode(0o600);
+    }
+    file.open(output_path)
+        .and_then(|mut file| file.write_all(contents.as_bytes()))
+        .map_err(|err| anyhow!(FsIoError::UnableToCreateFileError(err.to_string())))
+}
+
+/// generate a new X25519 identity for public-key encrypted messages;
+/// the identity file is written to `output_path` if given, an existing file is never overwritten
+pub fn keygen(output_path: Option<&Path>) -> Result<Keypair> {
+    let keypair = recipients::generate_keypair();
+    if let Some(output_path) = output_path {
+        write_key_file(output_path, &keypair.to_identity_file())?;
+    }
+    Ok(keypair)
+}
+
+/// generate a new Ed25519 key for signing messages;
+/// the signing key file is written to `output_path` if given, an existing file is never overwritten
+pub fn keygen_signing(output_path: Option<&Path>) -> Result<SigningKeypair> {
+    let keypair = signature::generate_signing_key();
+    if let Some(output_path) = output_path {
+        write_key_file(output_path, &keypair.to_key_file())?;
+    }
+    Ok(keypair)
+}
+
+/// How a message payload handed to `encode` is encoded
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum InputEncoding {
+    /// the payload bytes are used as they are
+    #[default]
+    Raw,
+    /// hexadecimal digits, surrounding whitespace is ignored
+    Hex,
+    /// standard, padded base64, surrounding whitespace is ignored
+    Base64,
 }
 
-/// search for hidden message in a PNG file; print the message if it exists
-pub fn decode(args: DecodeArgs) -> Result<()> {
-    let png = Png::read_file(args.input_path)?;
-    let chunk = png.chunk_by_type(args.chunk_type.as_str());
-    if let Some(retrieved_chunk) = chunk {
-        println!("{}", retrieved_chunk);
-        println!("Decodes as: {}", retrieved_chunk.data_as_string().unwrap());
+/// turn an encoded message payload into the bytes to store in the message chunk
+pub fn decode_payload(payload: &[u8], encoding: InputEncoding) -> Result<Vec<u8>> {
+    match encoding {
+        InputEncoding::Raw => Ok(payload.to_vec()),
+        InputEncoding::Hex => Ok(hex::decode(payload.trim_ascii())?),
+        InputEncoding::Base64 => Ok(BASE64.decode(payload.trim_ascii())?),
     }
-    Ok(())
 }
 
-/// remove a chunk from a PNG file and save the resulting PNG
-pub fn remove(args: RemoveArgs) -> Result<()> {
-    let mut png = Png::read_file(args.input_path.clone())?;
-    let removed_chunk = png.remove_chunk(&args.chunk_type)?;
+/// How to render a decoded message payload
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum OutputFormat {
+    /// UTF-8 text, replacing invalid sequences with U+FFFD
+    #[default]
+    Lossy,
+    /// UTF-8 text, failing on invalid sequences
+    Text,
+    /// lowercase hexadecimal digits
+    Hex,
+    /// standard, padded base64
+    Base64,
+    /// the payload bytes exactly as stored
+    Raw,
+}
+
+/// render the payload of a message chunk in the given output format
+pub fn format_payload(chunk: &Chunk, format: OutputFormat) -> Result<Vec<u8>> {
+    let formatted = match format {
+        OutputFormat::Lossy => chunk.data_as_string_lossy(),
+        OutputFormat::Text => chunk.data_as_string()?,
+        OutputFormat::Hex => chunk.data_as_hex(),
+        OutputFormat::Base64 => chunk.data_as_base64(),
+        OutputFormat::Raw => return Ok(chunk.data().clone()),
+    };
+    Ok(formatted.into_bytes())
+}
+
+/// remove the first chunk matching a chunk type from a PNG file, or all of them if `multiple_chunks` is set,
+/// and save the resulting PNG; returns the removed chunks.
+/// Removing a fragment removes every fragment of its message, so no partial message is left behind.
+pub fn remove(input_path: &Path, chunk_type: &str, multiple_chunks: bool, output_path: Option<&Path>) -> Result<Vec<Chunk>> {
+    let mut png = Png::read_file(input_path)?;
+    let removed_chunks = if multiple_chunks {
+        png.remove_chunks(chunk_type)?
+    } else {
+        let (_, first) = png.chunks_by_type(chunk_type).next().ok_or(PNGError::ChunkNotFound)?;
+        match fragment::message_id(first.data()) {
+            Some(message_id) => png.remove_chunks_where(|chunk| {
+                chunk.chunk_type().to_string() == chunk_type && fragment::message_id(chunk.data()) == Some(message_id)
+            }),
+            None => vec![png.remove_chunk(chunk_type)?],
+        }
+    };
     // make the output either a specified optional path, or the original input path
-    let output_path = &args.output_file.unwrap_or_else(|| args.input_path.clone());
+    let output_path = output_path.unwrap_or(input_path);
     png.write_file(output_path)?;
-    Ok(())
+    Ok(removed_chunks)
 }
 
-/// print all chunks in a PNG file
-pub fn print_chunks(args: PrintArgs) -> Result<()> {
-    let png = Png::read_file(args.input_path)?;
+/// remove every chunk from a PNG file that is neither critical nor kept by `keep`, a list of presets and chunk types,
+/// and save the resulting PNG; returns the removed chunks
+pub fn strip<S: AsRef<str>>(input_path: &Path, keep: &[S], output_path: Option<&Path>) -> Result<Vec<Chunk>> {
+    let allowlist = strip::allowlist(keep)?;
+    let mut png = Png::read_file(input_path)?;
+    let removed_chunks = strip::strip(&mut png, &allowlist);
+    png.write_file(output_path.unwrap_or(input_path))?;
+    Ok(removed_chunks)
+}
+
+/// list all chunks in a PNG file, together with the messages among them checked like `verify` does
+pub fn list_chunks(input_path: &Path, trusted_keys: Option<&Path>) -> Result<(Vec<Chunk>, Vec<DecodedMessage>)> {
+    let png = Png::read_file(input_path)?;
+    let messages = verify_messages(&png, None, &read_trusted_keys(trusted_keys)?);
+    Ok((png.chunks().to_vec(), messages))
+}
+
+/// image header and basic chunk statistics of a PNG file, as reported by `pngme info`
+#[derive(Debug, Clone)]
+pub struct PngInfo {
+    pub header: Ihdr,
+    /// total size of the file in bytes, including the signature
+    pub file_size: usize,
+    pub chunk_count: usize,
+    /// number of occurrences per chunk type, in order of first appearance
+    pub chunk_type_counts: Vec<(String, usize)>,
+    /// combined data length of all IDAT chunks, i.e. the compressed image data
+    pub image_data_length: usize,
+}
+
+/// read the image header and gather chunk statistics of a PNG file;
+/// fails if the first chunk is not a valid IHDR
+pub fn info(input_path: &Path) -> Result<PngInfo> {
+    let png = Png::read_file(input_path)?;
+    let header = png.ihdr()?;
+
+    let mut chunk_type_counts: Vec<(String, usize)> = Vec::new();
     for chunk in png.chunks() {
-        println!("Chunk: {}", chunk);
+        let chunk_type = chunk.chunk_type().to_string();
+        match chunk_type_counts.iter_mut().find(|(known, _)| *known == chunk_type) {
+            Some((_, count)) => *count += 1,
+            None => chunk_type_counts.push((chunk_type, 1)),
+        }
     }
-    Ok(())
+    let image_data_length = png.chunks()
+        .iter()
+        .filter(|chunk| chunk.chunk_type().to_string() == "IDAT")
+        .map(|chunk| chunk.length() as usize)
+        .sum();
+
+    Ok(
+        PngInfo {
+            header,
+            file_size: png.as_bytes().len(),
+            chunk_count: png.chunks().len(),
+            chunk_type_counts,
+            image_data_length,
+        }
+    )
 }
 
-///Run the above program based on specified subcommand
-pub fn run(subcommand: PngMeArgs) -> Result<()> {
-    match subcommand {
-        PngMeArgs::Encode(args) => encode(args),
-        PngMeArgs::Decode(args) => decode(args),
-        PngMeArgs::Remove(args) => remove(args),
-        PngMeArgs::Print(args) => print_chunks(args),
+/// check the chunk structure of a PNG file against the PNG spec;
+/// returns every violation found, an empty list means the file is valid
+pub fn check(input_path: &Path) -> Result<Vec<Violation>> {
+    let png = Png::read_file(input_path)?;
+    Ok(validator::validate(&png))
+}
+
+/// estimate how large a message each embedding method can hide in a PNG file,
+/// once wrapped in the layers described by `overhead`
+pub fn capacity(input_path: &Path, overhead: &Overhead) -> Result<Vec<Capacity>> {
+    let png = Png::read_file(input_path)?;
+    capacity::estimate(&png, overhead)
+}
+
+/// The scan of one file, as reported by `pngme scan`
+#[derive(Debug)]
+pub struct ScannedFile {
+    pub path: PathBuf,
+    /// fails if the file is no PNG, or it, or the directory holding it, could not be read
+    pub report: Result<ScanReport>,
+}
+
+// scan a single file
+fn scan_file(path: PathBuf) -> ScannedFile {
+    let report = fs::read(&path)
+        .map_err(|err| anyhow!(FsIoError::UnableToReadFileError(format!("{}: {}", path.display(), err))))
+        .and_then(|bytes| scan::scan(&bytes));
+    ScannedFile { path, report }
+}
+
+// scan the PNG files within a directory tree, in a stable order; directories that cannot be read
+// are reported as failed entries, symlinked directories are skipped, so links cannot loop
+fn scan_dir(dir: &Path, scanned: &mut Vec<ScannedFile>) {
+    let read_error = |path: &Path, err: std::io::Error| ScannedFile {
+        path: path.to_path_buf(),
+        report: Err(anyhow!(FsIoError::UnableToReadFileError(format!("{}: {}", path.display(), err)))),
+    };
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(err) => return scanned.push(read_error(dir, err)),
+    };
+    let mut paths = Vec::new();
+    for entry in entries {
+        match entry {
+            Ok(entry) => paths.push(entry.path()),
+            Err(err) => scanned.push(read_error(dir, err)),
+        }
     }
-}
\ No newline at end of file
+    paths.sort();
+    for path in paths {
+        match fs::symlink_metadata(&path) {
+            Ok(metadata) if metadata.is_dir() => scan_dir(&path, scanned),
+            Ok(metadata) if metadata.is_symlink() && path.is_dir() => {},
+            Ok(_) if path.extension().is_some_and(|extension| extension.eq_ignore_ascii_case("png")) => scanned.push(scan_file(path)),
+            Ok(_) => {},
+            Err(err) => scanned.push(read_error(&path, err)),
+        }
+    }
+}
+
+/// scan a PNG file, or every PNG file in a directory tree, for hidden data;
+/// files and directories that cannot be read are reported per entry
+pub fn scan(input_path: &Path) -> Result<Vec<ScannedFile>> {
+    let mut scanned = Vec::new();
+    if input_path.is_dir() {
+        scan_dir(input_path, &mut scanned);
+    } else {
+        scanned.push(scan_file(input_path.to_path_buf()));
+    }
+    Ok(scanned)
+}
+
+/// analyze the LSBs of the pixels of a PNG file for hidden messages, channel by channel;
+/// optionally writes their LSB plane, of a single channel or all color channels, to `lsb_plane_path`
+pub fn analyze(input_path: &Path, lsb_plane_path: Option<&Path>, channel: Option<usize>) -> Result<Vec<ChannelAnalysis>> {
+    let png = Png::read_file(input_path)?;
+    let pixels = pixels::decode(&png, pixels::DEFAULT_MAX_IMAGE_DATA_LENGTH)?;
+    let analyses = steganalysis::analyze(&pixels)?;
+    if let Some(lsb_plane_path) = lsb_plane_path {
+        let plane = steganalysis::lsb_plane(&pixels, channel)?;
+        pixels::to_png(&plane)?.write_file(lsb_plane_path)?;
+    }
+    Ok(analyses)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::{BundleError, CompressionError, FragmentError, LsbError, SealError};
+    use crate::test_utils::{SAMPLE_PNG_PATH, chunk, scratch_png};
+    use std::env;
+
+    #[test]
+    fn test_encode_then_decode() {
+        let path = scratch_png("encode_decode");
+        encode(&path, "ruSt", b"hidden in plain sight", &EncodeOptions::default(), None).unwrap();
+        let decoded = decode(&path, "ruSt", &DecodeOptions::default()).unwrap();
+        assert_eq!(decoded.len(), 1);
+        assert_eq!(decoded[0].chunk.data_as_string().unwrap(), "hidden in plain sight");
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_encode_inserts_before_iend() {
+        let path = scratch_png("encode_position");
+        encode(&path, "ruSt", b"conformant", &EncodeOptions { position: ChunkPosition::BeforeIend, ..Default::default() }, None).unwrap();
+        let (chunks, _) = list_chunks(&path, None).unwrap();
+        let position = chunks.iter().position(|chunk| chunk.chunk_type().to_string() == "ruSt").unwrap();
+        assert_eq!(&chunks[position + 1].chunk_type().to_string(), "IEND");
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_encode_refuses_unsafe_chunk_types() {
+        let path = scratch_png("encode_unsafe");
+        assert!(encode(&path, "RuST", b"breaks viewers", &EncodeOptions::default(), None).is_err());
+        assert!(encode(&path, "tEXt", b"looks official", &EncodeOptions::default(), None).is_err());
+        assert!(encode(&path, "ruSt", b"fine", &EncodeOptions::default(), None).is_ok());
+        assert!(encode(&path, "RuST", b"forced", &EncodeOptions { force: true, ..Default::default() }, None).is_ok());
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_decode_payload() {
+        assert_eq!(decode_payload(b"6869ff\n", InputEncoding::Hex).unwrap(), vec![0x68, 0x69, 0xff]);
+        assert_eq!(decode_payload(b" aGn/\n", InputEncoding::Base64).unwrap(), vec![0x68, 0x69, 0xff]);
+        assert_eq!(decode_payload(b" aGn/\n", InputEncoding::Raw).unwrap(), b" aGn/\n".to_vec());
+        assert!(decode_payload(b"6g", InputEncoding::Hex).is_err());
+        assert!(decode_payload(b"a!b", InputEncoding::Base64).is_err());
+    }
+
+    #[test]
+    fn test_encode_binary_payload() {
+        let path = scratch_png("encode_binary");
+        let payload: Vec<u8> = (0..=255).collect();
+        encode(&path, "ruSt", &payload, &EncodeOptions::default(), None).unwrap();
+        let decoded = decode(&path, "ruSt", &DecodeOptions::default()).unwrap();
+        assert_eq!(decoded[0].chunk.data(), &payload);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_format_payload() {
+        let chunk = chunk("ruSt", vec![0x68, 0x69, 0xff]);
+        assert!(format_payload(&chunk, OutputFormat::Text).is_err());
+        assert_eq!(format_payload(&chunk, OutputFormat::Lossy).unwrap(), "hi\u{fffd}".as_bytes());
+        assert_eq!(format_payload(&chunk, OutputFormat::Hex).unwrap(), b"6869ff");
+        assert_eq!(format_payload(&chunk, OutputFormat::Base64).unwrap(), b"aGn/");
+        assert_eq!(format_payload(&chunk, OutputFormat::Raw).unwrap(), vec![0x68, 0x69, 0xff]);
+    }
+
+    #[test]
+    fn test_encode_then_decode_encrypted() {
+        let path = scratch_png("encode_encrypted");
+        let encode_options = EncodeOptions { passphrase: Some(String::from("correct horse")), ..Default::default() };
+        let chunk = encode(&path, "ruSt", b"for your eyes only", &encode_options, None).unwrap().remove(0);
+        let envelope = Envelope::parse(chunk.data()).unwrap();
+        assert!(envelope.header.flags.passphrase);
+        assert!(crypto::is_encrypted(&envelope.body));
+
+        // without the passphrase, decode shows the encrypted body
+        let stored = decode(&path, "ruSt", &DecodeOptions::default()).unwrap();
+        assert_eq!(stored[0].chunk.data(), &Envelope::parse(chunk.data()).unwrap().body);
+
+        let decode_options = DecodeOptions { passphrase: Some(String::from("correct horse")), ..Default::default() };
+        let decrypted = decode(&path, "ruSt", &decode_options).unwrap();
+        assert_eq!(decrypted[0].chunk.data_as_string().unwrap(), "for your eyes only");
+
+        let wrong = DecodeOptions { passphrase: Some(String::from("battery staple")), ..Default::default() };
+        let err = decode(&path, "ruSt", &wrong).unwrap_err();
+        assert!(matches!(err.downcast_ref::<CryptoError>(), Some(CryptoError::DecryptionFailed)));
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_encode_then_decode_for_recipients() {
+        let path = scratch_png("encode_recipients");
+        let identity_path = env::temp_dir().join(format!("pngme_commands_identity_{}.txt", std::process::id()));
+        let keypair = keygen(Some(&identity_path)).unwrap();
+        // never overwrite an existing identity
+        assert!(keygen(Some(&identity_path)).is_err());
+
+        let encode_options = EncodeOptions { recipients: vec![keypair.recipient.clone()], ..Default::default() };
+        let chunk = encode(&path, "ruSt", b"for the team", &encode_options, None).unwrap().remove(0);
+        let envelope = Envelope::parse(chunk.data()).unwrap();
+        assert!(envelope.header.flags.recipients);
+        assert!(recipients::is_encrypted(&envelope.body));
+
+        let decode_options = DecodeOptions { identity_file: Some(identity_path.clone()), ..Default::default() };
+        let decrypted = decode(&path, "ruSt", &decode_options).unwrap();
+        assert_eq!(decrypted[0].chunk.data_as_string().unwrap(), "for the team");
+
+        let mixed = EncodeOptions { passphrase: Some(String::from("secret")), ..encode_options };
+        assert!(encode(&path, "ruSt", b"for the team", &mixed, None).is_err());
+        std::fs::remove_file(path).unwrap();
+        std::fs::remove_file(identity_path).unwrap();
+    }
+
+    #[test]
+    fn test_encode_signed_then_verify() {
+        let path = scratch_png("encode_signed");
+        let sign_key_path = env::temp_dir().join(format!("pngme_commands_sign_key_{}.txt", std::process::id()));
+        let trusted_keys_path = env::temp_dir().join(format!("pngme_commands_trusted_keys_{}.txt", std::process::id()));
+        let keypair = keygen_signing(Some(&sign_key_path)).unwrap();
+        std::fs::write(&trusted_keys_path, format!("{} Alice\n", keypair.public_key)).unwrap();
+
+        let signed = EncodeOptions { sign_key: Some(sign_key_path.clone()), ..Default::default() };
+        encode(&path, "ruSt", b"from alice", &signed, None).unwrap();
+        encode(&path, "ruSt", b"from nobody", &EncodeOptions::default(), None).unwrap();
+
+        let decode_options = DecodeOptions {
+            multiple_chunks: true,
+            trusted_keys: Some(trusted_keys_path.clone()),
+            ..Default::default()
+        };
+        let decoded = decode(&path, "ruSt", &decode_options).unwrap();
+        assert_eq!(decoded[0].chunk.data_as_string().unwrap(), "from alice");
+        assert_eq!(decoded[0].signature, SignatureStatus::Trusted(String::from("Alice")));
+        assert_eq!(decoded[1].signature, SignatureStatus::Unsigned);
+
+        // the sample image's own secret is an unsigned critical chunk, only checked when asked for
+        let verified = verify(&path, None, Some(&trusted_keys_path)).unwrap();
+        assert_eq!(verified.len(), 2);
+        assert_eq!(verify(&path, Some("RuSt"), Some(&trusted_keys_path)).unwrap()[0].signature, SignatureStatus::Unsigned);
+        std::fs::remove_file(path).unwrap();
+        std::fs::remove_file(sign_key_path).unwrap();
+        std::fs::remove_file(trusted_keys_path).unwrap();
+    }
+
+    #[test]
+    fn test_encode_signed_and_encrypted() {
+        let path = scratch_png("encode_signed_encrypted");
+        let sign_key_path = env::temp_dir().join(format!("pngme_commands_sign_key_encrypted_{}.txt", std::process::id()));
+        keygen_signing(Some(&sign_key_path)).unwrap();
+        let options = EncodeOptions {
+            passphrase: Some(String::from("correct horse")),
+            sign_key: Some(sign_key_path.clone()),
+            ..Default::default()
+        };
+        let chunk = encode(&path, "ruSt", b"signed secret", &options, None).unwrap().remove(0);
+        assert!(signature::is_signed(chunk.data()));
+
+        // the signer is known without the passphrase
+        let stored = decode(&path, "ruSt", &DecodeOptions::default()).unwrap();
+        assert!(matches!(stored[0].signature, SignatureStatus::Untrusted(_)));
+        assert!(crypto::is_encrypted(stored[0].chunk.data()));
+
+        let decode_options = DecodeOptions { passphrase: Some(String::from("correct horse")), ..Default::default() };
+        let decrypted = decode(&path, "ruSt", &decode_options).unwrap();
+        assert_eq!(decrypted[0].chunk.data_as_string().unwrap(), "signed secret");
+        std::fs::remove_file(path).unwrap();
+        std::fs::remove_file(sign_key_path).unwrap();
+    }
+
+    #[test]
+    fn test_encode_lsb_then_decode() {
+        let path = scratch_png("encode_lsb");
+        let chunk_types = |png: &Png| png.chunks().iter().map(|chunk| chunk.chunk_type().to_string()).filter(|chunk_type| chunk_type != "IDAT").collect::<Vec<String>>();
+        let original = Png::read_file(&path).unwrap();
+        let options = EncodeOptions { method: EmbedMethod::Lsb, bits_per_sample: Some(2), passphrase: Some(String::from("pw")), ..Default::default() };
+        encode(&path, lsb::CHUNK_TYPE, b"invisible ink", &options, None).unwrap();
+
+        // no chunk was added, and the pixels still decode
+        let png = Png::read_file(&path).unwrap();
+        assert_eq!(chunk_types(&png), chunk_types(&original));
+        assert!(pixels::decode(&png, pixels::DEFAULT_MAX_IMAGE_DATA_LENGTH).is_ok());
+
+        let decode_options = DecodeOptions { method: EmbedMethod::Lsb, passphrase: Some(String::from("pw")), ..Default::default() };
+        let decoded = decode(&path, lsb::CHUNK_TYPE, &decode_options).unwrap();
+        assert_eq!(decoded[0].chunk.data_as_string().unwrap(), "invisible ink");
+        assert_eq!(decoded[0].chunk_indices, png.chunks_by_type("IDAT").map(|(index, _)| index).collect::<Vec<usize>>());
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_encode_lsb_scattered_then_decode() {
+        let path = scratch_png("encode_lsb_scattered");
+        let options = EncodeOptions { method: EmbedMethod::Lsb, scatter_passphrase: Some(String::from("key")), ..Default::default() };
+        encode(&path, lsb::CHUNK_TYPE, b"needle in a haystack", &options, None).unwrap();
+
+        let decode_options = DecodeOptions { method: EmbedMethod::Lsb, scatter_passphrase: Some(String::from("key")), ..Default::default() };
+        assert_eq!(decode(&path, lsb::CHUNK_TYPE, &decode_options).unwrap()[0].chunk.data_as_string().unwrap(), "needle in a haystack");
+        let unkeyed = DecodeOptions { method: EmbedMethod::Lsb, ..Default::default() };
+        let err = decode(&path, lsb::CHUNK_TYPE, &unkeyed).unwrap_err();
+        assert!(matches!(err.downcast_ref::<LsbError>(), Some(LsbError::NoMessage)));
+
+        // scattering is meaningless for messages in chunks
+        let chunk_options = EncodeOptions { scatter_passphrase: Some(String::from("key")), ..Default::default() };
+        let err = encode(&path, "ruSt", b"needle", &chunk_options, None).unwrap_err();
+        assert!(matches!(err.downcast_ref::<LsbError>(), Some(LsbError::ScatterWithoutLsb)));
+        let chunk_options = DecodeOptions { scatter_passphrase: Some(String::from("key")), ..Default::default() };
+        let err = decode(&path, "ruSt", &chunk_options).unwrap_err();
+        assert!(matches!(err.downcast_ref::<LsbError>(), Some(LsbError::ScatterWithoutLsb)));
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_lsb_message_survives_stripping_chunks() {
+        let path = scratch_png("lsb_strip");
+        let options = EncodeOptions { method: EmbedMethod::Lsb, ..Default::default() };
+        encode(&path, lsb::CHUNK_TYPE, b"still here", &options, None).unwrap();
+        // keep the critical chunks only
+        let png = Png::read_file(&path).unwrap();
+        let critical: Vec<Chunk> = png.chunks().iter().filter(|chunk| chunk.chunk_type().is_critical()).cloned().collect();
+        Png::from_chunks(critical).write_file(&path).unwrap();
+
+        let decode_options = DecodeOptions { method: EmbedMethod::Lsb, ..Default::default() };
+        assert_eq!(decode(&path, lsb::CHUNK_TYPE, &decode_options).unwrap()[0].chunk.data_as_string().unwrap(), "still here");
+        // nothing to find in chunks
+        assert!(decode(&path, "ruSt", &DecodeOptions::default()).unwrap().is_empty());
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_decode_lsb_without_message() {
+        let path = scratch_png("lsb_none");
+        let decode_options = DecodeOptions { method: EmbedMethod::Lsb, ..Default::default() };
+        let err = decode(&path, lsb::CHUNK_TYPE, &decode_options).unwrap_err();
+        assert!(matches!(err.downcast_ref::<LsbError>(), Some(LsbError::NoMessage)));
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_seal_then_unseal() {
+        let path = scratch_png("seal");
+        let sign_key_path = env::temp_dir().join(format!("pngme_commands_seal_key_{}.txt", std::process::id()));
+        keygen_signing(Some(&sign_key_path)).unwrap();
+        seal(&path, &sign_key_path, None).unwrap();
+        // resealing replaces the seal
+        seal(&path, &sign_key_path, None).unwrap();
+        assert!(verify_seal(&path, None).unwrap().changes.is_empty());
+
+        encode(&path, "ruSt", b"added after sealing", &EncodeOptions::default(), None).unwrap();
+        let report = verify_seal(&path, None).unwrap();
+        assert!(matches!(report.changes.as_slice(), [seal::ChunkChange::Added(_, chunk_type)] if chunk_type == "ruSt"));
+
+        // neither trusted nor unchanged
+        let err = unseal(&path, None, false, None).unwrap_err();
+        assert!(matches!(err.downcast_ref::<SealError>(), Some(SealError::NotIntact(_))));
+        assert!(verify_seal(&path, None).is_ok());
+        unseal(&path, None, true, None).unwrap();
+        assert!(verify_seal(&path, None).is_err());
+        std::fs::remove_file(path).unwrap();
+        std::fs::remove_file(sign_key_path).unwrap();
+    }
+
+    #[test]
+    fn test_encode_compressed_then_decode() {
+        let path = scratch_png("encode_compressed");
+        let message = b"compress me, ".repeat(200);
+        let options = EncodeOptions {
+            compression: Compression::Zlib,
+            passphrase: Some(String::from("correct horse")),
+            ..Default::default()
+        };
+        let chunk = encode(&path, "ruSt", &message, &options, None).unwrap().remove(0);
+        assert!(chunk.data().len() < message.len() / 4);
+
+        let decode_options = DecodeOptions { passphrase: Some(String::from("correct horse")), ..Default::default() };
+        assert_eq!(decode(&path, "ruSt", &decode_options).unwrap()[0].chunk.data(), &message);
+
+        let limited = DecodeOptions { max_decompressed_length: Some(1024), ..decode_options };
+        let err = decode(&path, "ruSt", &limited).unwrap_err();
+        assert!(matches!(err.downcast_ref::<CompressionError>(), Some(CompressionError::TooLarge(1024))));
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_encode_writes_envelope() {
+        let path = scratch_png("encode_envelope");
+        let options = EncodeOptions { filename: Some(String::from("notes.txt")), ..Default::default() };
+        let chunk = encode(&path, "ruSt", b"see attached", &options, None).unwrap().remove(0);
+        assert!(envelope::is_envelope(chunk.data()));
+
+        let decoded = decode(&path, "ruSt", &DecodeOptions::default()).unwrap();
+        assert_eq!(decoded[0].chunk.data_as_string().unwrap(), "see attached");
+        let header = decoded[0].envelope.as_ref().unwrap();
+        assert_eq!(header.content_type, ContentType::Text);
+        assert_eq!(header.filename.as_deref(), Some("notes.txt"));
+
+        // a key for an unencrypted message is a mistake worth reporting
+        let with_passphrase = DecodeOptions { passphrase: Some(String::from("secret")), ..Default::default() };
+        let err = decode(&path, "ruSt", &with_passphrase).unwrap_err();
+        assert!(matches!(err.downcast_ref::<CryptoError>(), Some(CryptoError::NotEncrypted)));
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_decode_legacy_raw_chunk() {
+        // the sample image's secret predates envelopes
+        let path = scratch_png("decode_legacy");
+        let decoded = decode(&path, "RuSt", &DecodeOptions::default()).unwrap();
+        assert_eq!(decoded[0].chunk.data_as_string().unwrap(), "I can has cheezburger?");
+        assert!(decoded[0].envelope.is_none());
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_decode_legacy_chunks_starting_with_magics() {
+        let path = scratch_png("decode_legacy_magics");
+        let messages = [
+            "PMCZ is what I call my compressor",
+            "PMEV: evening meeting, bring snacks",
+            "PMFR fragments of a dream, long enough for a header",
+            "PMES stands for plant maintenance engineering schedule: the pumps in hall 3 are due for inspection on Monday",
+        ];
+        let mut png = Png::read_file(&path).unwrap();
+        for message in messages {
+            png.append_chunk(chunk("ruSt", message.as_bytes().to_vec()));
+        }
+        png.write_file(&path).unwrap();
+
+        let options = DecodeOptions { multiple_chunks: true, ..Default::default() };
+        let decoded = decode(&path, "ruSt", &options).unwrap();
+        let decoded: Vec<String> = decoded.iter().map(|message| message.chunk.data_as_string().unwrap()).collect();
+        assert_eq!(decoded, messages);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_envelope_holds_the_bare_compressed_stream() {
+        let path = scratch_png("envelope_compressed");
+        let options = EncodeOptions { compression: Compression::Zlib, ..Default::default() };
+        let message = b"squeeze me ".repeat(50);
+        let chunk = encode(&path, "ruSt", &message, &options, None).unwrap().remove(0);
+        let envelope = Envelope::parse(chunk.data()).unwrap();
+        assert_eq!(envelope.header.flags.compression, Compression::Zlib);
+        let body = compression::decompress_stream(&envelope.body, Compression::Zlib, compression::DEFAULT_MAX_DECOMPRESSED_LENGTH);
+        assert_eq!(body.unwrap(), message);
+        assert_eq!(decode(&path, "ruSt", &DecodeOptions::default()).unwrap()[0].chunk.data(), &message);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_encode_fragmented_then_decode() {
+        let path = scratch_png("encode_fragmented");
+        let message: Vec<u8> = (0..5000u32).map(|value| (value % 251) as u8).collect();
+        let options = EncodeOptions { max_chunk_length: Some(1024), position: ChunkPosition::AfterIhdr, ..Default::default() };
+        let chunks = encode(&path, "ruSt", &message, &options, None).unwrap();
+        assert_eq!(chunks.len(), 5);
+        assert!(chunks.iter().all(|chunk| chunk.length() <= 1024));
+
+        let decoded = decode(&path, "ruSt", &DecodeOptions::default()).unwrap();
+        assert_eq!(decoded.len(), 1);
+        assert_eq!(decoded[0].chunk.data(), &message);
+        assert_eq!(decoded[0].chunk_indices, vec![1, 2, 3, 4, 5]);
+        assert!(!decoded[0].reordered);
+
+        // losing a fragment is detected
+        let mut png = Png::read_file(&path).unwrap();
+        png.remove_chunk("ruSt").unwrap();
+        png.write_file(&path).unwrap();
+        let err = decode(&path, "ruSt", &DecodeOptions::default()).unwrap_err();
+        assert!(matches!(err.downcast_ref::<FragmentError>(), Some(FragmentError::Missing(_, missing)) if missing == &vec![0]));
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_attach_ls_extract() {
+        let path = scratch_png("attach");
+        let dir = env::temp_dir().join(format!("pngme_commands_attach_{}", std::process::id()));
+        let source_dir = dir.join("source");
+        let target_dir = dir.join("target");
+        std::fs::create_dir_all(&source_dir).unwrap();
+        let files = vec![source_dir.join("config.toml"), source_dir.join("notes.txt")];
+        std::fs::write(&files[0], "port = 8080\n").unwrap();
+        std::fs::write(&files[1], "remember the milk\n").unwrap();
+
+        let options = EncodeOptions { compression: Compression::Zlib, ..Default::default() };
+        attach(&path, bundle::ATTACHMENT_CHUNK_TYPE, &files, &options, None).unwrap();
+        encode(&path, bundle::ATTACHMENT_CHUNK_TYPE, b"not a bundle", &EncodeOptions::default(), None).unwrap();
+
+        let bundles = ls(&path, bundle::ATTACHMENT_CHUNK_TYPE, &DecodeOptions::default()).unwrap();
+        assert_eq!(bundles.len(), 1);
+        let names: Vec<&str> = bundles[0].entries.iter().map(|entry| entry.name.as_str()).collect();
+        assert_eq!(names, vec!["config.toml", "notes.txt"]);
+
+        let written = extract(&path, bundle::ATTACHMENT_CHUNK_TYPE, &DecodeOptions::default(), &target_dir, false).unwrap();
+        assert_eq!(written, vec![target_dir.join("config.toml"), target_dir.join("notes.txt")]);
+        assert_eq!(std::fs::read_to_string(target_dir.join("notes.txt")).unwrap(), "remember the milk\n");
+        // existing files are kept, unless forced
+        assert!(extract(&path, bundle::ATTACHMENT_CHUNK_TYPE, &DecodeOptions::default(), &target_dir, false).is_err());
+        assert!(extract(&path, bundle::ATTACHMENT_CHUNK_TYPE, &DecodeOptions::default(), &target_dir, true).is_ok());
+        std::fs::remove_file(path).unwrap();
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_extract_refuses_path_traversal() {
+        let path = scratch_png("extract_traversal");
+        let dir = env::temp_dir().join(format!("pngme_commands_traversal_{}", std::process::id()));
+        // a crafted bundle, as `attach` never stores such names
+        let mut bundle = bundle::to_bytes(&[BundleEntry { name: String::from("xxxescape"), mode: 0o644, contents: b"pwned".to_vec() }]).unwrap();
+        let name_start = 4 + 1 + 4 + 2;
+        bundle[name_start..name_start + 3].copy_from_slice(b"../");
+        encode_message(&path, bundle::ATTACHMENT_CHUNK_TYPE, &bundle, ContentType::Bundle, &EncodeOptions::default(), None).unwrap();
+
+        let err = extract(&path, bundle::ATTACHMENT_CHUNK_TYPE, &DecodeOptions::default(), &dir, false).unwrap_err();
+        assert!(matches!(err.downcast_ref::<BundleError>(), Some(BundleError::UnsafeName(name)) if name == "../escape"));
+        assert!(!dir.exists());
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_extract_refuses_duplicate_names() {
+        let path = scratch_png("extract_duplicates");
+        let dir = env::temp_dir().join(format!("pngme_commands_duplicates_{}", std::process::id()));
+        // attached twice, into two bundles
+        for contents in [b"first", b"other"] {
+            let bundle = bundle::to_bytes(&[BundleEntry { name: String::from("notes.txt"), mode: 0o644, contents: contents.to_vec() }]).unwrap();
+            encode_message(&path, bundle::ATTACHMENT_CHUNK_TYPE, &bundle, ContentType::Bundle, &EncodeOptions::default(), None).unwrap();
+        }
+
+        let options = DecodeOptions { multiple_chunks: true, ..Default::default() };
+        let err = extract(&path, bundle::ATTACHMENT_CHUNK_TYPE, &options, &dir, true).unwrap_err();
+        assert!(matches!(err.downcast_ref::<BundleError>(), Some(BundleError::DuplicateName(name)) if name == "notes.txt"));
+        assert!(!dir.exists());
+        std::fs::remove_file(path).unwrap();
+    }
+
+    // an intact message right after IHDR, and a 5000 byte message in 5 fragments before IEND
+    fn scratch_png_with_fragments(name: &str) -> PathBuf {
+        let path = scratch_png(name);
+        let message: Vec<u8> = (0..5000u32).map(|value| (value % 251) as u8).collect();
+        encode(&path, "ruSt", &message, &EncodeOptions { max_chunk_length: Some(1024), ..Default::default() }, None).unwrap();
+        encode(&path, "ruSt", b"intact", &EncodeOptions { position: ChunkPosition::AfterIhdr, ..Default::default() }, None).unwrap();
+        path
+    }
+
+    #[test]
+    fn test_verify_reports_damaged_message() {
+        let path = scratch_png_with_fragments("verify_damaged");
+        let mut png = Png::read_file(&path).unwrap();
+        png.remove_chunk_where(|chunk| fragment::is_fragment(chunk.data())).unwrap();
+        png.write_file(&path).unwrap();
+
+        // as used by `print`
+        let messages = verify(&path, None, None).unwrap();
+        assert_eq!(messages.len(), 2);
+        assert!(messages[0].damage.is_none());
+        assert!(matches!(&messages[1].damage, Some(FragmentError::Missing(_, missing)) if missing == &vec![0]));
+        assert_eq!(messages[1].chunk_indices.len(), 4);
+
+        // only the messages selected have to be complete
+        let decoded = decode(&path, "ruSt", &DecodeOptions::default()).unwrap();
+        assert_eq!(decoded[0].chunk.data(), b"intact");
+        let multiple = DecodeOptions { multiple_chunks: true, ..Default::default() };
+        assert!(decode(&path, "ruSt", &multiple).is_err());
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_remove_fragmented_message() {
+        let path = scratch_png_with_fragments("remove_fragmented");
+        assert_eq!(remove(&path, "ruSt", false, None).unwrap().len(), 1);
+        // the first chunk left is a fragment, its whole message goes
+        assert_eq!(remove(&path, "ruSt", false, None).unwrap().len(), 5);
+        assert!(decode(&path, "ruSt", &DecodeOptions::default()).unwrap().is_empty());
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_remove_returns_removed_chunk() {
+        let path = scratch_png("remove");
+        encode(&path, "ruSt", b"short-lived", &EncodeOptions::default(), None).unwrap();
+        let removed = remove(&path, "ruSt", false, None).unwrap();
+        assert_eq!(Envelope::parse(removed[0].data()).unwrap().body, b"short-lived");
+        assert!(decode(&path, "ruSt", &DecodeOptions::default()).unwrap().is_empty());
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_multiple_chunks() {
+        let path = scratch_png("multiple");
+        let multiple = DecodeOptions { multiple_chunks: true, ..Default::default() };
+        encode(&path, "ruSt", b"first", &EncodeOptions::default(), None).unwrap();
+        encode(&path, "ruSt", b"second", &EncodeOptions::default(), None).unwrap();
+
+        let decoded = decode(&path, "ruSt", &multiple).unwrap();
+        let messages: Vec<String> = decoded.iter().map(|message| message.chunk.data_as_string().unwrap()).collect();
+        assert_eq!(messages, vec!["first", "second"]);
+        assert_eq!(decoded[0].index + 1, decoded[1].index);
+        assert_eq!(decode(&path, "ruSt", &DecodeOptions::default()).unwrap().len(), 1);
+
+        let removed = remove(&path, "ruSt", true, None).unwrap();
+        assert_eq!(removed.len(), 2);
+        assert!(decode(&path, "ruSt", &multiple).unwrap().is_empty());
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_strip_removes_messages() {
+        let path = scratch_png("strip");
+        encode(&path, "ruSt", b"to be scrubbed", &EncodeOptions::default(), None).unwrap();
+        let removed = strip(&path, &["color"], None).unwrap();
+        let removed_types: Vec<String> = removed.iter().map(|chunk| chunk.chunk_type().to_string()).collect();
+        assert!(removed_types.contains(&String::from("ruSt")) && removed_types.contains(&String::from("RuSt")));
+        assert!(decode(&path, "ruSt", &DecodeOptions::default()).unwrap().is_empty());
+        assert!(check(&path).unwrap().is_empty());
+        assert!(strip(&path, &["color"], None).unwrap().is_empty());
+        assert!(strip(&path, &["colour"], None).is_err());
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_info() {
+        let path = scratch_png("info");
+        let info = info(&path).unwrap();
+        assert_eq!(info.header.width(), 727);
+        assert_eq!(info.header.height(), 684);
+        assert_eq!(info.chunk_type_counts[0], (String::from("IHDR"), 1));
+        assert!(info.chunk_type_counts.contains(&(String::from("IDAT"), 12)));
+        assert_eq!(info.file_size, std::fs::metadata(&path).unwrap().len() as usize);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_check_reports_chunk_after_iend() {
+        // the sample image carries its secret after IEND
+        let path = scratch_png("check");
+        let violations = check(&path).unwrap();
+        assert!(!violations.is_empty());
+        assert!(violations.iter().all(|violation| violation.chunk_index == Some(20)));
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_lsb_capacity_fits_encoded_message() {
+        let path = scratch_png("capacity");
+        let overhead = Overhead { passphrase: true, ..Default::default() };
+        let capacities = capacity(&path, &overhead).unwrap();
+        let lsb = capacities.iter().find(|capacity| capacity.carrier == capacity::Carrier::Lsb(1)).unwrap();
+        let length = lsb.message.unwrap();
+        let options = EncodeOptions { method: EmbedMethod::Lsb, passphrase: Some(String::from("pw")), ..Default::default() };
+        encode(&path, lsb::CHUNK_TYPE, &vec![b'x'; length], &options, None).unwrap();
+        assert!(encode(&path, lsb::CHUNK_TYPE, &vec![b'x'; length + 1], &options, None).is_err());
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_scan_directory_tree() {
+        let dir = env::temp_dir().join(format!("pngme_commands_scan_{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("nested")).unwrap();
+        std::fs::copy(SAMPLE_PNG_PATH, dir.join("nested").join("cat.PNG")).unwrap();
+        std::fs::write(dir.join("broken.png"), b"no png").unwrap();
+        std::fs::write(dir.join("notes.txt"), b"skipped").unwrap();
+
+        let scanned = scan(&dir).unwrap();
+        assert_eq!(scanned.len(), 2);
+        assert!(scanned[0].path.ends_with("broken.png") && scanned[0].report.is_err());
+        assert!(scanned[1].path.ends_with("nested/cat.PNG"));
+        assert_eq!(scanned[1].report.as_ref().unwrap().score(), 80);
+
+        // a symlink back up the tree is not followed
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::symlink(&dir, dir.join("nested").join("loop")).unwrap();
+            assert_eq!(scan(&dir).unwrap().len(), 2);
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        // a directory that cannot be read is a failed entry, not a failed scan
+        let mut scanned = Vec::new();
+        scan_dir(&dir, &mut scanned);
+        assert!(scanned.len() == 1 && scanned[0].path == dir && scanned[0].report.is_err());
+    }
+
+    #[test]
+    fn test_analyze_exports_lsb_plane() {
+        let path = scratch_png("analyze");
+        let plane_path = env::temp_dir().join(format!("pngme_commands_analyze_plane_{}.png", std::process::id()));
+        let analyses = analyze(&path, Some(&plane_path), Some(3)).unwrap();
+        assert_eq!(analyses.len(), 4);
+        let plane = pixels::decode(&Png::read_file(&plane_path).unwrap(), pixels::DEFAULT_MAX_IMAGE_DATA_LENGTH).unwrap();
+        assert_eq!((plane.width(), plane.height(), plane.channels()), (727, 684, 1));
+        std::fs::remove_file(path).unwrap();
+        std::fs::remove_file(plane_path).unwrap();
+    }
+
+    #[test]
+    fn test_list_chunks() {
+        let path = scratch_png("list");
+        encode(&path, "ruSt", b"listed", &EncodeOptions::default(), None).unwrap();
+        let (chunks, messages) = list_chunks(&path, None).unwrap();
+        assert_eq!(&chunks[0].chunk_type().to_string(), "IHDR");
+        let position = chunks.iter().position(|chunk| chunk.chunk_type().to_string() == "ruSt").unwrap();
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].chunk_indices, vec![position]);
+        assert_eq!(messages[0].signature, SignatureStatus::Unsigned);
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,177 @@
+use std::io::{Read, Write};
+
+use flate2::{read::ZlibDecoder, write::ZlibEncoder};
+
+use anyhow::{anyhow, Result};
+
+use crate::error as PngMeError;
+
+/*
+    Optional compression of message payloads, applied before encryption and signing.
+
+    Messages in an envelope hold the bare compressed stream, the envelope records the algorithm.
+
+    Decompression never produces more than a given limit of bytes, whatever the stream claims,
+    so a crafted chunk cannot expand into gigabytes of memory (a "decompression bomb").
+    zstd support is only compiled in with the `zstd` feature.
+*/
+
+/// default upper bound for decompressed payloads: 64 MiB
+pub const DEFAULT_MAX_DECOMPRESSED_LENGTH: usize = 64 << 20;
+
+const NONE: u8 = 0;
+const ZLIB: u8 = 1;
+#[cfg(feature = "zstd")]
+const ZSTD: u8 = 2;
+
+/// How to compress a message payload
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum Compression {
+    /// store the payload as it is
+    #[default]
+    None,
+    /// zlib (deflate), supported by every pngme build
+    Zlib,
+    /// zstd, usually smaller and faster than zlib
+    #[cfg(feature = "zstd")]
+    Zstd,
+}
+
+impl Compression {
+    /// the algorithm as recorded in envelopes
+    pub fn to_byte(self) -> u8 {
+        match self {
+            Compression::None => NONE,
+            Compression::Zlib => ZLIB,
+            #[cfg(feature = "zstd")]
+            Compression::Zstd => ZSTD,
+        }
+    }
+
+    /// the algorithm recorded as `algorithm`; fails for unknown ones, and for zstd in builds without it
+    pub fn from_byte(algorithm: u8) -> Result<Self> {
+        match algorithm {
+            NONE => Ok(Compression::None),
+            ZLIB => Ok(Compression::Zlib),
+            #[cfg(feature = "zstd")]
+            ZSTD => Ok(Compression::Zstd),
+            _ => Err(anyhow!(PngMeError::CompressionError::UnsupportedAlgorithm(algorithm))),
+        }
+    }
+}
+
+/// compress data into a bare stream; `Compression::None` returns it unchanged
+pub fn compress_stream(data: &[u8], compression: Compression) -> Result<Vec<u8>> {
+    match compression {
+        Compression::None => Ok(data.to_vec()),
+        Compression::Zlib => {
+            let mut encoder = ZlibEncoder::new(Vec::new(), flate2::Compression::best());
+            encoder.write_all(data)?;
+            Ok(encoder.finish()?)
+        },
+        #[cfg(feature = "zstd")]
+        Compression::Zstd => Ok(zstd::stream::encode_all(data, zstd::DEFAULT_COMPRESSION_LEVEL)?),
+    }
+}
+
+// read at most `max_length` bytes from a decoder, failing if there is more
+fn read_limited<R: Read>(decoder: R, max_length: usize) -> Result<Vec<u8>> {
+    let mut decompressed = Vec::new();
+    decoder
+        .take(max_length as u64 + 1)
+        .read_to_end(&mut decompressed)
+        .map_err(|err| PngMeError::CompressionError::CorruptData(err.to_string()))?;
+    if decompressed.len() > max_length {
+        return Err(anyhow!(PngMeError::CompressionError::TooLarge(max_length)));
+    }
+    Ok(decompressed)
+}
+
+/// decompress a bare stream produced by `compress_stream`, refusing to produce more than `max_length` bytes
+pub fn decompress_stream(stream: &[u8], compression: Compression, max_length: usize) -> Result<Vec<u8>> {
+    match compression {
+        Compression::None if stream.len() > max_length => Err(anyhow!(PngMeError::CompressionError::TooLarge(max_length))),
+        Compression::None => Ok(stream.to_vec()),
+        Compression::Zlib => read_limited(ZlibDecoder::new(stream), max_length),
+        #[cfg(feature = "zstd")]
+        Compression::Zstd => read_limited(zstd::stream::read::Decoder::new(stream)?, max_length),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text() -> Vec<u8> {
+        b"All work and no play makes Jack a dull boy. ".repeat(100)
+    }
+
+    #[test]
+    fn test_zlib_roundtrip() {
+        let compressed = compress_stream(&text(), Compression::Zlib).unwrap();
+        assert!(compressed.len() < text().len() / 10);
+        assert_eq!(decompress_stream(&compressed, Compression::Zlib, DEFAULT_MAX_DECOMPRESSED_LENGTH).unwrap(), text());
+    }
+
+    #[cfg(feature = "zstd")]
+    #[test]
+    fn test_zstd_roundtrip() {
+        let compressed = compress_stream(&text(), Compression::Zstd).unwrap();
+        assert!(compressed.len() < text().len() / 10);
+        assert_eq!(decompress_stream(&compressed, Compression::Zstd, DEFAULT_MAX_DECOMPRESSED_LENGTH).unwrap(), text());
+    }
+
+    #[test]
+    fn test_no_compression() {
+        assert_eq!(compress_stream(b"as is", Compression::None).unwrap(), b"as is");
+        assert_eq!(decompress_stream(b"as is", Compression::None, DEFAULT_MAX_DECOMPRESSED_LENGTH).unwrap(), b"as is");
+        assert!(matches!(
+            decompress_stream(b"as is", Compression::None, 2).unwrap_err().downcast_ref::<PngMeError::CompressionError>(),
+            Some(PngMeError::CompressionError::TooLarge(2))
+        ));
+    }
+
+    #[test]
+    fn test_decompression_limit() {
+        let compressed = compress_stream(&text(), Compression::Zlib).unwrap();
+        assert!(matches!(
+            decompress_stream(&compressed, Compression::Zlib, 100).unwrap_err().downcast_ref::<PngMeError::CompressionError>(),
+            Some(PngMeError::CompressionError::TooLarge(100))
+        ));
+    }
+
+    #[test]
+    fn test_bomb_is_bounded() {
+        // a few hundred bytes expanding into a megabyte are cut off at the limit
+        let compressed = compress_stream(&vec![0u8; 1 << 20], Compression::Zlib).unwrap();
+        assert!(compressed.len() < 2048);
+        assert!(matches!(
+            decompress_stream(&compressed, Compression::Zlib, 1024).unwrap_err().downcast_ref::<PngMeError::CompressionError>(),
+            Some(PngMeError::CompressionError::TooLarge(1024))
+        ));
+    }
+
+    #[test]
+    fn test_corrupt_data() {
+        let mut compressed = compress_stream(&text(), Compression::Zlib).unwrap();
+        let last = compressed.len() - 1;
+        compressed[last] ^= 1;
+        assert!(matches!(
+            decompress_stream(&compressed, Compression::Zlib, DEFAULT_MAX_DECOMPRESSED_LENGTH)
+                .unwrap_err()
+                .downcast_ref::<PngMeError::CompressionError>(),
+            Some(PngMeError::CompressionError::CorruptData(_))
+        ));
+    }
+
+    #[test]
+    fn test_algorithm_bytes() {
+        assert_eq!(Compression::from_byte(Compression::Zlib.to_byte()).unwrap(), Compression::Zlib);
+        assert_eq!(Compression::from_byte(NONE).unwrap(), Compression::None);
+        assert!(matches!(
+            Compression::from_byte(42).unwrap_err().downcast_ref::<PngMeError::CompressionError>(),
+            Some(PngMeError::CompressionError::UnsupportedAlgorithm(42))
+        ));
+    }
+}
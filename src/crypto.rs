@@ -0,0 +1,259 @@
+use argon2::{Algorithm, Argon2, Params, Version};
+use chacha20poly1305::aead::{rand_core::RngCore, Aead, AeadCore, KeyInit, OsRng, Payload};
+use chacha20poly1305::{Key, XChaCha20Poly1305, XNonce};
+
+use anyhow::{anyhow, Result};
+
+use crate::error as PngMeError;
+
+/*
+    Passphrase-based authenticated encryption of message payloads.
+
+    The passphrase is stretched into a 256 bit key with the memory-hard Argon2id KDF,
+    the payload is sealed with XChaCha20-Poly1305. Everything needed for decryption except
+    the passphrase travels in a versioned header in front of the ciphertext:
+
+        magic "PMEC"         4 bytes
+        version              1 byte
+        Argon2 memory (KiB)  4 bytes, big endian
+        Argon2 iterations    4 bytes, big endian
+        Argon2 parallelism   4 bytes, big endian
+        salt                16 bytes
+        nonce               24 bytes
+        ciphertext + tag     remaining bytes
+
+    The header is authenticated as associated data, so tampering with it fails decryption just like
+    tampering with the ciphertext does.
+*/
+
+/// identifies payloads encrypted by pngme
+pub const MAGIC: [u8; 4] = *b"PMEC";
+/// current version of the encrypted payload format
+pub const VERSION: u8 = 1;
+
+const SALT_LENGTH: usize = 16;
+const NONCE_LENGTH: usize = 24;
+const KEY_LENGTH: usize = 32;
+/// magic, version, KDF parameters, salt and nonce
+pub const HEADER_LENGTH: usize = 4 + 1 + 12 + SALT_LENGTH + NONCE_LENGTH;
+/// Poly1305 authentication tag appended to the ciphertext
+pub const TAG_LENGTH: usize = 16;
+
+// upper bounds for KDF parameters read from untrusted headers, so a crafted chunk cannot exhaust memory or CPU;
+// a few times the defaults pngme writes: 64 MiB of memory, 8 iterations, 4 lanes
+const MAX_MEMORY_KIB: u32 = 64 << 10;
+const MAX_ITERATIONS: u32 = 8;
+const MAX_PARALLELISM: u32 = 4;
+
+/// Cost parameters of the Argon2id key derivation
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KdfParams {
+    pub memory_kib: u32,
+    pub iterations: u32,
+    pub parallelism: u32,
+}
+
+impl Default for KdfParams {
+    /// the Argon2id defaults recommended by OWASP: 19 MiB of memory, 2 iterations, 1 lane
+    fn default() -> Self {
+        KdfParams {
+            memory_kib: Params::DEFAULT_M_COST,
+            iterations: Params::DEFAULT_T_COST,
+            parallelism: Params::DEFAULT_P_COST,
+        }
+    }
+}
+
+impl KdfParams {
+    /// stretch a passphrase into a 256 bit key, for XChaCha20-Poly1305 or any other use
+    pub fn derive_key(&self, passphrase: &str, salt: &[u8]) -> Result<[u8; KEY_LENGTH]> {
+        if self.memory_kib > MAX_MEMORY_KIB || self.iterations > MAX_ITERATIONS || self.parallelism > MAX_PARALLELISM {
+            return Err(anyhow!(PngMeError::CryptoError::InvalidKdfParams(format!(
+                "memory {} KiB, {} iterations, parallelism {} exceed the supported limits",
+                self.memory_kib, self.iterations, self.parallelism
+            ))));
+        }
+        let params = Params::new(self.memory_kib, self.iterations, self.parallelism, Some(KEY_LENGTH))
+            .map_err(|err| PngMeError::CryptoError::InvalidKdfParams(err.to_string()))?;
+        let mut key = [0u8; KEY_LENGTH];
+        Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+            .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+            .map_err(|err| PngMeError::CryptoError::InvalidKdfParams(err.to_string()))?;
+        Ok(key)
+    }
+}
+
+/// check whether a payload starts with the header of an encrypted pngme payload
+pub fn is_encrypted(payload: &[u8]) -> bool {
+    payload.starts_with(&MAGIC)
+}
+
+/// encrypt a payload with a passphrase, using the default KDF parameters
+pub fn encrypt(plaintext: &[u8], passphrase: &str) -> Result<Vec<u8>> {
+    encrypt_with_params(plaintext, passphrase, KdfParams::default())
+}
+
+/// encrypt a payload with a passphrase and explicit KDF parameters; returns header and ciphertext
+pub fn encrypt_with_params(plaintext: &[u8], passphrase: &str, kdf_params: KdfParams) -> Result<Vec<u8>> {
+    let mut salt = [0u8; SALT_LENGTH];
+    OsRng.fill_bytes(&mut salt);
+    let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+    let key = kdf_params.derive_key(passphrase, &salt)?;
+
+    let header: Vec<u8> = MAGIC
+        .iter()
+        .chain([VERSION].iter())
+        .chain(kdf_params.memory_kib.to_be_bytes().iter())
+        .chain(kdf_params.iterations.to_be_bytes().iter())
+        .chain(kdf_params.parallelism.to_be_bytes().iter())
+        .chain(salt.iter())
+        .chain(nonce.iter())
+        .copied()
+        .collect();
+
+    let cipher = XChaCha20Poly1305::new(Key::from_slice(&key));
+    let ciphertext = cipher
+        .encrypt(&nonce, Payload { msg: plaintext, aad: &header })
+        .map_err(|_| PngMeError::CryptoError::EncryptionFailed)?;
+
+    Ok(header.into_iter().chain(ciphertext).collect())
+}
+
+/// decrypt a payload produced by `encrypt`; fails with `CryptoError::DecryptionFailed`
+/// if the passphrase is wrong or header or ciphertext were tampered with
+pub fn decrypt(payload: &[u8], passphrase: &str) -> Result<Vec<u8>> {
+    if !is_encrypted(payload) {
+        return Err(anyhow!(PngMeError::CryptoError::NotEncrypted));
+    }
+    if payload.len() < HEADER_LENGTH + TAG_LENGTH {
+        return Err(anyhow!(PngMeError::CryptoError::InvalidHeader(format!(
+            "expected at least {} bytes, found {}",
+            HEADER_LENGTH + TAG_LENGTH,
+            payload.len()
+        ))));
+    }
+    let (header, ciphertext) = payload.split_at(HEADER_LENGTH);
+    let version = header[4];
+    if version != VERSION {
+        return Err(anyhow!(PngMeError::CryptoError::UnsupportedVersion(version)));
+    }
+
+    let read_u32 = |start: usize| u32::from_be_bytes([header[start], header[start + 1], header[start + 2], header[start + 3]]);
+    let kdf_params = KdfParams {
+        memory_kib: read_u32(5),
+        iterations: read_u32(9),
+        parallelism: read_u32(13),
+    };
+    let salt = &header[17..17 + SALT_LENGTH];
+    let nonce = XNonce::from_slice(&header[17 + SALT_LENGTH..]);
+    let key = kdf_params.derive_key(passphrase, salt)?;
+
+    let cipher = XChaCha20Poly1305::new(Key::from_slice(&key));
+    let plaintext = cipher
+        .decrypt(nonce, Payload { msg: ciphertext, aad: header })
+        .map_err(|_| PngMeError::CryptoError::DecryptionFailed)?;
+    Ok(plaintext)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // keep tests fast; the minimum Argon2 memory cost is 8 KiB per lane
+    const TEST_PARAMS: KdfParams = KdfParams { memory_kib: 8, iterations: 1, parallelism: 1 };
+
+    #[test]
+    fn test_encrypt_decrypt_roundtrip() {
+        let encrypted = encrypt_with_params(b"meet me at midnight", "correct horse", TEST_PARAMS).unwrap();
+        assert!(is_encrypted(&encrypted));
+        assert_eq!(encrypted.len(), HEADER_LENGTH + 19 + TAG_LENGTH);
+        assert_eq!(decrypt(&encrypted, "correct horse").unwrap(), b"meet me at midnight");
+    }
+
+    #[test]
+    fn test_encryption_is_randomized() {
+        let first = encrypt_with_params(b"same", "same", TEST_PARAMS).unwrap();
+        let second = encrypt_with_params(b"same", "same", TEST_PARAMS).unwrap();
+        assert_ne!(first, second);
+    }
+
+    #[test]
+    fn test_wrong_passphrase() {
+        let encrypted = encrypt_with_params(b"meet me at midnight", "correct horse", TEST_PARAMS).unwrap();
+        assert!(matches!(
+            decrypt(&encrypted, "battery staple").unwrap_err().downcast_ref::<PngMeError::CryptoError>(),
+            Some(PngMeError::CryptoError::DecryptionFailed)
+        ));
+    }
+
+    #[test]
+    fn test_tampered_ciphertext() {
+        let mut encrypted = encrypt_with_params(b"meet me at midnight", "correct horse", TEST_PARAMS).unwrap();
+        let last = encrypted.len() - 1;
+        encrypted[last] ^= 1;
+        assert!(matches!(
+            decrypt(&encrypted, "correct horse").unwrap_err().downcast_ref::<PngMeError::CryptoError>(),
+            Some(PngMeError::CryptoError::DecryptionFailed)
+        ));
+    }
+
+    #[test]
+    fn test_tampered_header() {
+        let mut encrypted = encrypt_with_params(b"meet me at midnight", "correct horse", TEST_PARAMS).unwrap();
+        // flip a bit of the nonce
+        encrypted[HEADER_LENGTH - 1] ^= 1;
+        assert!(matches!(
+            decrypt(&encrypted, "correct horse").unwrap_err().downcast_ref::<PngMeError::CryptoError>(),
+            Some(PngMeError::CryptoError::DecryptionFailed)
+        ));
+    }
+
+    #[test]
+    fn test_unencrypted_payload() {
+        assert!(!is_encrypted(b"plain text"));
+        assert!(matches!(
+            decrypt(b"plain text", "correct horse").unwrap_err().downcast_ref::<PngMeError::CryptoError>(),
+            Some(PngMeError::CryptoError::NotEncrypted)
+        ));
+    }
+
+    #[test]
+    fn test_unsupported_version() {
+        let mut encrypted = encrypt_with_params(b"meet me at midnight", "correct horse", TEST_PARAMS).unwrap();
+        encrypted[4] = 2;
+        assert!(matches!(
+            decrypt(&encrypted, "correct horse").unwrap_err().downcast_ref::<PngMeError::CryptoError>(),
+            Some(PngMeError::CryptoError::UnsupportedVersion(2))
+        ));
+    }
+
+    #[test]
+    fn test_excessive_kdf_params() {
+        let mut encrypted = encrypt_with_params(b"meet me at midnight", "correct horse", TEST_PARAMS).unwrap();
+        encrypted[5..9].copy_from_slice(&u32::MAX.to_be_bytes());
+        assert!(matches!(
+            decrypt(&encrypted, "correct horse").unwrap_err().downcast_ref::<PngMeError::CryptoError>(),
+            Some(PngMeError::CryptoError::InvalidKdfParams(_))
+        ));
+    }
+
+    #[test]
+    fn test_kdf_params_are_capped_near_the_defaults() {
+        let defaults = KdfParams::default();
+        assert!(defaults.memory_kib <= MAX_MEMORY_KIB && defaults.iterations <= MAX_ITERATIONS && defaults.parallelism <= MAX_PARALLELISM);
+        // just over each limit; refused before any memory is allocated
+        let over_limit = [
+            (5, MAX_MEMORY_KIB + 1),
+            (9, MAX_ITERATIONS + 1),
+            (13, MAX_PARALLELISM + 1),
+        ];
+        for (offset, value) in over_limit {
+            let mut encrypted = encrypt_with_params(b"meet me at midnight", "correct horse", TEST_PARAMS).unwrap();
+            encrypted[offset..offset + 4].copy_from_slice(&value.to_be_bytes());
+            assert!(matches!(
+                decrypt(&encrypted, "correct horse").unwrap_err().downcast_ref::<PngMeError::CryptoError>(),
+                Some(PngMeError::CryptoError::InvalidKdfParams(_))
+            ));
+        }
+    }
+}
@@ -0,0 +1,362 @@
+use std::fmt;
+
+use anyhow::{anyhow, Result};
+
+use crate::chunk::calculate_crc_ieee_checksum;
+use crate::compression::Compression;
+use crate::error as PngMeError;
+
+/*
+    Self-describing envelope around message payloads, so decode knows what a message chunk holds
+    and which pngme wrote it, instead of guessing from its first bytes.
+
+        magic "PMEV"         4 bytes
+        version              1 byte
+        writer version       3 bytes: major, minor, patch of the pngme that wrote it
+        flags                1 byte: 0x02 encrypted with passphrase, 0x04 encrypted to recipients
+        compression          1 byte: 0 = none, 1 = zlib, 2 = zstd
+        content type         1 byte: 0 = binary, 1 = UTF-8 text, 2 = file bundle
+        filename length      1 byte, 0 if there is no filename
+        filename             UTF-8, up to 255 bytes
+        body length          4 bytes, big endian
+        body checksum        4 bytes, big endian: CRC-32 (IEEE) of the body
+        body                 remaining bytes
+
+    The header describes the message before compression and encryption; the body is the message
+    after both. The envelope is the only header describing them: the body holds the bare compressed
+    stream, and decode never guesses from the body what was done to it.
+    Signatures wrap the whole envelope. The filename is stored in the clear, even for encrypted messages.
+
+    Chunks written by older versions of pngme hold the raw message only; `is_envelope` tells them apart,
+    by the magic and a valid fixed-size header, so raw messages merely starting with "PMEV" stay raw.
+*/
+
+/// identifies message envelopes
+pub const MAGIC: [u8; 4] = *b"PMEV";
+/// current version of the envelope format
+pub const VERSION: u8 = 1;
+/// longest filename an envelope can carry, in bytes
+pub const MAX_FILENAME_LENGTH: usize = u8::MAX as usize;
+
+const PASSPHRASE: u8 = 0x02;
+const RECIPIENTS: u8 = 0x04;
+// fixed-size part of the header before the filename
+const PREFIX_LENGTH: usize = 4 + 1 + 3 + 1 + 1 + 1 + 1;
+/// fixed-size part of the header: everything but the filename
+pub const HEADER_LENGTH: usize = PREFIX_LENGTH + 4 + 4;
+
+/// Processing applied to the message before it became the envelope body
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct EnvelopeFlags {
+    pub compression: Compression,
+    pub passphrase: bool,
+    pub recipients: bool,
+}
+
+impl EnvelopeFlags {
+    fn to_byte(self) -> u8 {
+        [(self.passphrase, PASSPHRASE), (self.recipients, RECIPIENTS)]
+            .iter()
+            .filter(|(set, _)| *set)
+            .fold(0, |flags, (_, flag)| flags | flag)
+    }
+
+    fn from_bytes(flags: u8, compression: u8) -> Result<Self> {
+        if flags & !(PASSPHRASE | RECIPIENTS) != 0 || flags & PASSPHRASE != 0 && flags & RECIPIENTS != 0 {
+            return Err(anyhow!(PngMeError::EnvelopeError::UnsupportedFlags(flags)));
+        }
+        Ok(
+            EnvelopeFlags {
+                compression: Compression::from_byte(compression)?,
+                passphrase: flags & PASSPHRASE != 0,
+                recipients: flags & RECIPIENTS != 0,
+            }
+        )
+    }
+
+    /// the body is encrypted, either with a passphrase or to recipients
+    pub fn is_encrypted(&self) -> bool {
+        self.passphrase || self.recipients
+    }
+}
+
+/// What kind of data a message holds
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ContentType {
+    #[default]
+    Binary = 0,
+    Text = 1,
+    /// files attached with `pngme attach`, see `bundle`
+    Bundle = 2,
+}
+
+impl ContentType {
+    /// text if the message is valid UTF-8, binary otherwise
+    pub fn detect(message: &[u8]) -> Self {
+        if std::str::from_utf8(message).is_ok() { ContentType::Text } else { ContentType::Binary }
+    }
+}
+
+impl TryFrom<u8> for ContentType {
+    type Error = anyhow::Error;
+
+    fn try_from(value: u8) -> Result<Self> {
+        match value {
+            0 => Ok(ContentType::Binary),
+            1 => Ok(ContentType::Text),
+            2 => Ok(ContentType::Bundle),
+            _ => Err(anyhow!(PngMeError::EnvelopeError::InvalidContentType(value))),
+        }
+    }
+}
+
+impl fmt::Display for ContentType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ContentType::Binary => write!(f, "binary"),
+            ContentType::Text => write!(f, "text"),
+            ContentType::Bundle => write!(f, "file bundle"),
+        }
+    }
+}
+
+/// Everything an envelope tells about its message, without the body
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EnvelopeHeader {
+    /// envelope format version
+    pub version: u8,
+    /// major, minor and patch version of the pngme that wrote the envelope
+    pub writer_version: (u8, u8, u8),
+    pub flags: EnvelopeFlags,
+    pub content_type: ContentType,
+    pub filename: Option<String>,
+}
+
+impl EnvelopeHeader {
+    /// a header for messages written by this version of pngme
+    pub fn new(flags: EnvelopeFlags, content_type: ContentType, filename: Option<String>) -> Self {
+        let version_part = |part: &str| part.parse::<u8>().unwrap_or(u8::MAX);
+        EnvelopeHeader {
+            version: VERSION,
+            writer_version: (
+                version_part(env!("CARGO_PKG_VERSION_MAJOR")),
+                version_part(env!("CARGO_PKG_VERSION_MINOR")),
+                version_part(env!("CARGO_PKG_VERSION_PATCH")),
+            ),
+            flags,
+            content_type,
+            filename,
+        }
+    }
+}
+
+impl fmt::Display for EnvelopeHeader {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (major, minor, patch) = self.writer_version;
+        write!(f, "v{} (pngme {}.{}.{}), {}", self.version, major, minor, patch, self.content_type)?;
+        if let Some(filename) = &self.filename {
+            write!(f, ", file \"{}\"", filename)?;
+        }
+        if self.flags.compression != Compression::None {
+            write!(f, ", compressed")?;
+        }
+        if self.flags.passphrase {
+            write!(f, ", encrypted with passphrase")?;
+        }
+        if self.flags.recipients {
+            write!(f, ", encrypted to recipients")?;
+        }
+        Ok(())
+    }
+}
+
+/// A message payload with its envelope header
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Envelope {
+    pub header: EnvelopeHeader,
+    /// the message after compression and encryption
+    pub body: Vec<u8>,
+}
+
+/// check whether chunk data is a message envelope, rather than a legacy raw message
+pub fn is_envelope(data: &[u8]) -> bool {
+    data.starts_with(&MAGIC)
+        && data.len() >= PREFIX_LENGTH
+        && data[4] == VERSION
+        && EnvelopeFlags::from_bytes(data[8], data[9]).is_ok()
+        && ContentType::try_from(data[10]).is_ok()
+}
+
+impl Envelope {
+    /// serialize header and body; fails if the filename is too long
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        let filename = self.header.filename.as_deref().unwrap_or_default().as_bytes();
+        if filename.len() > MAX_FILENAME_LENGTH {
+            return Err(anyhow!(PngMeError::EnvelopeError::FilenameTooLong(filename.len())));
+        }
+        let body_length = u32::try_from(self.body.len())
+            .map_err(|_| PngMeError::EnvelopeError::InvalidHeader(format!("body of {} bytes is too long", self.body.len())))?;
+        let (major, minor, patch) = self.header.writer_version;
+        Ok(
+            MAGIC
+                .iter()
+                .chain([self.header.version, major, minor, patch].iter())
+                .chain([self.header.flags.to_byte(), self.header.flags.compression.to_byte()].iter())
+                .chain([self.header.content_type as u8, filename.len() as u8].iter())
+                .chain(filename.iter())
+                .chain(body_length.to_be_bytes().iter())
+                .chain(calculate_crc_ieee_checksum(&self.body).to_be_bytes().iter())
+                .chain(self.body.iter())
+                .copied()
+                .collect()
+        )
+    }
+
+    /// parse an envelope, checking body length and checksum
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        if !data.starts_with(&MAGIC) || data.len() < PREFIX_LENGTH {
+            return Err(anyhow!(PngMeError::EnvelopeError::InvalidHeader(String::from("missing envelope header"))));
+        }
+        let version = data[4];
+        if version != VERSION {
+            return Err(anyhow!(PngMeError::EnvelopeError::UnsupportedVersion(version)));
+        }
+        let writer_version = (data[5], data[6], data[7]);
+        let flags = EnvelopeFlags::from_bytes(data[8], data[9])?;
+        let content_type = ContentType::try_from(data[10])?;
+        let filename_length = data[11] as usize;
+
+        let rest = &data[PREFIX_LENGTH..];
+        if rest.len() < filename_length + 8 {
+            return Err(anyhow!(PngMeError::EnvelopeError::InvalidHeader(format!(
+                "expected at least {} bytes, found {}",
+                PREFIX_LENGTH + filename_length + 8,
+                data.len()
+            ))));
+        }
+        let (filename, rest) = rest.split_at(filename_length);
+        let filename = match filename_length {
+            0 => None,
+            _ => Some(
+                String::from_utf8(filename.to_vec())
+                    .map_err(|_| PngMeError::EnvelopeError::InvalidHeader(String::from("filename is not valid UTF-8")))?
+            ),
+        };
+        let body_length = u32::from_be_bytes([rest[0], rest[1], rest[2], rest[3]]);
+        let checksum = u32::from_be_bytes([rest[4], rest[5], rest[6], rest[7]]);
+        let body = &rest[8..];
+        if body.len() != body_length as usize {
+            return Err(anyhow!(PngMeError::EnvelopeError::LengthMismatch(body_length, body.len())));
+        }
+        if calculate_crc_ieee_checksum(body) != checksum {
+            return Err(anyhow!(PngMeError::EnvelopeError::ChecksumMismatch));
+        }
+
+        Ok(
+            Envelope {
+                header: EnvelopeHeader { version, writer_version, flags, content_type, filename },
+                body: body.to_vec(),
+            }
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn testing_envelope() -> Envelope {
+        let flags = EnvelopeFlags { compression: Compression::Zlib, ..Default::default() };
+        Envelope {
+            header: EnvelopeHeader::new(flags, ContentType::Text, Some(String::from("notes.txt"))),
+            body: b"not really compressed".to_vec(),
+        }
+    }
+
+    #[test]
+    fn test_envelope_roundtrip() {
+        let envelope = testing_envelope();
+        let bytes = envelope.to_bytes().unwrap();
+        assert!(is_envelope(&bytes));
+        assert_eq!(Envelope::parse(&bytes).unwrap(), envelope);
+        assert_eq!(envelope.header.writer_version.1, env!("CARGO_PKG_VERSION_MINOR").parse::<u8>().unwrap());
+    }
+
+    #[test]
+    fn test_envelope_without_filename() {
+        let envelope = Envelope {
+            header: EnvelopeHeader::new(EnvelopeFlags::default(), ContentType::Binary, None),
+            body: vec![0, 159, 146, 150],
+        };
+        assert_eq!(Envelope::parse(&envelope.to_bytes().unwrap()).unwrap(), envelope);
+    }
+
+    #[test]
+    fn test_legacy_payload_is_no_envelope() {
+        assert!(!is_envelope(b"I can has cheezburger?"));
+        assert!(!is_envelope(b"PMEV stands for nothing in particular"));
+        assert!(matches!(
+            Envelope::parse(b"I can has cheezburger?").unwrap_err().downcast_ref::<PngMeError::EnvelopeError>(),
+            Some(PngMeError::EnvelopeError::InvalidHeader(_))
+        ));
+    }
+
+    #[test]
+    fn test_corrupted_body() {
+        let mut bytes = testing_envelope().to_bytes().unwrap();
+        let last = bytes.len() - 1;
+        bytes[last] ^= 1;
+        assert!(matches!(
+            Envelope::parse(&bytes).unwrap_err().downcast_ref::<PngMeError::EnvelopeError>(),
+            Some(PngMeError::EnvelopeError::ChecksumMismatch)
+        ));
+        bytes.pop();
+        assert!(matches!(
+            Envelope::parse(&bytes).unwrap_err().downcast_ref::<PngMeError::EnvelopeError>(),
+            Some(PngMeError::EnvelopeError::LengthMismatch(21, 20))
+        ));
+    }
+
+    #[test]
+    fn test_invalid_header_fields() {
+        let bytes = testing_envelope().to_bytes().unwrap();
+        let with = |index: usize, value: u8| {
+            let mut bytes = bytes.clone();
+            bytes[index] = value;
+            Envelope::parse(&bytes)
+        };
+        assert!(matches!(
+            with(4, 2).unwrap_err().downcast_ref::<PngMeError::EnvelopeError>(),
+            Some(PngMeError::EnvelopeError::UnsupportedVersion(2))
+        ));
+        assert!(matches!(
+            with(8, 0x80).unwrap_err().downcast_ref::<PngMeError::EnvelopeError>(),
+            Some(PngMeError::EnvelopeError::UnsupportedFlags(0x80))
+        ));
+        assert!(matches!(
+            with(8, PASSPHRASE | RECIPIENTS).unwrap_err().downcast_ref::<PngMeError::EnvelopeError>(),
+            Some(PngMeError::EnvelopeError::UnsupportedFlags(_))
+        ));
+        assert!(matches!(
+            with(9, 42).unwrap_err().downcast_ref::<PngMeError::CompressionError>(),
+            Some(PngMeError::CompressionError::UnsupportedAlgorithm(42))
+        ));
+        assert!(matches!(
+            with(10, 7).unwrap_err().downcast_ref::<PngMeError::EnvelopeError>(),
+            Some(PngMeError::EnvelopeError::InvalidContentType(7))
+        ));
+        assert!(matches!(
+            with(11, 200).unwrap_err().downcast_ref::<PngMeError::EnvelopeError>(),
+            Some(PngMeError::EnvelopeError::InvalidHeader(_))
+        ));
+    }
+
+    #[test]
+    fn test_filename_too_long() {
+        let envelope = Envelope {
+            header: EnvelopeHeader::new(EnvelopeFlags::default(), ContentType::Text, Some("x".repeat(256))),
+            body: Vec::new(),
+        };
+        assert!(envelope.to_bytes().is_err());
+    }
+}
@@ -8,10 +8,18 @@ use std::fmt;
 // Handle Errors occuring while instantiating ChunkTypes
 #[derive(Debug)]
 pub enum ChunkTypeError {
-    // input bytes length smaller than the necessary 12 bytes for metadata
+    // input contains bytes other than ASCII letters
     InvalidBytes(String),
     //InvalidChunkType
     InvalidChunkType,
+    // chunk types are exactly 4 bytes long
+    InvalidLength(usize),
+    // third letter MUST be uppercase
+    ReservedBitInvalid(String),
+    // critical chunk types are unfit to carry messages
+    CriticalChunkType(String),
+    // public chunk types are reserved for chunks registered with the PNG spec
+    PublicChunkType(String),
 }
 
 impl error::Error for ChunkTypeError {}
@@ -23,7 +31,19 @@ impl fmt::Display for ChunkTypeError {
             ChunkTypeError::InvalidBytes(bytes) => {
                 write!(f, "Invalid input bytes provided: {}", bytes)
             },
-            ChunkTypeError::InvalidChunkType => write!(f, "Invalid chunk type")
+            ChunkTypeError::InvalidChunkType => write!(f, "Invalid chunk type"),
+            ChunkTypeError::InvalidLength(length) => {
+                write!(f, "Expected 4 bytes, received {} instead", length)
+            },
+            ChunkTypeError::ReservedBitInvalid(chunk_type) => {
+                write!(f, "Invalid chunk type {}: the third letter (reserved bit) MUST be uppercase", chunk_type)
+            },
+            ChunkTypeError::CriticalChunkType(chunk_type) => {
+                write!(f, "Refusing critical chunk type {}: viewers would be unable to decode the PNG (first letter MUST be lowercase, or use --force)", chunk_type)
+            },
+            ChunkTypeError::PublicChunkType(chunk_type) => {
+                write!(f, "Refusing public chunk type {}: reserved for registered chunks (second letter MUST be lowercase, or use --force)", chunk_type)
+            },
         }
     }
 }
@@ -31,12 +51,12 @@ impl fmt::Display for ChunkTypeError {
 // Handle Errors occuring while instantiating Chunks
 #[derive(Debug)]
 pub enum ChunkError {
-    // input bytes length smaller than the necessary 12 bytes for metadata
-    InputTooSmall(u32),
+    // input ends before the chunk does: offset of the chunk, bytes needed, bytes available
+    Truncated(usize, usize, usize),
+    // length field exceeds the 2^31-1 limit of the PNG spec: offset of the chunk, length
+    LengthTooLarge(usize, u32),
     // invalid crc for chunk
     InvalidCrc(u32,u32),
-    
-    // InvalidChunkLength(String),
 }
 
 impl error::Error for ChunkError {}
@@ -45,8 +65,19 @@ impl error::Error for ChunkError {}
 impl fmt::Display for ChunkError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
-            ChunkError::InputTooSmall(actual_length) => {
-                write!(f, "At least 12 bytes MUST be supplied to construct a chunk (was {})",actual_length)
+            ChunkError::Truncated(offset, expected, actual) => {
+                write!(
+                    f,
+                    "Truncated chunk at offset {}: expected {} bytes, but only {} are available",
+                    offset, expected, actual
+                )
+            },
+            ChunkError::LengthTooLarge(offset, length) => {
+                write!(
+                    f,
+                    "Chunk at offset {} declares a length of {} bytes, exceeding the maximum of 2^31-1",
+                    offset, length
+                )
             },
             ChunkError::InvalidCrc(expected, actual) => {
                 write!(
@@ -68,6 +99,8 @@ pub enum PNGError {
     TooSmall,
     // the input does not start with the necessary PNG header sequence
     InvalidSignature,
+    // chunk index beyond the number of chunks in the PNG
+    IndexOutOfBounds(usize, usize),
 }
 
 impl error::Error for PNGError {}
@@ -84,6 +117,9 @@ impl fmt::Display for PNGError {
             PNGError::InvalidSignature => {
                 write!(f, "Input begins with invalid set of bytes, mismatching necessary PNG signature header")
             }
+            PNGError::IndexOutOfBounds(index, length) => {
+                write!(f, "Chunk index {} is out of bounds for a PNG with {} chunks", index, length)
+            }
         }
     }
 }
@@ -113,4 +149,457 @@ impl fmt::Display for FsIoError {
             },
         }
     }
-}
\ No newline at end of file
+}
+// Handle Errors occuring while interpreting the IHDR image header chunk
+#[derive(Debug)]
+pub enum IhdrError {
+    // the PNG contains no chunks at all
+    MissingIhdr,
+    // the first chunk is of another type than IHDR
+    NotIhdr(String),
+    // IHDR data MUST be exactly 13 bytes long
+    InvalidLength(u32),
+    // width and height MUST be within 1..=2^31-1
+    InvalidDimensions(u32, u32),
+    // unknown color type value
+    InvalidColorType(u8),
+    // bit depth not allowed for the given color type
+    InvalidBitDepth(u8, u8),
+    // only compression method 0 (deflate) is defined
+    InvalidCompressionMethod(u8),
+    // only filter method 0 (adaptive filtering) is defined
+    InvalidFilterMethod(u8),
+    // only interlace methods 0 (none) and 1 (Adam7) are defined
+    InvalidInterlaceMethod(u8),
+}
+
+impl error::Error for IhdrError {}
+
+impl fmt::Display for IhdrError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            IhdrError::MissingIhdr => {
+                write!(f, "PNG contains no chunks, expected an IHDR chunk first")
+            },
+            IhdrError::NotIhdr(chunk_type) => {
+                write!(f, "First chunk MUST be IHDR, but found {}", chunk_type)
+            },
+            IhdrError::InvalidLength(length) => {
+                write!(f, "IHDR data MUST be 13 bytes long (was {})", length)
+            },
+            IhdrError::InvalidDimensions(width, height) => {
+                write!(f, "Invalid image dimensions {}x{}: width and height MUST be between 1 and 2^31-1", width, height)
+            },
+            IhdrError::InvalidColorType(color_type) => {
+                write!(f, "Invalid color type: {}", color_type)
+            },
+            IhdrError::InvalidBitDepth(bit_depth, color_type) => {
+                write!(f, "Bit depth {} is not allowed for color type {}", bit_depth, color_type)
+            },
+            IhdrError::InvalidCompressionMethod(method) => {
+                write!(f, "Invalid compression method: {}", method)
+            },
+            IhdrError::InvalidFilterMethod(method) => {
+                write!(f, "Invalid filter method: {}", method)
+            },
+            IhdrError::InvalidInterlaceMethod(method) => {
+                write!(f, "Invalid interlace method: {}", method)
+            },
+        }
+    }
+}
+
+// Handle Errors occuring while encrypting or decrypting message payloads
+#[derive(Debug)]
+pub enum CryptoError {
+    // payload does not start with the header of an encrypted pngme payload
+    NotEncrypted,
+    // header is incomplete or malformed
+    InvalidHeader(String),
+    // payload was written by an unknown version of the format
+    UnsupportedVersion(u8),
+    // key derivation parameters are invalid or exceed the supported limits
+    InvalidKdfParams(String),
+    // the cipher refused to encrypt the payload
+    EncryptionFailed,
+    // wrong passphrase, or header or ciphertext were tampered with
+    DecryptionFailed,
+    // malformed age recipient (public key)
+    InvalidRecipient(String),
+    // malformed or empty identity file
+    InvalidIdentity(String),
+    // payload is encrypted to recipients none of the given identities belong to
+    NoMatchingIdentity,
+    // a payload can be encrypted with either a passphrase or to recipients, not both
+    MixedPassphraseAndRecipients,
+}
+
+impl error::Error for CryptoError {}
+
+impl fmt::Display for CryptoError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CryptoError::NotEncrypted => {
+                write!(f, "Payload is not encrypted")
+            },
+            CryptoError::InvalidHeader(reason) => {
+                write!(f, "Invalid encryption header: {}", reason)
+            },
+            CryptoError::UnsupportedVersion(version) => {
+                write!(f, "Unsupported encryption format version: {}", version)
+            },
+            CryptoError::InvalidKdfParams(reason) => {
+                write!(f, "Invalid key derivation parameters: {}", reason)
+            },
+            CryptoError::EncryptionFailed => {
+                write!(f, "Unable to encrypt payload")
+            },
+            CryptoError::DecryptionFailed => {
+                write!(f, "Decryption failed: wrong passphrase, or the message was tampered with")
+            },
+            CryptoError::InvalidRecipient(reason) => {
+                write!(f, "Invalid recipient: {}", reason)
+            },
+            CryptoError::InvalidIdentity(reason) => {
+                write!(f, "Invalid identity file: {}", reason)
+            },
+            CryptoError::NoMatchingIdentity => {
+                write!(f, "Message is not encrypted to any of the given identities")
+            },
+            CryptoError::MixedPassphraseAndRecipients => {
+                write!(f, "Encrypt either with a passphrase or to recipients, not both")
+            },
+        }
+    }
+}
+
+// Handle Errors occuring while signing message payloads or reading key files
+#[derive(Debug)]
+pub enum SignatureError {
+    // malformed signing key or trusted public key
+    InvalidKey(String),
+}
+
+impl error::Error for SignatureError {}
+
+impl fmt::Display for SignatureError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SignatureError::InvalidKey(reason) => {
+                write!(f, "Invalid key: {}", reason)
+            },
+        }
+    }
+}
+
+// Handle Errors occuring while checking a PNG against its integrity seal
+#[derive(Debug)]
+pub enum SealError {
+    // the PNG carries no seal chunk
+    MissingSeal,
+    // only a single seal chunk is allowed
+    MultipleSeals,
+    // seal is unsigned, badly signed or malformed
+    InvalidSeal(String),
+    // seal was written by an unknown version of the format
+    UnsupportedVersion(u8),
+    // seal is untrusted or the image changed since sealing
+    NotIntact(String),
+}
+
+impl error::Error for SealError {}
+
+impl fmt::Display for SealError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SealError::MissingSeal => {
+                write!(f, "PNG is not sealed")
+            },
+            SealError::MultipleSeals => {
+                write!(f, "PNG contains more than one seal chunk")
+            },
+            SealError::InvalidSeal(reason) => {
+                write!(f, "Invalid seal: {}", reason)
+            },
+            SealError::UnsupportedVersion(version) => {
+                write!(f, "Unsupported seal format version: {}", version)
+            },
+            SealError::NotIntact(reason) => {
+                write!(f, "Seal is not intact: {}", reason)
+            },
+        }
+    }
+}
+
+// Handle Errors occuring while compressing or decompressing message payloads
+#[derive(Debug)]
+pub enum CompressionError {
+    // unknown compression algorithm, or one this build does not support
+    UnsupportedAlgorithm(u8),
+    // payload exceeds the size limit (in bytes)
+    TooLarge(usize),
+    // compressed data is broken
+    CorruptData(String),
+}
+
+impl error::Error for CompressionError {}
+
+impl fmt::Display for CompressionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CompressionError::UnsupportedAlgorithm(algorithm) => {
+                write!(f, "Unsupported compression algorithm: {} (zstd requires the `zstd` feature)", algorithm)
+            },
+            CompressionError::TooLarge(limit) => {
+                write!(f, "Payload exceeds the limit of {} bytes", limit)
+            },
+            CompressionError::CorruptData(reason) => {
+                write!(f, "Corrupt compressed data: {}", reason)
+            },
+        }
+    }
+}
+
+// Handle Errors occuring while reading message envelopes
+#[derive(Debug)]
+pub enum EnvelopeError {
+    // header is incomplete or malformed
+    InvalidHeader(String),
+    // envelope was written by an unknown version of the format
+    UnsupportedVersion(u8),
+    // unknown or contradicting flags
+    UnsupportedFlags(u8),
+    // unknown content type value
+    InvalidContentType(u8),
+    // filenames are limited to 255 bytes
+    FilenameTooLong(usize),
+    // body length recorded in the header vs. actual body length
+    LengthMismatch(u32, usize),
+    // body does not match the checksum in the header
+    ChecksumMismatch,
+}
+
+impl error::Error for EnvelopeError {}
+
+impl fmt::Display for EnvelopeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EnvelopeError::InvalidHeader(reason) => {
+                write!(f, "Invalid envelope header: {}", reason)
+            },
+            EnvelopeError::UnsupportedVersion(version) => {
+                write!(f, "Unsupported envelope format version: {}", version)
+            },
+            EnvelopeError::UnsupportedFlags(flags) => {
+                write!(f, "Unsupported envelope flags: {:#04x}", flags)
+            },
+            EnvelopeError::InvalidContentType(content_type) => {
+                write!(f, "Invalid envelope content type: {}", content_type)
+            },
+            EnvelopeError::FilenameTooLong(length) => {
+                write!(f, "Filename MUST NOT exceed 255 bytes (was {})", length)
+            },
+            EnvelopeError::LengthMismatch(expected, found) => {
+                write!(f, "Envelope body should be {} bytes long, found {}", expected, found)
+            },
+            EnvelopeError::ChecksumMismatch => {
+                write!(f, "Envelope body does not match its checksum")
+            },
+        }
+    }
+}
+
+// Handle Errors occuring while splitting messages into fragments or reassembling them
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FragmentError {
+    // fragment header is incomplete or malformed
+    InvalidHeader(String),
+    // fragment was written by an unknown version of the format
+    UnsupportedVersion(u8),
+    // maximum chunk length leaves no room for data next to the fragment header
+    ChunkLengthTooSmall(usize),
+    // fragments of a message disagree on the number of fragments: message id
+    InconsistentCount(u32),
+    // the same fragment occurs twice: message id & sequence number
+    Duplicate(u32, u32),
+    // fragments missing from a message: message id & sequence numbers
+    Missing(u32, Vec<u32>),
+}
+
+impl error::Error for FragmentError {}
+
+impl fmt::Display for FragmentError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FragmentError::InvalidHeader(reason) => {
+                write!(f, "Invalid fragment header: {}", reason)
+            },
+            FragmentError::UnsupportedVersion(version) => {
+                write!(f, "Unsupported fragment format version: {}", version)
+            },
+            FragmentError::ChunkLengthTooSmall(length) => {
+                write!(f, "Maximum chunk length of {} bytes is too small to split the message", length)
+            },
+            FragmentError::InconsistentCount(message_id) => {
+                write!(f, "Fragments of message {:08x} disagree on the number of fragments", message_id)
+            },
+            FragmentError::Duplicate(message_id, sequence) => {
+                write!(f, "Fragment {} of message {:08x} occurs more than once", sequence, message_id)
+            },
+            FragmentError::Missing(message_id, missing) => {
+                write!(f, "Message {:08x} is missing fragment(s) {:?}", message_id, missing)
+            },
+        }
+    }
+}
+
+// Handle Errors occuring while bundling files or reading bundles
+#[derive(Debug)]
+pub enum BundleError {
+    // bundle is truncated or malformed
+    InvalidBundle(String),
+    // bundle was written by an unknown version of the format
+    UnsupportedVersion(u8),
+    // contents of a file do not match its checksum: file name
+    ChecksumMismatch(String),
+    // file name is absolute, contains a directory part or refers to a parent directory
+    UnsafeName(String),
+    // two files share a name: file name
+    DuplicateName(String),
+}
+
+impl error::Error for BundleError {}
+
+impl fmt::Display for BundleError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BundleError::InvalidBundle(reason) => {
+                write!(f, "Invalid file bundle: {}", reason)
+            },
+            BundleError::UnsupportedVersion(version) => {
+                write!(f, "Unsupported bundle format version: {}", version)
+            },
+            BundleError::ChecksumMismatch(name) => {
+                write!(f, "Contents of {} do not match its checksum", name)
+            },
+            BundleError::UnsafeName(name) => {
+                write!(f, "Refusing unsafe file name {:?}: only plain file names can be extracted", name)
+            },
+            BundleError::DuplicateName(name) => {
+                write!(f, "More than one file is named {:?}", name)
+            },
+        }
+    }
+}
+
+// Handle Errors occuring while decoding the image data of IDAT chunks
+#[derive(Debug)]
+pub enum PixelError {
+    // the PNG contains no IDAT chunk
+    MissingImageData,
+    // the zlib stream of the IDAT chunks cannot be inflated
+    CorruptData(String),
+    // decoded image data would exceed the limit: limit in bytes
+    TooLarge(usize),
+    // inflated image data has the wrong size: expected & actual length
+    InvalidLength(usize, usize),
+    // unknown scanline filter type: filter type & row
+    InvalidFilterType(u8, usize),
+    // samples are too narrow or too wide for the bit depth
+    MismatchedSamples(u8),
+}
+
+impl error::Error for PixelError {}
+
+impl fmt::Display for PixelError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PixelError::MissingImageData => {
+                write!(f, "PNG contains no IDAT chunk")
+            },
+            PixelError::CorruptData(reason) => {
+                write!(f, "Corrupt image data: {}", reason)
+            },
+            PixelError::TooLarge(limit) => {
+                write!(f, "Image data exceeds the limit of {} bytes", limit)
+            },
+            PixelError::InvalidLength(expected, actual) => {
+                write!(f, "Image data has the wrong size: expected {} bytes, found {}", expected, actual)
+            },
+            PixelError::InvalidFilterType(filter_type, row) => {
+                write!(f, "Invalid filter type {} in scanline {}", filter_type, row)
+            },
+            PixelError::MismatchedSamples(bit_depth) => {
+                write!(f, "Samples do not match a bit depth of {}", bit_depth)
+            },
+        }
+    }
+}
+
+// Handle Errors occuring while hiding messages in, or extracting them from, the image samples
+#[derive(Debug)]
+pub enum LsbError {
+    // image cannot carry messages in its samples: reason
+    UnsupportedImage(String),
+    // only 1 or 2 bits per sample can be replaced
+    InvalidBitsPerSample(u8),
+    // message does not fit into the samples: needed & available bytes
+    CapacityExceeded(usize, usize),
+    // the samples hold no message
+    NoMessage,
+    // message was written by an unknown version of the format
+    UnsupportedVersion(u8),
+    // message header is malformed
+    InvalidHeader(String),
+    // scattering only applies to messages in the pixels
+    ScatterWithoutLsb,
+}
+
+impl error::Error for LsbError {}
+
+impl fmt::Display for LsbError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LsbError::UnsupportedImage(reason) => {
+                write!(f, "Image cannot carry a message in its pixels: {}", reason)
+            },
+            LsbError::InvalidBitsPerSample(bits) => {
+                write!(f, "Cannot replace {} bits per sample, only 1 or 2", bits)
+            },
+            LsbError::CapacityExceeded(needed, available) => {
+                write!(f, "Message of {} bytes does not fit into the pixels, which hold at most {} bytes", needed, available)
+            },
+            LsbError::NoMessage => {
+                write!(f, "No message found in the pixels")
+            },
+            LsbError::UnsupportedVersion(version) => {
+                write!(f, "Unsupported pixel message format version: {}", version)
+            },
+            LsbError::InvalidHeader(reason) => {
+                write!(f, "Invalid pixel message header: {}", reason)
+            },
+            LsbError::ScatterWithoutLsb => {
+                write!(f, "Scattering requires the message to be hidden in the pixels (--method lsb)")
+            },
+        }
+    }
+}
+
+// Handle Errors occuring while analyzing the pixel samples for hidden messages
+#[derive(Debug)]
+pub enum AnalysisError {
+    // no such channel: channel & number of channels
+    InvalidChannel(usize, usize),
+}
+
+impl error::Error for AnalysisError {}
+
+impl fmt::Display for AnalysisError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AnalysisError::InvalidChannel(channel, channels) => {
+                write!(f, "Channel {} does not exist, the image has {} channels", channel, channels)
+            },
+        }
+    }
+}
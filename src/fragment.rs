@@ -0,0 +1,295 @@
+use chacha20poly1305::aead::{rand_core::RngCore, OsRng};
+
+use anyhow::{anyhow, Result};
+
+use crate::chunk::Chunk;
+use crate::error as PngMeError;
+
+/*
+    Fragmentation of large message payloads across several chunks of the same type.
+
+    Every fragment chunk starts with a header, followed by its slice of the payload:
+
+        magic "PMFR"         4 bytes
+        version              1 byte
+        message id           4 bytes, random; ties the fragments of one message together
+        sequence number      4 bytes, big endian, counting from 0
+        fragment count       4 bytes, big endian
+
+    Fragmentation is the last step of encode: the complete (signed) payload is split, so signatures
+    and envelope checksums are verified on the reassembled payload. Payloads that fit into a single
+    chunk are stored without a fragment header.
+*/
+
+/// identifies message fragments
+pub const MAGIC: [u8; 4] = *b"PMFR";
+/// current version of the fragment format
+pub const VERSION: u8 = 1;
+/// magic, version, message id, sequence number and fragment count
+pub const HEADER_LENGTH: usize = 4 + 1 + 4 + 4 + 4;
+/// chunks carrying messages are kept at or below 1 MiB of data unless configured otherwise
+pub const DEFAULT_MAX_CHUNK_LENGTH: usize = 1 << 20;
+
+/// Position of a single fragment within its message
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FragmentHeader {
+    pub message_id: u32,
+    pub sequence: u32,
+    pub total: u32,
+}
+
+/// check whether chunk data is a fragment of a larger message;
+/// raw messages merely starting with the magic, without a header of this version, are not
+pub fn is_fragment(data: &[u8]) -> bool {
+    data.starts_with(&MAGIC) && data.len() >= HEADER_LENGTH && data[4] == VERSION
+}
+
+/// split a payload into chunk data of at most `max_chunk_length` bytes each, fragment header included;
+/// a payload that fits is returned as it is, without fragment header
+pub fn split(payload: &[u8], max_chunk_length: usize) -> Result<Vec<Vec<u8>>> {
+    if payload.len() <= max_chunk_length {
+        return Ok(vec![payload.to_vec()]);
+    }
+    if max_chunk_length <= HEADER_LENGTH {
+        return Err(anyhow!(PngMeError::FragmentError::ChunkLengthTooSmall(max_chunk_length)));
+    }
+    let fragments: Vec<&[u8]> = payload.chunks(max_chunk_length - HEADER_LENGTH).collect();
+    let total = u32::try_from(fragments.len())
+        .map_err(|_| PngMeError::FragmentError::ChunkLengthTooSmall(max_chunk_length))?;
+    let message_id = OsRng.next_u32();
+    Ok(
+        fragments
+            .iter()
+            .zip(0u32..)
+            .map(|(fragment, sequence)| {
+                MAGIC
+                    .iter()
+                    .chain([VERSION].iter())
+                    .chain(message_id.to_be_bytes().iter())
+                    .chain(sequence.to_be_bytes().iter())
+                    .chain(total.to_be_bytes().iter())
+                    .chain(fragment.iter())
+                    .copied()
+                    .collect()
+            })
+            .collect()
+    )
+}
+
+/// read the header of a fragment; returns it with the fragment's slice of the payload
+pub fn parse(data: &[u8]) -> Result<(FragmentHeader, &[u8])> {
+    Ok(parse_header(data)?)
+}
+
+fn parse_header(data: &[u8]) -> Result<(FragmentHeader, &[u8]), PngMeError::FragmentError> {
+    if !data.starts_with(&MAGIC) || data.len() < HEADER_LENGTH {
+        return Err(PngMeError::FragmentError::InvalidHeader(String::from("missing fragment header")));
+    }
+    if data[4] != VERSION {
+        return Err(PngMeError::FragmentError::UnsupportedVersion(data[4]));
+    }
+    let read_u32 = |start: usize| u32::from_be_bytes([data[start], data[start + 1], data[start + 2], data[start + 3]]);
+    let header = FragmentHeader {
+        message_id: read_u32(5),
+        sequence: read_u32(9),
+        total: read_u32(13),
+    };
+    if header.sequence >= header.total {
+        return Err(PngMeError::FragmentError::InvalidHeader(format!(
+            "fragment {} of a message with {} fragments",
+            header.sequence, header.total
+        )));
+    }
+    Ok((header, &data[HEADER_LENGTH..]))
+}
+
+/// the message id of a fragment; `None` for chunk data that is no valid fragment
+pub fn message_id(data: &[u8]) -> Option<u32> {
+    parse_header(data).ok().map(|(header, _)| header.message_id)
+}
+
+/// A message payload, reassembled from its fragments if it was split
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Reassembled {
+    /// indices of the chunks holding the message, in file order
+    pub chunk_indices: Vec<usize>,
+    /// the payload; for damaged messages, whatever the fragments found hold, in sequence order
+    pub payload: Vec<u8>,
+    /// the fragments were not stored in sequence order
+    pub reordered: bool,
+    /// why the chunks do not form a complete message; `None` if they do
+    pub damage: Option<PngMeError::FragmentError>,
+}
+
+impl Reassembled {
+    /// the message, if it is complete
+    pub fn complete(self) -> Result<Reassembled> {
+        match self.damage {
+            Some(damage) => Err(anyhow!(damage)),
+            None => Ok(self),
+        }
+    }
+}
+
+// put the fragments of one message back together, in sequence order
+fn reassemble(message_id: u32, fragments: &[(usize, FragmentHeader, &[u8])]) -> Reassembled {
+    let total = fragments[0].1.total;
+    let mut ordered: Vec<&(usize, FragmentHeader, &[u8])> = fragments.iter().collect();
+    ordered.sort_by_key(|(_, header, _)| header.sequence);
+    let missing: Vec<u32> = (0..total)
+        .filter(|sequence| !ordered.iter().any(|(_, header, _)| header.sequence == *sequence))
+        .collect();
+
+    let damage = if fragments.iter().any(|(_, header, _)| header.total != total) {
+        Some(PngMeError::FragmentError::InconsistentCount(message_id))
+    } else if let Some(pair) = ordered.windows(2).find(|pair| pair[0].1.sequence == pair[1].1.sequence) {
+        Some(PngMeError::FragmentError::Duplicate(message_id, pair[0].1.sequence))
+    } else if !missing.is_empty() {
+        Some(PngMeError::FragmentError::Missing(message_id, missing))
+    } else {
+        None
+    };
+    Reassembled {
+        chunk_indices: fragments.iter().map(|(index, _, _)| *index).collect(),
+        payload: ordered.iter().flat_map(|(_, _, data)| data.iter().copied()).collect(),
+        reordered: fragments.windows(2).any(|pair| pair[0].1.sequence > pair[1].1.sequence),
+        damage,
+    }
+}
+
+// a message while gathering chunks: complete, or the fragments found so far
+enum Gathered<'a> {
+    Single(Reassembled),
+    // chunk type, message id and fragments
+    Fragments(String, u32, Vec<(usize, FragmentHeader, &'a [u8])>),
+}
+
+/// gather the messages held by a sequence of chunks, reassembling fragmented ones;
+/// messages are returned in the order of their first chunk. Reordered fragments are put back in sequence;
+/// messages with missing, duplicated or inconsistent fragments, and fragments with a malformed header,
+/// are returned with the damage recorded, so one broken message does not hide the others.
+pub fn reassemble_messages<'a>(chunks: impl Iterator<Item = (usize, &'a Chunk)>) -> Vec<Reassembled> {
+    let mut messages: Vec<Gathered> = Vec::new();
+    for (index, chunk) in chunks {
+        let single = |damage| Gathered::Single(Reassembled { chunk_indices: vec![index], payload: chunk.data().clone(), reordered: false, damage });
+        if !is_fragment(chunk.data()) {
+            messages.push(single(None));
+            continue;
+        }
+        let (header, data) = match parse_header(chunk.data()) {
+            Ok(parsed) => parsed,
+            Err(err) => {
+                messages.push(single(Some(err)));
+                continue;
+            },
+        };
+        let chunk_type = chunk.chunk_type().to_string();
+        let known = messages.iter_mut().find_map(|message| match message {
+            Gathered::Fragments(known_type, id, fragments) if *known_type == chunk_type && *id == header.message_id => Some(fragments),
+            _ => None,
+        });
+        match known {
+            Some(fragments) => fragments.push((index, header, data)),
+            None => messages.push(Gathered::Fragments(chunk_type, header.message_id, vec![(index, header, data)])),
+        }
+    }
+
+    messages
+        .into_iter()
+        .map(|message| match message {
+            Gathered::Single(message) => message,
+            Gathered::Fragments(_, message_id, fragments) => reassemble(message_id, &fragments),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::chunk;
+
+    fn chunks(datas: Vec<Vec<u8>>) -> Vec<Chunk> {
+        datas.into_iter().map(|data| chunk("ruSt", data)).collect()
+    }
+
+    fn payload() -> Vec<u8> {
+        (0..100u8).collect()
+    }
+
+    #[test]
+    fn test_split_and_reassemble() {
+        let fragments = split(&payload(), HEADER_LENGTH + 30).unwrap();
+        assert_eq!(fragments.len(), 4);
+        assert!(fragments.iter().all(|fragment| fragment.len() <= HEADER_LENGTH + 30));
+        let (header, _) = parse(&fragments[3]).unwrap();
+        assert_eq!((header.sequence, header.total), (3, 4));
+
+        let chunks = chunks(fragments);
+        let messages = reassemble_messages(chunks.iter().enumerate());
+        assert_eq!(messages, vec![Reassembled { chunk_indices: vec![0, 1, 2, 3], payload: payload(), reordered: false, damage: None }]);
+    }
+
+    #[test]
+    fn test_small_payload_is_not_split() {
+        assert_eq!(split(&payload(), 100).unwrap(), vec![payload()]);
+        assert!(split(&payload(), HEADER_LENGTH).is_err());
+    }
+
+    #[test]
+    fn test_reordered_fragments() {
+        let mut fragments = split(&payload(), HEADER_LENGTH + 30).unwrap();
+        fragments.swap(0, 2);
+        let chunks = chunks(fragments);
+        let messages = reassemble_messages(chunks.iter().enumerate());
+        assert_eq!(messages[0].payload, payload());
+        assert!(messages[0].reordered);
+    }
+
+    #[test]
+    fn test_missing_fragment() {
+        let mut fragments = split(&payload(), HEADER_LENGTH + 30).unwrap();
+        fragments.remove(1);
+        let chunks = chunks(fragments);
+        let messages = reassemble_messages(chunks.iter().enumerate());
+        assert!(matches!(&messages[0].damage, Some(PngMeError::FragmentError::Missing(_, missing)) if *missing == vec![1]));
+        assert!(messages[0].clone().complete().is_err());
+    }
+
+    #[test]
+    fn test_duplicated_fragment() {
+        let mut fragments = split(&payload(), HEADER_LENGTH + 30).unwrap();
+        fragments.push(fragments[2].clone());
+        let chunks = chunks(fragments);
+        let messages = reassemble_messages(chunks.iter().enumerate());
+        assert!(matches!(messages[0].damage, Some(PngMeError::FragmentError::Duplicate(_, 2))));
+    }
+
+    #[test]
+    fn test_interleaved_messages() {
+        let first = split(&payload(), HEADER_LENGTH + 60).unwrap();
+        let second = split(b"second message, long enough to be split", HEADER_LENGTH + 20).unwrap();
+        let datas = vec![first[0].clone(), b"unfragmented".to_vec(), second[0].clone(), first[1].clone(), second[1].clone()];
+        let chunks = chunks(datas);
+        let messages = reassemble_messages(chunks.iter().enumerate());
+        let payloads: Vec<&[u8]> = messages.iter().map(|message| message.payload.as_slice()).collect();
+        assert_eq!(payloads, vec![&payload()[..], b"unfragmented", b"second message, long enough to be split"]);
+        assert_eq!(messages[0].chunk_indices, vec![0, 3]);
+    }
+
+    #[test]
+    fn test_damaged_message_does_not_hide_others() {
+        let mut fragments = split(&payload(), HEADER_LENGTH + 30).unwrap();
+        fragments.remove(0);
+        // fragment count 0
+        let mut malformed = fragments[0].clone();
+        malformed[13..17].copy_from_slice(&[0; 4]);
+        let datas = vec![malformed, b"intact".to_vec()].into_iter().chain(fragments).collect();
+        let chunks = chunks(datas);
+        let messages = reassemble_messages(chunks.iter().enumerate());
+        assert_eq!(messages.len(), 3);
+        assert!(matches!(messages[0].damage, Some(PngMeError::FragmentError::InvalidHeader(_))));
+        assert_eq!(messages[1].clone().complete().unwrap().payload, b"intact");
+        assert!(matches!(&messages[2].damage, Some(PngMeError::FragmentError::Missing(_, missing)) if *missing == vec![0]));
+        assert_eq!(messages[2].chunk_indices, vec![2, 3, 4]);
+    }
+}
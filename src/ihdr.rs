@@ -0,0 +1,296 @@
+use std::convert::TryFrom;
+use std::fmt;
+use std::str::FromStr;
+
+use anyhow::{anyhow, Result};
+
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::error as PngMeError;
+
+/// The five color types defined for PNG images
+/// Reference: http://www.libpng.org/pub/png/spec/1.2/PNG-Chunks.html#C.IHDR
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorType {
+    Grayscale,
+    Rgb,
+    Indexed,
+    GrayscaleAlpha,
+    Rgba,
+}
+
+impl ColorType {
+    /// the raw value stored in the IHDR chunk
+    pub fn value(&self) -> u8 {
+        match self {
+            ColorType::Grayscale => 0,
+            ColorType::Rgb => 2,
+            ColorType::Indexed => 3,
+            ColorType::GrayscaleAlpha => 4,
+            ColorType::Rgba => 6,
+        }
+    }
+
+    /// number of samples stored per pixel
+    pub fn channels(&self) -> u8 {
+        match self {
+            ColorType::Grayscale | ColorType::Indexed => 1,
+            ColorType::GrayscaleAlpha => 2,
+            ColorType::Rgb => 3,
+            ColorType::Rgba => 4,
+        }
+    }
+
+    /// bit depths the PNG spec allows in combination with this color type
+    pub fn allowed_bit_depths(&self) -> &'static [u8] {
+        match self {
+            ColorType::Grayscale => &[1, 2, 4, 8, 16],
+            ColorType::Indexed => &[1, 2, 4, 8],
+            ColorType::Rgb | ColorType::GrayscaleAlpha | ColorType::Rgba => &[8, 16],
+        }
+    }
+}
+
+impl TryFrom<u8> for ColorType {
+    type Error = anyhow::Error;
+
+    fn try_from(value: u8) -> Result<Self> {
+        match value {
+            0 => Ok(ColorType::Grayscale),
+            2 => Ok(ColorType::Rgb),
+            3 => Ok(ColorType::Indexed),
+            4 => Ok(ColorType::GrayscaleAlpha),
+            6 => Ok(ColorType::Rgba),
+            _ => Err(anyhow!(PngMeError::IhdrError::InvalidColorType(value))),
+        }
+    }
+}
+
+impl fmt::Display for ColorType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            ColorType::Grayscale => "Grayscale",
+            ColorType::Rgb => "RGB",
+            ColorType::Indexed => "Indexed",
+            ColorType::GrayscaleAlpha => "Grayscale + Alpha",
+            ColorType::Rgba => "RGBA",
+        };
+        write!(f, "{} ({})", name, self.value())
+    }
+}
+
+/// Typed view over the IHDR image header chunk, which MUST be the first chunk of every PNG
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Ihdr {
+    width: u32,
+    height: u32,
+    bit_depth: u8,
+    color_type: ColorType,
+    compression_method: u8,
+    filter_method: u8,
+    interlaced: bool,
+}
+
+impl Ihdr {
+    /// IHDR data is always exactly 13 bytes long
+    pub const LENGTH: u32 = 13;
+    /// width and height MUST NOT exceed 2^31-1
+    pub const MAX_DIMENSION: u32 = (1 << 31) - 1;
+
+    /// header of a new, non-interlaced image; fails for dimensions and bit depths the spec does not allow
+    pub fn new(width: u32, height: u32, bit_depth: u8, color_type: ColorType) -> Result<Ihdr> {
+        let ihdr = Ihdr {
+            width,
+            height,
+            bit_depth,
+            color_type,
+            compression_method: 0,
+            filter_method: 0,
+            interlaced: false,
+        };
+        Ihdr::try_from(&ihdr.to_chunk()?)
+    }
+
+    /// the IHDR chunk holding this header
+    pub fn to_chunk(&self) -> Result<Chunk> {
+        let data: Vec<u8> = self.width
+            .to_be_bytes()
+            .into_iter()
+            .chain(self.height.to_be_bytes())
+            .chain([self.bit_depth, self.color_type.value(), self.compression_method, self.filter_method, self.interlace_method()])
+            .collect();
+        Ok(Chunk::new(ChunkType::from_str("IHDR")?, data))
+    }
+
+    // getters
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn bit_depth(&self) -> u8 {
+        self.bit_depth
+    }
+
+    pub fn color_type(&self) -> ColorType {
+        self.color_type
+    }
+
+    pub fn compression_method(&self) -> u8 {
+        self.compression_method
+    }
+
+    pub fn filter_method(&self) -> u8 {
+        self.filter_method
+    }
+
+    pub fn is_interlaced(&self) -> bool {
+        self.interlaced
+    }
+
+    /// the raw interlace method value: 0 for none, 1 for Adam7
+    pub fn interlace_method(&self) -> u8 {
+        self.interlaced as u8
+    }
+}
+
+impl TryFrom<&Chunk> for Ihdr {
+    type Error = anyhow::Error;
+    /// interpret and validate an IHDR chunk
+    fn try_from(chunk: &Chunk) -> Result<Self> {
+        let chunk_type = chunk.chunk_type().to_string();
+        if chunk_type != "IHDR" {
+            return Err(anyhow!(PngMeError::IhdrError::NotIhdr(chunk_type)));
+        }
+        if chunk.length() != Ihdr::LENGTH {
+            return Err(anyhow!(PngMeError::IhdrError::InvalidLength(chunk.length())));
+        }
+
+        let data = chunk.data();
+        let width = u32::from_be_bytes(data[0..4].try_into()?);
+        let height = u32::from_be_bytes(data[4..8].try_into()?);
+        let (bit_depth, color_type, compression_method, filter_method, interlace_method) =
+            (data[8], data[9], data[10], data[11], data[12]);
+
+        if width == 0 || height == 0 || width > Ihdr::MAX_DIMENSION || height > Ihdr::MAX_DIMENSION {
+            return Err(anyhow!(PngMeError::IhdrError::InvalidDimensions(width, height)));
+        }
+        let color_type = ColorType::try_from(color_type)?;
+        if !color_type.allowed_bit_depths().contains(&bit_depth) {
+            return Err(anyhow!(PngMeError::IhdrError::InvalidBitDepth(bit_depth, color_type.value())));
+        }
+        if compression_method != 0 {
+            return Err(anyhow!(PngMeError::IhdrError::InvalidCompressionMethod(compression_method)));
+        }
+        if filter_method != 0 {
+            return Err(anyhow!(PngMeError::IhdrError::InvalidFilterMethod(filter_method)));
+        }
+        let interlaced = match interlace_method {
+            0 => false,
+            1 => true,
+            _ => return Err(anyhow!(PngMeError::IhdrError::InvalidInterlaceMethod(interlace_method))),
+        };
+
+        Ok(
+            Ihdr {
+                width,
+                height,
+                bit_depth,
+                color_type,
+                compression_method,
+                filter_method,
+                interlaced,
+            }
+        )
+    }
+}
+
+impl fmt::Display for Ihdr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "width: {}, height: {}, bit depth: {}, color type: {}, compression: {}, filter: {}, interlace: {}",
+            self.width,
+            self.height,
+            self.bit_depth,
+            self.color_type,
+            self.compression_method,
+            self.filter_method,
+            if self.interlaced { "Adam7" } else { "none" },
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::chunk;
+
+    fn ihdr_chunk(width: u32, height: u32, bit_depth: u8, color_type: u8, interlace: u8) -> Chunk {
+        let data: Vec<u8> = width
+            .to_be_bytes()
+            .iter()
+            .chain(height.to_be_bytes().iter())
+            .chain([bit_depth, color_type, 0, 0, interlace].iter())
+            .copied()
+            .collect();
+        chunk("IHDR", data)
+    }
+
+    #[test]
+    fn test_valid_ihdr() {
+        let ihdr = Ihdr::try_from(&ihdr_chunk(50, 40, 8, 6, 0)).unwrap();
+        assert_eq!(ihdr.width(), 50);
+        assert_eq!(ihdr.height(), 40);
+        assert_eq!(ihdr.bit_depth(), 8);
+        assert_eq!(ihdr.color_type(), ColorType::Rgba);
+        assert!(!ihdr.is_interlaced());
+    }
+
+    #[test]
+    fn test_interlaced_ihdr() {
+        let ihdr = Ihdr::try_from(&ihdr_chunk(1, 1, 1, 0, 1)).unwrap();
+        assert!(ihdr.is_interlaced());
+        assert_eq!(ihdr.interlace_method(), 1);
+    }
+
+    #[test]
+    fn test_invalid_bit_depth_for_color_type() {
+        assert!(Ihdr::try_from(&ihdr_chunk(1, 1, 4, 2, 0)).is_err());
+        assert!(Ihdr::try_from(&ihdr_chunk(1, 1, 16, 3, 0)).is_err());
+    }
+
+    #[test]
+    fn test_invalid_color_type() {
+        assert!(Ihdr::try_from(&ihdr_chunk(1, 1, 8, 5, 0)).is_err());
+    }
+
+    #[test]
+    fn test_invalid_dimensions() {
+        assert!(Ihdr::try_from(&ihdr_chunk(0, 1, 8, 6, 0)).is_err());
+        assert!(Ihdr::try_from(&ihdr_chunk(1, 1 << 31, 8, 6, 0)).is_err());
+    }
+
+    #[test]
+    fn test_invalid_interlace_method() {
+        assert!(Ihdr::try_from(&ihdr_chunk(1, 1, 8, 6, 2)).is_err());
+    }
+
+    #[test]
+    fn test_wrong_chunk_type() {
+        let chunk = chunk("RuSt", vec![0; 13]);
+        assert!(Ihdr::try_from(&chunk).is_err());
+    }
+
+    #[test]
+    fn test_new_round_trip() {
+        let ihdr = Ihdr::new(50, 40, 16, ColorType::Rgb).unwrap();
+        assert_eq!(Ihdr::try_from(&ihdr.to_chunk().unwrap()).unwrap(), ihdr);
+        assert_eq!(ihdr.to_chunk().unwrap().data(), ihdr_chunk(50, 40, 16, 2, 0).data());
+        assert!(Ihdr::new(50, 40, 4, ColorType::Rgb).is_err());
+    }
+}
@@ -0,0 +1,42 @@
+//! # pngme
+//!
+//! Hide secret messages inside valid PNG files.
+//!
+//! The library exposes the PNG building blocks ([`Png`], [`Chunk`], [`ChunkType`], [`Ihdr`]),
+//! the error categories raised while working with them, and the command logic
+//! behind the `pngme` binary as plain functions in [`commands`].
+//!
+//! The binary requires the `cli` feature, on by default; without it, the library builds without
+//! the command line dependencies.
+
+pub mod bundle;
+pub mod capacity;
+pub mod chunk;
+pub mod chunk_type;
+pub mod commands;
+pub mod compression;
+pub mod crypto;
+pub mod envelope;
+pub mod fragment;
+pub mod error;
+pub mod ihdr;
+pub mod lsb;
+pub mod pixels;
+pub mod png;
+pub mod recipients;
+pub mod scan;
+pub mod seal;
+pub mod signature;
+pub mod steganalysis;
+pub mod strip;
+pub mod validator;
+
+#[cfg(test)]
+mod test_utils;
+
+pub use chunk::Chunk;
+pub use chunk_type::ChunkType;
+pub use error::{AnalysisError, BundleError, ChunkError, ChunkTypeError, CompressionError, CryptoError, EnvelopeError, FragmentError, FsIoError, IhdrError, LsbError, PixelError, PNGError, SealError, SignatureError};
+pub use ihdr::{ColorType, Ihdr};
+pub use pixels::PixelBuffer;
+pub use png::{ChunkPosition, ChunkReader, Png};
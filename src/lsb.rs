@@ -0,0 +1,375 @@
+use std::collections::HashMap;
+
+use rand_chacha::ChaCha20Rng;
+use rand_chacha::rand_core::{RngCore, SeedableRng};
+
+use anyhow::{anyhow, Result};
+
+use crate::crypto::KdfParams;
+use crate::ihdr::{ColorType, Ihdr};
+use crate::pixels::PixelBuffer;
+use crate::error as PngMeError;
+
+/*
+    Messages hidden in the least significant bits of the image samples, instead of a chunk of their own.
+    They survive tools that strip ancillary chunks and do not show up in a chunk listing, but are lost
+    as soon as the pixels change, e.g. by lossy conversion or resizing.
+
+        magic "PMLS"         4 bytes
+        version              1 byte
+        bits per sample      1 byte: 1 or 2
+        payload length       4 bytes, big endian
+        payload              `payload length` bytes
+
+    The header always takes the lowest bit of the first 80 samples, so it can be read without knowing
+    the bits per sample; the payload takes the lowest 1 or 2 bits of the samples following it.
+    Bits are stored most significant first, samples are used in image order: row by row, pixel by pixel,
+    channel by channel, alpha included.
+
+    Only grayscale and truecolor images with 8 or 16 bits per sample can carry messages: changing palette
+    indices or samples of fewer bits alters the colors visibly.
+
+    Messages in image order are easy to find and read for anyone. Scattered messages take the samples
+    in an order only holders of a passphrase can reproduce instead: the passphrase is stretched with
+    Argon2id into the seed of a ChaCha20 generator, which draws the samples one by one without
+    repetition (a lazy Fisher-Yates shuffle). Header and payload are stored as above, in drawn order.
+    The salt is fixed and the image dimensions are mixed in, so the same passphrase scatters
+    differently across images of different sizes.
+*/
+
+/// identifies messages hidden in the samples
+pub const MAGIC: [u8; 4] = *b"PMLS";
+/// current version of the pixel message format
+pub const VERSION: u8 = 1;
+/// magic, version, bits per sample and payload length
+pub const HEADER_LENGTH: usize = 4 + 1 + 1 + 4;
+/// bits replaced per sample unless configured otherwise
+pub const DEFAULT_BITS_PER_SAMPLE: u8 = 1;
+/// at most this many bits per sample are replaced, beyond that the noise becomes visible
+pub const MAX_BITS_PER_SAMPLE: u8 = 2;
+/// chunk type under which messages hidden in the samples are signed and reported; never stored in a PNG
+pub const CHUNK_TYPE: &str = "lsBt";
+// salt of the scatter key derivation; distinct from anything `crypto` derives
+const SCATTER_SALT: &[u8] = b"pngme lsb scatter v1";
+
+/// Secret that determines which samples carry a scattered message
+#[derive(Clone, PartialEq, Eq)]
+pub struct ScatterKey([u8; 32]);
+
+impl ScatterKey {
+    /// derive the scatter key for an image from a passphrase
+    pub fn derive(passphrase: &str, ihdr: &Ihdr) -> Result<ScatterKey> {
+        let salt: Vec<u8> = SCATTER_SALT
+            .iter()
+            .chain(ihdr.width().to_be_bytes().iter())
+            .chain(ihdr.height().to_be_bytes().iter())
+            .copied()
+            .collect();
+        Ok(ScatterKey(KdfParams::default().derive_key(passphrase, &salt)?))
+    }
+}
+
+impl std::fmt::Debug for ScatterKey {
+    // never print the key itself
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "ScatterKey(..)")
+    }
+}
+
+// Sample positions in a key-dependent order, each drawn once: a Fisher-Yates shuffle, one step per position.
+// Only the slots a swap displaced are stored, any other slot still holds its own position,
+// so the memory needed grows with the positions drawn rather than with the image.
+struct ScatteredPositions {
+    rng: ChaCha20Rng,
+    sample_count: usize,
+    displaced: HashMap<usize, usize>,
+    drawn: usize,
+}
+
+impl ScatteredPositions {
+    fn new(key: &ScatterKey, sample_count: usize) -> Self {
+        ScatteredPositions { rng: ChaCha20Rng::from_seed(key.0), sample_count, displaced: HashMap::new(), drawn: 0 }
+    }
+
+    // uniform in 0..bound without modulo bias; spelled out, so the order never changes with a library update
+    fn below(&mut self, bound: u64) -> u64 {
+        let zone = u64::MAX - u64::MAX % bound;
+        loop {
+            let value = self.rng.next_u64();
+            if value < zone {
+                return value % bound;
+            }
+        }
+    }
+}
+
+impl Iterator for ScatteredPositions {
+    type Item = usize;
+
+    fn next(&mut self) -> Option<usize> {
+        let remaining = self.sample_count - self.drawn;
+        if remaining == 0 {
+            return None;
+        }
+        let chosen = self.drawn + self.below(remaining as u64) as usize;
+        // swap slots `drawn` and `chosen`; slot `drawn` is never looked at again
+        let current = self.displaced.remove(&self.drawn).unwrap_or(self.drawn);
+        let position = if chosen == self.drawn {
+            current
+        } else {
+            self.displaced.insert(chosen, current).unwrap_or(chosen)
+        };
+        self.drawn += 1;
+        Some(position)
+    }
+}
+
+/// check that an image can carry a message in its samples
+pub fn check_carrier(ihdr: &Ihdr) -> Result<()> {
+    if ihdr.color_type() == ColorType::Indexed {
+        return Err(anyhow!(PngMeError::LsbError::UnsupportedImage(String::from(
+            "changing palette indices alters the colors"
+        ))));
+    }
+    if ihdr.bit_depth() < 8 {
+        return Err(anyhow!(PngMeError::LsbError::UnsupportedImage(format!(
+            "{} bits per sample are too few to change unnoticed",
+            ihdr.bit_depth()
+        ))));
+    }
+    Ok(())
+}
+
+fn check_bits_per_sample(bits_per_sample: u8) -> Result<()> {
+    if !(1..=MAX_BITS_PER_SAMPLE).contains(&bits_per_sample) {
+        return Err(anyhow!(PngMeError::LsbError::InvalidBitsPerSample(bits_per_sample)));
+    }
+    Ok(())
+}
+
+/// the largest payload, in bytes, an image can carry with `bits_per_sample` bits per sample
+pub fn capacity(ihdr: &Ihdr, bits_per_sample: u8) -> Result<usize> {
+    check_carrier(ihdr)?;
+    check_bits_per_sample(bits_per_sample)?;
+    let samples = (ihdr.width() as usize)
+        .saturating_mul(ihdr.height() as usize)
+        .saturating_mul(ihdr.color_type().channels() as usize);
+    Ok(samples.saturating_sub(HEADER_LENGTH * 8).saturating_mul(bits_per_sample as usize) / 8)
+}
+
+// split bytes into groups of `bits_per_sample` bits, most significant first
+fn bit_groups(data: &[u8], bits_per_sample: u8) -> impl Iterator<Item = u16> + '_ {
+    let mask = (1u8 << bits_per_sample) - 1;
+    data.iter().flat_map(move |byte| {
+        (0..8 / bits_per_sample).rev().map(move |group| ((byte >> (group * bits_per_sample)) & mask) as u16)
+    })
+}
+
+// replace the low bits of the samples at `positions` with `data`; the caller ensures there are enough positions
+fn write_bits(pixels: &mut PixelBuffer, positions: &mut impl Iterator<Item = usize>, data: &[u8], bits_per_sample: u8) {
+    let mask = (1u16 << bits_per_sample) - 1;
+    let samples = pixels.samples_mut();
+    // groups first: zip takes no position once they run out
+    for (value, index) in bit_groups(data, bits_per_sample).zip(positions) {
+        samples.set(index, samples.get(index) & !mask | value);
+    }
+}
+
+// gather `length` bytes from the low bits of the samples at `positions`; the caller ensures there are enough positions
+fn read_bits(pixels: &PixelBuffer, positions: &mut impl Iterator<Item = usize>, length: usize, bits_per_sample: u8) -> Vec<u8> {
+    let mask = (1u16 << bits_per_sample) - 1;
+    let groups_per_byte = (8 / bits_per_sample) as usize;
+    let groups: Vec<u8> = positions
+        .take(length * groups_per_byte)
+        .map(|index| (pixels.samples().get(index) & mask) as u8)
+        .collect();
+    groups
+        .chunks(groups_per_byte)
+        .map(|byte| byte.iter().fold(0u8, |value, group| value << bits_per_sample | group))
+        .collect()
+}
+
+// hide a payload in the samples at `positions`, which must cover all samples
+fn embed_at(pixels: &mut PixelBuffer, positions: &mut impl Iterator<Item = usize>, payload: &[u8], bits_per_sample: u8) -> Result<()> {
+    let available = capacity(pixels.ihdr(), bits_per_sample)?.min(u32::MAX as usize);
+    if payload.len() > available {
+        return Err(anyhow!(PngMeError::LsbError::CapacityExceeded(payload.len(), available)));
+    }
+    let header: Vec<u8> = MAGIC
+        .iter()
+        .chain([VERSION, bits_per_sample].iter())
+        .chain((payload.len() as u32).to_be_bytes().iter())
+        .copied()
+        .collect();
+    write_bits(pixels, positions, &header, 1);
+    write_bits(pixels, positions, payload, bits_per_sample);
+    Ok(())
+}
+
+// extract a payload hidden in the samples at `positions`, which must cover all samples
+fn extract_at(pixels: &PixelBuffer, positions: &mut impl Iterator<Item = usize>) -> Result<Vec<u8>> {
+    check_carrier(pixels.ihdr())?;
+    if pixels.samples().len() < HEADER_LENGTH * 8 {
+        return Err(anyhow!(PngMeError::LsbError::NoMessage));
+    }
+    let header = read_bits(pixels, positions, HEADER_LENGTH, 1);
+    if header[..4] != MAGIC {
+        return Err(anyhow!(PngMeError::LsbError::NoMessage));
+    }
+    if header[4] != VERSION {
+        return Err(anyhow!(PngMeError::LsbError::UnsupportedVersion(header[4])));
+    }
+    let bits_per_sample = header[5];
+    check_bits_per_sample(bits_per_sample)
+        .map_err(|_| PngMeError::LsbError::InvalidHeader(format!("{} bits per sample", bits_per_sample)))?;
+    let length = u32::from_be_bytes([header[6], header[7], header[8], header[9]]) as usize;
+    let available = capacity(pixels.ihdr(), bits_per_sample)?;
+    if length > available {
+        return Err(anyhow!(PngMeError::LsbError::InvalidHeader(format!(
+            "payload of {} bytes exceeds the capacity of {} bytes",
+            length, available
+        ))));
+    }
+    Ok(read_bits(pixels, positions, length, bits_per_sample))
+}
+
+/// hide a payload in the lowest `bits_per_sample` bits of the samples, in image order
+pub fn embed(pixels: &mut PixelBuffer, payload: &[u8], bits_per_sample: u8) -> Result<()> {
+    let mut positions = 0..pixels.samples().len();
+    embed_at(pixels, &mut positions, payload, bits_per_sample)
+}
+
+/// extract a payload hidden with `embed`; fails with `LsbError::NoMessage` if there is none
+pub fn extract(pixels: &PixelBuffer) -> Result<Vec<u8>> {
+    let mut positions = 0..pixels.samples().len();
+    extract_at(pixels, &mut positions)
+}
+
+/// hide a payload in the lowest `bits_per_sample` bits of samples chosen by a scatter key
+pub fn embed_scattered(pixels: &mut PixelBuffer, payload: &[u8], bits_per_sample: u8, key: &ScatterKey) -> Result<()> {
+    let mut positions = ScatteredPositions::new(key, pixels.samples().len());
+    embed_at(pixels, &mut positions, payload, bits_per_sample)
+}
+
+/// extract a payload hidden with `embed_scattered`; without the right key,
+/// the samples read are noise and fail with `LsbError::NoMessage`
+pub fn extract_scattered(pixels: &PixelBuffer, key: &ScatterKey) -> Result<Vec<u8>> {
+    let mut positions = ScatteredPositions::new(key, pixels.samples().len());
+    extract_at(pixels, &mut positions)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::sample_pixels;
+
+    #[test]
+    fn test_embed_extract_roundtrip() {
+        for bits_per_sample in [1, 2] {
+            let mut pixels = sample_pixels();
+            let original = pixels.clone();
+            embed(&mut pixels, b"meet me at midnight", bits_per_sample).unwrap();
+            assert_eq!(extract(&pixels).unwrap(), b"meet me at midnight");
+            // only the lowest bits change
+            let mask = (1u16 << bits_per_sample) - 1;
+            assert!((0..pixels.samples().len()).all(|index| pixels.samples().get(index) & !mask == original.samples().get(index) & !mask));
+            assert_ne!(pixels, original);
+        }
+    }
+
+    #[test]
+    fn test_capacity() {
+        let ihdr = sample_pixels().ihdr().clone();
+        let samples = 727 * 684 * 4;
+        assert_eq!(capacity(&ihdr, 1).unwrap(), (samples - 80) / 8);
+        assert_eq!(capacity(&ihdr, 2).unwrap(), (samples - 80) * 2 / 8);
+        assert!(capacity(&ihdr, 3).is_err());
+
+        let mut pixels = sample_pixels();
+        let too_large = vec![0u8; capacity(&ihdr, 1).unwrap() + 1];
+        let err = embed(&mut pixels, &too_large, 1).unwrap_err().downcast::<PngMeError::LsbError>().unwrap();
+        assert!(matches!(err, PngMeError::LsbError::CapacityExceeded(needed, _) if needed == too_large.len()));
+        let full = vec![0xa5u8; capacity(&ihdr, 1).unwrap()];
+        embed(&mut pixels, &full, 1).unwrap();
+        assert_eq!(extract(&pixels).unwrap(), full);
+    }
+
+    #[test]
+    fn test_no_message() {
+        assert!(matches!(
+            extract(&sample_pixels()).unwrap_err().downcast_ref::<PngMeError::LsbError>(),
+            Some(PngMeError::LsbError::NoMessage)
+        ));
+    }
+
+    #[test]
+    fn test_invalid_bits_per_sample() {
+        let mut pixels = sample_pixels();
+        assert!(embed(&mut pixels, b"hi", 0).is_err());
+        assert!(embed(&mut pixels, b"hi", 3).is_err());
+    }
+
+    fn scatter_key(passphrase: &str) -> ScatterKey {
+        ScatterKey::derive(passphrase, sample_pixels().ihdr()).unwrap()
+    }
+
+    #[test]
+    fn test_scattered_positions_are_a_permutation() {
+        let mut positions: Vec<usize> = ScatteredPositions::new(&ScatterKey([7; 32]), 1000).collect();
+        assert_ne!(positions, (0..1000).collect::<Vec<usize>>());
+        positions.sort();
+        assert_eq!(positions, (0..1000).collect::<Vec<usize>>());
+        // the same key always draws the same order
+        let first: Vec<usize> = ScatteredPositions::new(&ScatterKey([7; 32]), 1000).take(50).collect();
+        let second: Vec<usize> = ScatteredPositions::new(&ScatterKey([7; 32]), 1000).take(50).collect();
+        assert_eq!(first, second);
+        // the order is part of the format, it must never change
+        assert_eq!(first[..8], [308, 830, 478, 430, 429, 420, 4, 970]);
+    }
+
+    #[test]
+    fn test_scattered_positions_are_sparse() {
+        // drawing a few positions of a huge image neither allocates nor walks all of them
+        let positions: Vec<usize> = ScatteredPositions::new(&ScatterKey([7; 32]), usize::MAX / 2).take(4).collect();
+        assert_eq!(positions.len(), 4);
+        assert!(positions.iter().all(|position| *position < usize::MAX / 2));
+    }
+
+    #[test]
+    fn test_embed_extract_scattered() {
+        let mut pixels = sample_pixels();
+        let key = scatter_key("open sesame");
+        embed_scattered(&mut pixels, b"scattered to the winds", 1, &key).unwrap();
+        assert_eq!(extract_scattered(&pixels, &scatter_key("open sesame")).unwrap(), b"scattered to the winds");
+
+        // the first samples, where an unkeyed message would start, are barely touched
+        let original = sample_pixels();
+        let changed = (0..HEADER_LENGTH * 8).filter(|index| pixels.samples().get(*index) != original.samples().get(*index)).count();
+        assert!(changed < 4, "{} of the first samples changed", changed);
+    }
+
+    #[test]
+    fn test_extract_scattered_without_key_yields_noise() {
+        let mut pixels = sample_pixels();
+        let payload = vec![0u8; 4096];
+        embed_scattered(&mut pixels, &payload, 1, &scatter_key("open sesame")).unwrap();
+
+        // neither reading in image order nor with another key finds the message
+        assert!(matches!(
+            extract(&pixels).unwrap_err().downcast_ref::<PngMeError::LsbError>(),
+            Some(PngMeError::LsbError::NoMessage)
+        ));
+        assert!(matches!(
+            extract_scattered(&pixels, &scatter_key("open sesam")).unwrap_err().downcast_ref::<PngMeError::LsbError>(),
+            Some(PngMeError::LsbError::NoMessage)
+        ));
+
+        // and the bits read with the wrong key look random instead of the all-zero payload
+        let length = HEADER_LENGTH + payload.len();
+        let mut positions = ScatteredPositions::new(&scatter_key("wrong"), pixels.samples().len());
+        let read = read_bits(&pixels, &mut positions, length, 1);
+        let ones: u32 = read[HEADER_LENGTH..].iter().map(|byte| byte.count_ones()).sum();
+        let bits = (payload.len() * 8) as f64;
+        assert!((ones as f64 / bits - 0.5).abs() < 0.05, "{} of {} bits set", ones, bits);
+    }
+}
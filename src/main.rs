@@ -1,14 +1,18 @@
 mod args;
-mod chunk;
-mod chunk_type;
-mod commands;
-mod png;
-mod error;
 
-use anyhow::Result;
-use args::Commands;
+use std::env;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::process;
 
-use clap::{builder::styling::Color, Parser};
+use anyhow::{anyhow, Result};
+use args::{Commands, PngMeArgs};
+use clap::Parser;
+
+use pngme::commands::{self, EmbedMethod, OutputFormat};
+use pngme::{FsIoError, LsbError};
+use pngme::capacity::Overhead;
+use pngme::lsb;
 
 // consideration as suggested by [**Jordan**](https://github.com/jrdngr):
 // use anyhow::{Context, Result,};  // may be used in future
@@ -16,6 +20,347 @@ use clap::{builder::styling::Color, Parser};
 fn main() -> Result<()>{
     // use the parse trait implemented by the Commands struct
     let args = Commands::parse();
-    commands::run(args.command)?;
+    run(args.command)?;
+    Ok(())
+}
+
+/// environment variable consulted for the passphrase before prompting, for non-interactive use
+const PASSPHRASE_VARIABLE: &str = "PNGME_PASSPHRASE";
+
+/// get the passphrase for encryption from the environment, or prompt for it without echoing;
+/// new passphrases are prompted for twice to catch typos
+fn read_passphrase(confirm: bool) -> Result<String> {
+    if let Ok(passphrase) = env::var(PASSPHRASE_VARIABLE) {
+        return Ok(passphrase);
+    }
+    let passphrase = rpassword::prompt_password("Passphrase: ")?;
+    if confirm && rpassword::prompt_password("Confirm passphrase: ")? != passphrase {
+        return Err(anyhow!("Passphrases do not match"));
+    }
+    Ok(passphrase)
+}
+
+/// the chunk type of a message: required for messages in chunks, optional for messages in the pixels
+fn message_chunk_type(chunk_type: Option<&str>, method: EmbedMethod) -> Result<&str> {
+    match (chunk_type, method) {
+        (Some(chunk_type), _) => Ok(chunk_type),
+        (None, EmbedMethod::Lsb) => Ok(lsb::CHUNK_TYPE),
+        (None, EmbedMethod::Chunk) => Err(anyhow!("A chunk type (--chunk-type) is required, unless --method lsb is used")),
+    }
+}
+
+/// scattering only applies to messages in the pixels, fail before asking for a passphrase
+fn check_scatter(scatter: bool, method: EmbedMethod) -> Result<()> {
+    if scatter && method != EmbedMethod::Lsb {
+        return Err(anyhow!(LsbError::ScatterWithoutLsb));
+    }
+    Ok(())
+}
+
+///Run the above program based on specified subcommand
+fn run(subcommand: PngMeArgs) -> Result<()> {
+    match subcommand {
+        PngMeArgs::Encode(args) => {
+            let payload = match (args.message, &args.message_file) {
+                (Some(message), _) => message.into_bytes(),
+                (None, Some(message_path)) => fs::read(message_path)
+                    .map_err(|err| FsIoError::UnableToReadFileError(err.to_string()))?,
+                (None, None) => {
+                    let mut payload = Vec::new();
+                    io::stdin().read_to_end(&mut payload)?;
+                    payload
+                },
+            };
+            let message = commands::decode_payload(&payload, args.input_encoding)?;
+            let filename = args.message_file
+                .as_deref()
+                .and_then(|message_path| message_path.file_name())
+                .map(|filename| filename.to_string_lossy().into_owned());
+            check_scatter(args.scatter, args.method)?;
+            // encryption and scattering share the one passphrase
+            let passphrase = if args.encrypt || args.scatter { Some(read_passphrase(true)?) } else { None };
+            let options = commands::EncodeOptions {
+                position: args.position,
+                force: args.force,
+                compression: args.compress,
+                passphrase: passphrase.clone().filter(|_| args.encrypt),
+                recipients: args.recipients,
+                sign_key: args.sign_key,
+                filename,
+                max_chunk_length: args.max_chunk_size,
+                method: args.method,
+                bits_per_sample: args.lsb_bits,
+                scatter_passphrase: passphrase.filter(|_| args.scatter),
+            };
+            commands::encode(
+                &args.input_path,
+                message_chunk_type(args.chunk_type.as_deref(), args.method)?,
+                &message,
+                &options,
+                args.output_file.as_deref(),
+            )?;
+        },
+        PngMeArgs::Decode(args) => {
+            check_scatter(args.scatter, args.method)?;
+            let passphrase = if args.decrypt || args.scatter { Some(read_passphrase(false)?) } else { None };
+            let options = commands::DecodeOptions {
+                multiple_chunks: args.multiple_chunks,
+                passphrase: passphrase.clone().filter(|_| args.decrypt),
+                identity_file: args.identity,
+                trusted_keys: args.trusted_keys,
+                max_decompressed_length: args.max_size,
+                method: args.method,
+                scatter_passphrase: passphrase.filter(|_| args.scatter),
+            };
+            let chunk_type = message_chunk_type(args.chunk_type.as_deref(), args.method)?;
+            let decoded = commands::decode(&args.input_path, chunk_type, &options)?;
+            match (&args.out, args.format) {
+                // payloads only: back to back for raw bytes, one per line otherwise
+                (Some(_), _) | (None, OutputFormat::Raw) => {
+                    let mut output: Vec<u8> = Vec::new();
+                    for message in &decoded {
+                        output.extend(commands::format_payload(&message.chunk, args.format)?);
+                        if args.format != OutputFormat::Raw {
+                            output.push(b'\n');
+                        }
+                    }
+                    match &args.out {
+                        Some(out_path) => fs::write(out_path, output)
+                            .map_err(|err| FsIoError::UnableToWriteToOutputFileError(err.to_string()))?,
+                        None => io::stdout().write_all(&output)?,
+                    }
+                },
+                (None, _) => {
+                    for message in &decoded {
+                        let payload = commands::format_payload(&message.chunk, args.format)?;
+                        match args.method {
+                            EmbedMethod::Chunk => println!("Chunk #{}: {}", message.index, message.chunk),
+                            EmbedMethod::Lsb => println!("Pixels: {}", message.chunk),
+                        }
+                        if args.method == EmbedMethod::Chunk && message.chunk_indices.len() > 1 {
+                            println!("Fragments: {} chunks {:?}{}", message.chunk_indices.len(), message.chunk_indices,
+                                if message.reordered { " (REORDERED, reassembled in sequence)" } else { "" });
+                        }
+                        println!("Signature: {}", message.signature);
+                        match &message.envelope {
+                            Some(header) => println!("Envelope: {}", header),
+                            None => println!("Envelope: none (raw message)"),
+                        }
+                        println!("Decodes as: {}", String::from_utf8_lossy(&payload));
+                    }
+                },
+            }
+        },
+        PngMeArgs::Remove(args) => {
+            let removed_chunks = commands::remove(
+                &args.input_path,
+                &args.chunk_type,
+                args.multiple_chunks,
+                args.output_file.as_deref(),
+            )?;
+            println!("Removed {} {} chunk(s)", removed_chunks.len(), args.chunk_type);
+        },
+        PngMeArgs::Strip(args) => {
+            let removed_chunks = commands::strip(&args.input_path, &args.keep, args.output_file.as_deref())?;
+            for chunk in &removed_chunks {
+                println!("Removed {} ({} bytes)", chunk.chunk_type(), chunk.length());
+            }
+            println!(
+                "Stripped {} chunk(s) from {}",
+                removed_chunks.len(),
+                args.output_file.as_ref().unwrap_or(&args.input_path).display()
+            );
+        },
+        PngMeArgs::Print(args) => {
+            // message chunks are flagged with their signature status
+            let (chunks, messages) = commands::list_chunks(&args.input_path, args.trusted_keys.as_deref())?;
+            for (index, chunk) in chunks.iter().enumerate() {
+                match messages.iter().find(|message| message.chunk_indices.contains(&index)) {
+                    Some(commands::DecodedMessage { damage: Some(damage), .. }) => println!("Chunk: {} [DAMAGED: {}]", chunk, damage),
+                    Some(message) => println!("Chunk: {} [{}]", chunk, message.signature),
+                    None => println!("Chunk: {}", chunk),
+                }
+            }
+        },
+        PngMeArgs::Info(args) => {
+            let info = commands::info(&args.input_path)?;
+            let header = &info.header;
+            println!("Dimensions:  {} x {}", header.width(), header.height());
+            println!("Bit depth:   {}", header.bit_depth());
+            println!("Color type:  {}", header.color_type());
+            println!("Compression: {}", header.compression_method());
+            println!("Filter:      {}", header.filter_method());
+            println!("Interlace:   {}", if header.is_interlaced() { "Adam7" } else { "none" });
+            println!("File size:   {} bytes", info.file_size);
+            println!("Image data:  {} bytes (compressed)", info.image_data_length);
+            println!("Chunks:      {}", info.chunk_count);
+            for (chunk_type, count) in &info.chunk_type_counts {
+                println!("  {}: {}", chunk_type, count);
+            }
+        },
+        PngMeArgs::Check(args) => {
+            let violations = commands::check(&args.input_path)?;
+            if violations.is_empty() {
+                println!("OK: {}", args.input_path.display());
+            } else {
+                for violation in &violations {
+                    println!("{}: {}", args.input_path.display(), violation);
+                }
+                println!("ERRORS DETECTED in {} ({} violations)", args.input_path.display(), violations.len());
+                process::exit(1);
+            }
+        },
+        PngMeArgs::Keygen(args) if args.signing => {
+            let keypair = commands::keygen_signing(args.output_file.as_deref())?;
+            match &args.output_file {
+                Some(_) => println!("Public key: {}", keypair.public_key),
+                None => print!("{}", keypair.to_key_file()),
+            }
+        },
+        PngMeArgs::Keygen(args) => {
+            let keypair = commands::keygen(args.output_file.as_deref())?;
+            match &args.output_file {
+                Some(_) => println!("Public key: {}", keypair.recipient),
+                None => print!("{}", keypair.to_identity_file()),
+            }
+        },
+        PngMeArgs::Verify(args) => {
+            let messages = commands::verify(&args.input_path, args.chunk_type.as_deref(), Some(&args.trusted_keys))?;
+            for message in &messages {
+                match &message.damage {
+                    Some(damage) => println!("Chunk #{} {}: DAMAGED: {}", message.index, message.chunk.chunk_type(), damage),
+                    None => println!("Chunk #{} {}: {}", message.index, message.chunk.chunk_type(), message.signature),
+                }
+            }
+            let failures = messages
+                .iter()
+                .filter(|message| message.damage.is_some() || !message.signature.is_trusted())
+                .count();
+            if messages.is_empty() {
+                println!("No message chunks found in {}", args.input_path.display());
+                process::exit(1);
+            } else if failures > 0 {
+                println!("VERIFICATION FAILED for {} of {} chunk(s) in {}", failures, messages.len(), args.input_path.display());
+                process::exit(1);
+            }
+            println!("OK: {}", args.input_path.display());
+        },
+        PngMeArgs::Attach(args) => {
+            let options = commands::EncodeOptions {
+                compression: args.compress,
+                passphrase: if args.encrypt { Some(read_passphrase(true)?) } else { None },
+                recipients: args.recipients,
+                sign_key: args.sign_key,
+                ..Default::default()
+            };
+            let entries = commands::attach(
+                &args.input_path,
+                &args.chunk_type,
+                &args.files,
+                &options,
+                args.output_file.as_deref(),
+            )?;
+            println!("Attached {} file(s)", entries.len());
+        },
+        PngMeArgs::Extract(args) => {
+            let options = commands::DecodeOptions {
+                passphrase: if args.decrypt { Some(read_passphrase(false)?) } else { None },
+                identity_file: args.identity,
+                trusted_keys: args.trusted_keys,
+                ..Default::default()
+            };
+            for path in commands::extract(&args.input_path, &args.chunk_type, &options, &args.dir, args.force)? {
+                println!("Extracted {}", path.display());
+            }
+        },
+        PngMeArgs::Ls(args) => {
+            let options = commands::DecodeOptions {
+                passphrase: if args.decrypt { Some(read_passphrase(false)?) } else { None },
+                identity_file: args.identity,
+                trusted_keys: args.trusted_keys,
+                ..Default::default()
+            };
+            let bundles = commands::ls(&args.input_path, &args.chunk_type, &options)?;
+            if bundles.is_empty() {
+                println!("No attached files found in {}", args.input_path.display());
+            }
+            for bundle in &bundles {
+                println!("Chunk #{} ({}):", bundle.index, bundle.signature);
+                for entry in &bundle.entries {
+                    println!("  {:04o} {:>10} {} {}", entry.permissions(), entry.size(), hex::encode(entry.checksum()), entry.name);
+                }
+            }
+        },
+        PngMeArgs::Capacity(args) => {
+            let overhead = Overhead {
+                passphrase: args.encrypt,
+                recipients: args.recipients,
+                signed: args.sign,
+                filename_length: args.filename.map_or(0, |filename| filename.len()),
+            };
+            for capacity in commands::capacity(&args.input_path, &overhead)? {
+                let note = if capacity.carrier.is_supported() { "" } else { " (not supported by encode)" };
+                match (capacity.message, capacity.raw) {
+                    (Some(message), Some(raw)) => println!("{}: {} bytes (raw {} bytes){}", capacity.carrier, message, raw, note),
+                    _ => println!("{}: unlimited{}", capacity.carrier, note),
+                }
+            }
+        },
+        PngMeArgs::Scan(args) => {
+            let scanned = commands::scan(&args.input_path)?;
+            let mut flagged = 0;
+            for file in &scanned {
+                match &file.report {
+                    Ok(report) if report.score() >= args.min_score => {
+                        flagged += 1;
+                        println!("{}: score {}", file.path.display(), report.score());
+                        for finding in &report.findings {
+                            println!("  {}", finding);
+                        }
+                    },
+                    Ok(_) => {},
+                    Err(err) => eprintln!("{}: {}", file.path.display(), err),
+                }
+            }
+            println!("Scanned {} file(s), {} flagged", scanned.len(), flagged);
+        },
+        PngMeArgs::Analyze(args) => {
+            let analyses = commands::analyze(&args.input_path, args.lsb_plane.as_deref(), args.channel)?;
+            println!("Channel  Chi-square  RS estimate  LSB entropy  Score");
+            for analysis in &analyses {
+                println!(
+                    "{:<7}  {:>10.3}  {:>11.3}  {:>11.3}  {:>5}",
+                    analysis.name, analysis.chi_square, analysis.rs, analysis.lsb_entropy, analysis.score()
+                );
+            }
+            if let Some(lsb_plane) = &args.lsb_plane {
+                println!("LSB plane written to {}", lsb_plane.display());
+            }
+        },
+        PngMeArgs::Seal(args) => {
+            commands::seal(&args.input_path, &args.sign_key, args.output_file.as_deref())?;
+            println!("Sealed {}", args.output_file.as_ref().unwrap_or(&args.input_path).display());
+        },
+        PngMeArgs::Unseal(args) => {
+            let report = if args.verify {
+                commands::verify_seal(&args.input_path, args.trusted_keys.as_deref())?
+            } else {
+                commands::unseal(&args.input_path, args.trusted_keys.as_deref(), args.force, args.output_file.as_deref())?
+            };
+            println!("Seal: {}", report.signature);
+            for change in &report.changes {
+                println!("{}: {}", args.input_path.display(), change);
+            }
+            if !report.changes.is_empty() {
+                println!("CHANGED SINCE SEALING: {} ({} changes)", args.input_path.display(), report.changes.len());
+            }
+            if args.verify {
+                if !report.is_intact() {
+                    process::exit(1);
+                }
+                println!("OK: {}", args.input_path.display());
+            }
+        },
+    }
     Ok(())
 }
@@ -0,0 +1,680 @@
+use std::io::{Read, Write};
+use std::str::FromStr;
+
+use flate2::{read::ZlibDecoder, write::ZlibEncoder};
+
+use anyhow::{anyhow, Result};
+
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::ihdr::{ColorType, Ihdr};
+use crate::png::Png;
+use crate::error as PngMeError;
+
+/*
+    Decoding of the image data stored in IDAT chunks.
+    Reference: http://www.libpng.org/pub/png/spec/1.2/PNG-Filters.html
+
+    All IDAT chunks together hold a single zlib stream. Inflated, it is a sequence of scanlines,
+    each made of a filter type byte and the filtered bytes of one row of pixels. Samples narrower
+    than a byte are packed most significant bits first, 16-bit samples are stored big endian.
+    Adam7 interlaced images hold seven reduced images (passes) one after another, each with its
+    own scanlines; decoding scatters them back into the full image.
+
+    Decoded samples are stored row by row, pixel by pixel, channel by channel, one value per sample
+    whatever the bit depth. Indexed images keep their palette indices.
+
+    Encoding reverses every step, keeping the interlace method of the image. Each scanline gets
+    the filter type with the smallest sum of absolute differences, as suggested by the spec;
+    indexed images and bit depths below 8 are never filtered.
+*/
+
+/// default upper bound for inflated image data and decoded samples: 512 MiB
+pub const DEFAULT_MAX_IMAGE_DATA_LENGTH: usize = 512 << 20;
+/// IDAT chunks written by `write_image_data` hold at most this many bytes: 64 KiB
+pub const MAX_IDAT_LENGTH: usize = 1 << 16;
+/// Adam7 passes: first column, first row, column step, row step
+pub const ADAM7_PASSES: [(usize, usize, usize, usize); 7] = [
+    (0, 0, 8, 8),
+    (4, 0, 8, 8),
+    (0, 4, 4, 8),
+    (2, 0, 4, 4),
+    (0, 2, 2, 4),
+    (1, 0, 2, 2),
+    (0, 1, 1, 2),
+];
+
+/// The five scanline filter types of filter method 0
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FilterType {
+    None = 0,
+    Sub = 1,
+    Up = 2,
+    Average = 3,
+    Paeth = 4,
+}
+
+impl FilterType {
+    /// the filter type for the byte in front of a scanline, if it is a known one
+    pub fn from_value(value: u8) -> Option<FilterType> {
+        match value {
+            0 => Some(FilterType::None),
+            1 => Some(FilterType::Sub),
+            2 => Some(FilterType::Up),
+            3 => Some(FilterType::Average),
+            4 => Some(FilterType::Paeth),
+            _ => None,
+        }
+    }
+}
+
+/// The samples of a decoded image: bit depths up to 8 take a byte per sample, 16-bit samples two
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Samples {
+    Eight(Vec<u8>),
+    Sixteen(Vec<u16>),
+}
+
+impl Samples {
+    fn zeroed(bit_depth: u8, length: usize) -> Samples {
+        match bit_depth {
+            16 => Samples::Sixteen(vec![0; length]),
+            _ => Samples::Eight(vec![0; length]),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        match self {
+            Samples::Eight(samples) => samples.len(),
+            Samples::Sixteen(samples) => samples.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// the sample at an index, widened to 16 bits
+    pub fn get(&self, index: usize) -> u16 {
+        match self {
+            Samples::Eight(samples) => samples[index] as u16,
+            Samples::Sixteen(samples) => samples[index],
+        }
+    }
+
+    /// replace the sample at an index; values are truncated to the width of the samples
+    pub fn set(&mut self, index: usize, value: u16) {
+        match self {
+            Samples::Eight(samples) => samples[index] = value as u8,
+            Samples::Sixteen(samples) => samples[index] = value,
+        }
+    }
+}
+
+/// The decoded pixels of a PNG, together with the image header describing them
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PixelBuffer {
+    ihdr: Ihdr,
+    samples: Samples,
+}
+
+impl PixelBuffer {
+    /// pixels of an image from its header and samples, in row-major order
+    pub fn new(ihdr: Ihdr, samples: Samples) -> Result<PixelBuffer> {
+        let expected = (ihdr.width() as usize)
+            .saturating_mul(ihdr.height() as usize)
+            .saturating_mul(ihdr.color_type().channels() as usize);
+        if samples.len() != expected {
+            return Err(anyhow!(PngMeError::PixelError::InvalidLength(expected, samples.len())));
+        }
+        if matches!(samples, Samples::Sixteen(_)) != (ihdr.bit_depth() == 16) {
+            return Err(anyhow!(PngMeError::PixelError::MismatchedSamples(ihdr.bit_depth())));
+        }
+        Ok(PixelBuffer { ihdr, samples })
+    }
+
+    pub fn ihdr(&self) -> &Ihdr {
+        &self.ihdr
+    }
+
+    pub fn width(&self) -> u32 {
+        self.ihdr.width()
+    }
+
+    pub fn height(&self) -> u32 {
+        self.ihdr.height()
+    }
+
+    pub fn bit_depth(&self) -> u8 {
+        self.ihdr.bit_depth()
+    }
+
+    pub fn color_type(&self) -> ColorType {
+        self.ihdr.color_type()
+    }
+
+    /// number of samples per pixel
+    pub fn channels(&self) -> usize {
+        self.ihdr.color_type().channels() as usize
+    }
+
+    pub fn samples(&self) -> &Samples {
+        &self.samples
+    }
+
+    pub fn samples_mut(&mut self) -> &mut Samples {
+        &mut self.samples
+    }
+
+    /// index into `samples` of a channel of the pixel at column `x` and row `y`
+    pub fn sample_index(&self, x: u32, y: u32, channel: usize) -> usize {
+        (y as usize * self.width() as usize + x as usize) * self.channels() + channel
+    }
+
+    /// a channel of the pixel at column `x` and row `y`
+    pub fn sample(&self, x: u32, y: u32, channel: usize) -> u16 {
+        self.samples.get(self.sample_index(x, y, channel))
+    }
+}
+
+// A reduced image of an interlaced PNG, or the full image of a non-interlaced one
+#[derive(Debug, Clone, Copy)]
+struct Pass {
+    x0: usize,
+    y0: usize,
+    dx: usize,
+    dy: usize,
+    width: usize,
+    height: usize,
+}
+
+// the reduced images stored in the image data, in order; empty passes included
+fn passes(ihdr: &Ihdr) -> Vec<Pass> {
+    let (width, height) = (ihdr.width() as usize, ihdr.height() as usize);
+    if !ihdr.is_interlaced() {
+        return vec![Pass { x0: 0, y0: 0, dx: 1, dy: 1, width, height }];
+    }
+    ADAM7_PASSES
+        .iter()
+        .map(|&(x0, y0, dx, dy)| Pass {
+            x0,
+            y0,
+            dx,
+            dy,
+            width: width.saturating_sub(x0).div_ceil(dx),
+            height: height.saturating_sub(y0).div_ceil(dy),
+        })
+        .collect()
+}
+
+// bytes per scanline of an image `width` pixels wide, filter type byte excluded
+fn stride(ihdr: &Ihdr, width: usize) -> Option<usize> {
+    let bits_per_pixel = ihdr.color_type().channels() as usize * ihdr.bit_depth() as usize;
+    Some(width.checked_mul(bits_per_pixel)?.div_ceil(8))
+}
+
+/// distance in bytes between a byte and the corresponding byte of the previous pixel, at least 1
+pub fn bytes_per_pixel(ihdr: &Ihdr) -> usize {
+    (ihdr.color_type().channels() as usize * ihdr.bit_depth() as usize).div_ceil(8)
+}
+
+/// length of the inflated image data of an image described by `ihdr`, filter type bytes included;
+/// `None` if it does not fit into memory
+pub fn image_data_length(ihdr: &Ihdr) -> Option<usize> {
+    passes(ihdr)
+        .iter()
+        .filter(|pass| pass.width > 0 && pass.height > 0)
+        .try_fold(0usize, |length, pass| {
+            length.checked_add(stride(ihdr, pass.width)?.checked_add(1)?.checked_mul(pass.height)?)
+        })
+}
+
+/// the zlib stream of the image data: the data of all IDAT chunks, concatenated
+pub fn idat_data(png: &Png) -> Result<Vec<u8>> {
+    let data: Vec<u8> = png
+        .chunks_by_type("IDAT")
+        .flat_map(|(_, chunk)| chunk.data().iter().copied())
+        .collect();
+    if png.chunk_by_type("IDAT").is_none() {
+        return Err(anyhow!(PngMeError::PixelError::MissingImageData));
+    }
+    Ok(data)
+}
+
+/// inflate the image data of a PNG into filtered scanlines, refusing to produce more than `max_length` bytes
+pub fn inflate(png: &Png, max_length: usize) -> Result<Vec<u8>> {
+    let ihdr = png.ihdr()?;
+    let expected = image_data_length(&ihdr)
+        .filter(|length| *length <= max_length)
+        .ok_or(PngMeError::PixelError::TooLarge(max_length))?;
+    let data = idat_data(png)?;
+
+    let mut inflated = Vec::with_capacity(expected);
+    ZlibDecoder::new(data.as_slice())
+        .take(expected as u64 + 1)
+        .read_to_end(&mut inflated)
+        .map_err(|err| PngMeError::PixelError::CorruptData(err.to_string()))?;
+    if inflated.len() != expected {
+        return Err(anyhow!(PngMeError::PixelError::InvalidLength(expected, inflated.len())));
+    }
+    Ok(inflated)
+}
+
+// the Paeth predictor: whichever of left, above and upper left is closest to left + above - upper left
+fn paeth(left: u8, above: u8, upper_left: u8) -> u8 {
+    let estimate = left as i16 + above as i16 - upper_left as i16;
+    let (to_left, to_above, to_upper_left) = (
+        (estimate - left as i16).abs(),
+        (estimate - above as i16).abs(),
+        (estimate - upper_left as i16).abs(),
+    );
+    if to_left <= to_above && to_left <= to_upper_left {
+        left
+    } else if to_above <= to_upper_left {
+        above
+    } else {
+        upper_left
+    }
+}
+
+// reverse the filters of consecutive scanlines in place; `first_row` only numbers the scanlines in errors
+fn unfilter(scanlines: &mut [u8], stride: usize, bytes_per_pixel: usize, first_row: usize) -> Result<()> {
+    let mut previous = vec![0u8; stride];
+    for (row, scanline) in scanlines.chunks_exact_mut(stride + 1).enumerate() {
+        let (filter_type, line) = scanline.split_at_mut(1);
+        let filter_type = FilterType::from_value(filter_type[0])
+            .ok_or(PngMeError::PixelError::InvalidFilterType(filter_type[0], first_row + row))?;
+        for i in 0..stride {
+            let left = if i >= bytes_per_pixel { line[i - bytes_per_pixel] } else { 0 };
+            let upper_left = if i >= bytes_per_pixel { previous[i - bytes_per_pixel] } else { 0 };
+            let above = previous[i];
+            let prediction = match filter_type {
+                FilterType::None => 0,
+                FilterType::Sub => left,
+                FilterType::Up => above,
+                FilterType::Average => ((left as u16 + above as u16) / 2) as u8,
+                FilterType::Paeth => paeth(left, above, upper_left),
+            };
+            line[i] = line[i].wrapping_add(prediction);
+        }
+        previous.copy_from_slice(line);
+    }
+    Ok(())
+}
+
+// the `index`th sample of an unfiltered scanline
+fn unpack_sample(line: &[u8], bit_depth: u8, index: usize) -> u16 {
+    match bit_depth {
+        16 => u16::from_be_bytes([line[2 * index], line[2 * index + 1]]),
+        8 => line[index] as u16,
+        _ => {
+            let bit = index * bit_depth as usize;
+            let shift = 8 - bit_depth as usize - bit % 8;
+            ((line[bit / 8] >> shift) & ((1u8 << bit_depth) - 1)) as u16
+        },
+    }
+}
+
+/// filter an unfiltered scanline given the previous one (all zeros for the first),
+/// returning it with the filter type byte in front
+pub fn filter(filter_type: FilterType, line: &[u8], previous: &[u8], bytes_per_pixel: usize) -> Vec<u8> {
+    let mut filtered = Vec::with_capacity(line.len() + 1);
+    filtered.push(filter_type as u8);
+    for i in 0..line.len() {
+        let left = if i >= bytes_per_pixel { line[i - bytes_per_pixel] } else { 0 };
+        let upper_left = if i >= bytes_per_pixel { previous[i - bytes_per_pixel] } else { 0 };
+        let above = previous[i];
+        let prediction = match filter_type {
+            FilterType::None => 0,
+            FilterType::Sub => left,
+            FilterType::Up => above,
+            FilterType::Average => ((left as u16 + above as u16) / 2) as u8,
+            FilterType::Paeth => paeth(left, above, upper_left),
+        };
+        filtered.push(line[i].wrapping_sub(prediction));
+    }
+    filtered
+}
+
+// filter a scanline with whichever filter type leaves the smallest sum of absolute differences
+fn filter_adaptive(line: &[u8], previous: &[u8], bytes_per_pixel: usize) -> Vec<u8> {
+    [FilterType::None, FilterType::Sub, FilterType::Up, FilterType::Average, FilterType::Paeth]
+        .iter()
+        .map(|filter_type| filter(*filter_type, line, previous, bytes_per_pixel))
+        .min_by_key(|filtered| filtered[1..].iter().map(|byte| (*byte as i8).unsigned_abs() as u64).sum::<u64>())
+        .unwrap_or_default()
+}
+
+// store the `index`th sample of a scanline; inverse of `unpack_sample`
+fn pack_sample(line: &mut [u8], bit_depth: u8, index: usize, value: u16) {
+    match bit_depth {
+        16 => line[2 * index..2 * index + 2].copy_from_slice(&value.to_be_bytes()),
+        8 => line[index] = value as u8,
+        _ => {
+            let bit = index * bit_depth as usize;
+            let shift = 8 - bit_depth as usize - bit % 8;
+            let mask = ((1u8 << bit_depth) - 1) << shift;
+            line[bit / 8] = line[bit / 8] & !mask | ((value as u8) << shift) & mask;
+        },
+    }
+}
+
+/// filter and compress the samples of a pixel buffer into a zlib stream of image data
+pub fn encode(buffer: &PixelBuffer) -> Result<Vec<u8>> {
+    let ihdr = &buffer.ihdr;
+    let (width, channels, bit_depth) = (ihdr.width() as usize, buffer.channels(), ihdr.bit_depth());
+    let adaptive = bit_depth >= 8 && ihdr.color_type() != ColorType::Indexed;
+    let bytes_per_pixel = bytes_per_pixel(ihdr);
+    let mut encoder = ZlibEncoder::new(Vec::new(), flate2::Compression::best());
+    for pass in passes(ihdr).into_iter().filter(|pass| pass.width > 0 && pass.height > 0) {
+        let stride = stride(ihdr, pass.width).ok_or(PngMeError::PixelError::TooLarge(usize::MAX))?;
+        let mut previous = vec![0u8; stride];
+        for row in 0..pass.height {
+            let y = pass.y0 + row * pass.dy;
+            let mut line = vec![0u8; stride];
+            for index in 0..pass.width * channels {
+                let x = pass.x0 + index / channels * pass.dx;
+                pack_sample(&mut line, bit_depth, index, buffer.samples.get((y * width + x) * channels + index % channels));
+            }
+            let filtered = if adaptive {
+                filter_adaptive(&line, &previous, bytes_per_pixel)
+            } else {
+                filter(FilterType::None, &line, &previous, bytes_per_pixel)
+            };
+            encoder.write_all(&filtered)?;
+            previous = line;
+        }
+    }
+    Ok(encoder.finish()?)
+}
+
+/// replace the IDAT chunks of a PNG with the encoded samples of a pixel buffer, at the position of the first one;
+/// returns the new IDAT chunks
+pub fn write_image_data(png: &mut Png, buffer: &PixelBuffer) -> Result<Vec<Chunk>> {
+    let index = png
+        .chunks_by_type("IDAT")
+        .map(|(index, _)| index)
+        .next()
+        .ok_or(PngMeError::PixelError::MissingImageData)?;
+    let chunks = idat_chunks(buffer)?;
+    png.remove_chunks("IDAT")?;
+    for (offset, chunk) in chunks.iter().enumerate() {
+        png.insert_chunk(index + offset, chunk.clone())?;
+    }
+    Ok(chunks)
+}
+
+// the encoded samples of a pixel buffer, split into IDAT chunks
+fn idat_chunks(buffer: &PixelBuffer) -> Result<Vec<Chunk>> {
+    let idat = ChunkType::from_str("IDAT")?;
+    let chunks = encode(buffer)?
+        .chunks(MAX_IDAT_LENGTH)
+        .map(|data| Chunk::new(idat.clone(), data.to_vec()))
+        .collect();
+    Ok(chunks)
+}
+
+/// a minimal PNG showing a pixel buffer: its header, image data and IEND
+pub fn to_png(buffer: &PixelBuffer) -> Result<Png> {
+    let mut chunks = vec![buffer.ihdr().to_chunk()?];
+    chunks.extend(idat_chunks(buffer)?);
+    chunks.push(Chunk::new(ChunkType::from_str("IEND")?, Vec::new()));
+    Ok(Png::from_chunks(chunks))
+}
+
+/// decode the image data of a PNG into its samples, de-interlacing Adam7 images;
+/// neither the inflated data nor the decoded samples may take more than `max_length` bytes
+pub fn decode(png: &Png, max_length: usize) -> Result<PixelBuffer> {
+    let ihdr = png.ihdr()?;
+    let channels = ihdr.color_type().channels() as usize;
+    let (width, bit_depth) = (ihdr.width() as usize, ihdr.bit_depth());
+    let sample_count = width
+        .checked_mul(ihdr.height() as usize)
+        .and_then(|pixels| pixels.checked_mul(channels))
+        .filter(|count| count.saturating_mul(if bit_depth == 16 { 2 } else { 1 }) <= max_length)
+        .ok_or(PngMeError::PixelError::TooLarge(max_length))?;
+
+    let mut data = inflate(png, max_length)?;
+    let mut samples = Samples::zeroed(bit_depth, sample_count);
+    let bytes_per_pixel = bytes_per_pixel(&ihdr);
+    let (mut offset, mut first_row) = (0, 0);
+    for pass in passes(&ihdr).into_iter().filter(|pass| pass.width > 0 && pass.height > 0) {
+        let stride = stride(&ihdr, pass.width).ok_or(PngMeError::PixelError::TooLarge(max_length))?;
+        let scanlines = &mut data[offset..offset + (stride + 1) * pass.height];
+        unfilter(scanlines, stride, bytes_per_pixel, first_row)?;
+        for (row, scanline) in scanlines.chunks_exact(stride + 1).enumerate() {
+            let y = pass.y0 + row * pass.dy;
+            for index in 0..pass.width * channels {
+                let x = pass.x0 + index / channels * pass.dx;
+                samples.set((y * width + x) * channels + index % channels, unpack_sample(&scanline[1..], bit_depth, index));
+            }
+        }
+        offset += scanlines.len();
+        first_row += pass.height;
+    }
+    Ok(PixelBuffer { ihdr, samples })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{chunk, sample_png};
+
+    fn ihdr(width: u32, height: u32, bit_depth: u8, color_type: u8, interlace: u8) -> Chunk {
+        let data: Vec<u8> = width
+            .to_be_bytes()
+            .iter()
+            .chain(height.to_be_bytes().iter())
+            .chain([bit_depth, color_type, 0, 0, interlace].iter())
+            .copied()
+            .collect();
+        chunk("IHDR", data)
+    }
+
+    // a PNG holding already filtered scanlines, compressed and split across two IDAT chunks
+    fn png_from_scanlines(ihdr_chunk: Chunk, scanlines: &[u8]) -> Png {
+        let mut encoder = ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(scanlines).unwrap();
+        let compressed = encoder.finish().unwrap();
+        let (first, second) = compressed.split_at(compressed.len() / 2);
+        Png::from_chunks(vec![
+            ihdr_chunk,
+            chunk("IDAT", first.to_vec()),
+            chunk("IDAT", second.to_vec()),
+            chunk("IEND", Vec::new()),
+        ])
+    }
+
+    // 8-bit grayscale scanlines of a `width` x `height` image, filtered with every filter type in turn
+    fn filtered_gray(pixels: &[u8], width: usize) -> Vec<u8> {
+        let filter_types = [FilterType::None, FilterType::Sub, FilterType::Up, FilterType::Average, FilterType::Paeth];
+        let mut previous = vec![0u8; width];
+        let mut scanlines = Vec::new();
+        for (row, line) in pixels.chunks(width).enumerate() {
+            scanlines.extend(filter(filter_types[row % filter_types.len()], line, &previous, 1));
+            previous = line.to_vec();
+        }
+        scanlines
+    }
+
+    fn gradient(width: usize, height: usize) -> Vec<u8> {
+        (0..width * height).map(|i| (i * 37 % 251) as u8).collect()
+    }
+
+    #[test]
+    fn test_decode_all_filter_types() {
+        let pixels = gradient(7, 10);
+        let png = png_from_scanlines(ihdr(7, 10, 8, 0, 0), &filtered_gray(&pixels, 7));
+        let buffer = decode(&png, DEFAULT_MAX_IMAGE_DATA_LENGTH).unwrap();
+        assert_eq!(buffer.samples(), &Samples::Eight(pixels));
+        assert_eq!(buffer.sample(3, 2, 0), (17 * 37 % 251) as u16);
+    }
+
+    #[test]
+    fn test_decode_rgba_with_paeth() {
+        let pixels: Vec<u8> = (0..3 * 2 * 4).map(|i| (i * 11) as u8).collect();
+        let mut scanlines = filter(FilterType::Sub, &pixels[..12], &[0; 12], 4);
+        scanlines.extend(filter(FilterType::Paeth, &pixels[12..], &pixels[..12], 4));
+        let buffer = decode(&png_from_scanlines(ihdr(3, 2, 8, 6, 0), &scanlines), DEFAULT_MAX_IMAGE_DATA_LENGTH).unwrap();
+        assert_eq!(buffer.channels(), 4);
+        assert_eq!(buffer.sample(1, 1, 2), pixels[4 * 4 + 2] as u16);
+        assert_eq!(buffer.samples(), &Samples::Eight(pixels));
+    }
+
+    #[test]
+    fn test_decode_sub_byte_samples() {
+        // 10 pixels of 1 bit: 1011001110, padded to two bytes
+        let png = png_from_scanlines(ihdr(10, 1, 1, 0, 0), &[0, 0b1011_0011, 0b1000_0000]);
+        let buffer = decode(&png, DEFAULT_MAX_IMAGE_DATA_LENGTH).unwrap();
+        assert_eq!(buffer.samples(), &Samples::Eight(vec![1, 0, 1, 1, 0, 0, 1, 1, 1, 0]));
+
+        // 3 palette indices of 4 bits each
+        let png = png_from_scanlines(ihdr(3, 1, 4, 3, 0), &[0, 0xa5, 0xf0]);
+        let buffer = decode(&png, DEFAULT_MAX_IMAGE_DATA_LENGTH).unwrap();
+        assert_eq!(buffer.color_type(), ColorType::Indexed);
+        assert_eq!(buffer.samples(), &Samples::Eight(vec![0xa, 0x5, 0xf]));
+    }
+
+    #[test]
+    fn test_decode_16_bit_samples() {
+        let line = [0x12, 0x34, 0xff, 0xfe, 0x00, 0x01];
+        let png = png_from_scanlines(ihdr(1, 1, 16, 2, 0), &filter(FilterType::Average, &line, &[0; 6], 6));
+        let buffer = decode(&png, DEFAULT_MAX_IMAGE_DATA_LENGTH).unwrap();
+        assert_eq!(buffer.samples(), &Samples::Sixteen(vec![0x1234, 0xfffe, 0x0001]));
+    }
+
+    #[test]
+    fn test_decode_adam7() {
+        let (width, height) = (11, 9);
+        let pixels = gradient(width, height);
+        let mut scanlines = Vec::new();
+        for (x0, y0, dx, dy) in ADAM7_PASSES {
+            let reduced: Vec<u8> = (y0..height)
+                .step_by(dy)
+                .flat_map(|y| (x0..width).step_by(dx).map(move |x| y * width + x))
+                .map(|index| pixels[index])
+                .collect();
+            let pass_width = (x0..width).step_by(dx).count();
+            if !reduced.is_empty() {
+                scanlines.extend(filtered_gray(&reduced, pass_width));
+            }
+        }
+        let png = png_from_scanlines(ihdr(width as u32, height as u32, 8, 0, 1), &scanlines);
+        let buffer = decode(&png, DEFAULT_MAX_IMAGE_DATA_LENGTH).unwrap();
+        assert_eq!(buffer.samples(), &Samples::Eight(pixels));
+    }
+
+    #[test]
+    fn test_decode_tiny_adam7_skips_empty_passes() {
+        // a single pixel only appears in the first pass
+        let png = png_from_scanlines(ihdr(1, 1, 8, 4, 1), &[0, 200, 100]);
+        let buffer = decode(&png, DEFAULT_MAX_IMAGE_DATA_LENGTH).unwrap();
+        assert_eq!(buffer.samples(), &Samples::Eight(vec![200, 100]));
+    }
+
+    #[test]
+    fn test_decode_sample_image() {
+        let png = sample_png();
+        let buffer = decode(&png, DEFAULT_MAX_IMAGE_DATA_LENGTH).unwrap();
+        assert_eq!(buffer.samples().len(), 727 * 684 * 4);
+    }
+
+    #[test]
+    fn test_invalid_filter_type() {
+        let png = png_from_scanlines(ihdr(2, 2, 8, 0, 0), &[0, 1, 2, 5, 3, 4]);
+        assert!(matches!(
+            decode(&png, DEFAULT_MAX_IMAGE_DATA_LENGTH).unwrap_err().downcast_ref::<PngMeError::PixelError>(),
+            Some(PngMeError::PixelError::InvalidFilterType(5, 1))
+        ));
+    }
+
+    #[test]
+    fn test_wrong_image_data_length() {
+        let png = png_from_scanlines(ihdr(2, 2, 8, 0, 0), &[0, 1, 2, 0, 3]);
+        assert!(matches!(
+            decode(&png, DEFAULT_MAX_IMAGE_DATA_LENGTH).unwrap_err().downcast_ref::<PngMeError::PixelError>(),
+            Some(PngMeError::PixelError::InvalidLength(6, 5))
+        ));
+        let png = png_from_scanlines(ihdr(2, 2, 8, 0, 0), &[0; 7]);
+        assert!(matches!(
+            decode(&png, DEFAULT_MAX_IMAGE_DATA_LENGTH).unwrap_err().downcast_ref::<PngMeError::PixelError>(),
+            Some(PngMeError::PixelError::InvalidLength(6, 7))
+        ));
+    }
+
+    #[test]
+    fn test_corrupt_image_data() {
+        let png = Png::from_chunks(vec![ihdr(2, 2, 8, 0, 0), chunk("IDAT", vec![0x78, 0x9c, 0xff, 0xff]), chunk("IEND", Vec::new())]);
+        assert!(matches!(
+            decode(&png, DEFAULT_MAX_IMAGE_DATA_LENGTH).unwrap_err().downcast_ref::<PngMeError::PixelError>(),
+            Some(PngMeError::PixelError::CorruptData(_))
+        ));
+    }
+
+    #[test]
+    fn test_missing_image_data() {
+        let png = Png::from_chunks(vec![ihdr(2, 2, 8, 0, 0), chunk("IEND", Vec::new())]);
+        assert!(matches!(
+            decode(&png, DEFAULT_MAX_IMAGE_DATA_LENGTH).unwrap_err().downcast_ref::<PngMeError::PixelError>(),
+            Some(PngMeError::PixelError::MissingImageData)
+        ));
+    }
+
+    #[test]
+    fn test_image_data_limit() {
+        // the header alone announces far too much data, nothing is inflated
+        let png = png_from_scanlines(ihdr(1 << 20, 1 << 20, 16, 6, 0), &[0]);
+        assert!(matches!(
+            decode(&png, DEFAULT_MAX_IMAGE_DATA_LENGTH).unwrap_err().downcast_ref::<PngMeError::PixelError>(),
+            Some(PngMeError::PixelError::TooLarge(_))
+        ));
+    }
+
+    #[test]
+    fn test_encode_roundtrip() {
+        for (bit_depth, color_type, interlace) in [(8, 6, 0), (16, 2, 1), (1, 0, 0), (4, 3, 1), (2, 0, 1)] {
+            let max = (1u32 << bit_depth) - 1;
+            // image data to be replaced
+            let mut png = png_from_scanlines(ihdr(13, 6, bit_depth, color_type, interlace), &[]);
+            let ihdr = png.ihdr().unwrap();
+            let count = 13 * 6 * ihdr.color_type().channels() as usize;
+            let mut samples = Samples::zeroed(bit_depth, count);
+            (0..count).for_each(|index| samples.set(index, (index as u64 * 2654435761 % (max as u64 + 1)) as u16));
+            let buffer = PixelBuffer { ihdr, samples };
+            let idat = write_image_data(&mut png, &buffer).unwrap();
+            assert!(png.chunks()[1..=idat.len()].iter().zip(&idat).all(|(stored, new)| stored.as_bytes() == new.as_bytes()));
+            assert_eq!(png.chunks().len(), idat.len() + 2);
+            assert_eq!(decode(&png, DEFAULT_MAX_IMAGE_DATA_LENGTH).unwrap(), buffer);
+        }
+    }
+
+    #[test]
+    fn test_encode_sample_image_roundtrip() {
+        let mut png = sample_png();
+        let mut buffer = decode(&png, DEFAULT_MAX_IMAGE_DATA_LENGTH).unwrap();
+        buffer.samples_mut().set(0, 42);
+        write_image_data(&mut png, &buffer).unwrap();
+        assert_eq!(png.chunks().first().unwrap().chunk_type().to_string(), "IHDR");
+        assert_eq!(png.chunks().last().unwrap().chunk_type().to_string(), "RuSt");
+        assert_eq!(decode(&png, DEFAULT_MAX_IMAGE_DATA_LENGTH).unwrap(), buffer);
+    }
+
+    #[test]
+    fn test_new_pixel_buffer_to_png() {
+        let ihdr = Ihdr::new(3, 2, 8, ColorType::GrayscaleAlpha).unwrap();
+        let buffer = PixelBuffer::new(ihdr.clone(), Samples::Eight((0..12).collect())).unwrap();
+        let png = to_png(&buffer).unwrap();
+        let types: Vec<String> = png.chunks().iter().map(|chunk| chunk.chunk_type().to_string()).collect();
+        assert_eq!(types, vec!["IHDR", "IDAT", "IEND"]);
+        assert_eq!(decode(&png, DEFAULT_MAX_IMAGE_DATA_LENGTH).unwrap(), buffer);
+
+        assert!(matches!(
+            PixelBuffer::new(ihdr.clone(), Samples::Eight(vec![0; 11])).unwrap_err().downcast_ref::<PngMeError::PixelError>(),
+            Some(PngMeError::PixelError::InvalidLength(12, 11))
+        ));
+        assert!(matches!(
+            PixelBuffer::new(ihdr, Samples::Sixteen(vec![0; 12])).unwrap_err().downcast_ref::<PngMeError::PixelError>(),
+            Some(PngMeError::PixelError::MismatchedSamples(8))
+        ));
+    }
+}
@@ -1,13 +1,32 @@
 use std::fmt;
-use std::fs::{read as read_file, File};
-use std::io::Write;
-use std::path::PathBuf;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::Path;
 
 use anyhow::{anyhow, Result};
 
 use crate::chunk::Chunk;
+use crate::ihdr::Ihdr;
 use crate::error as PngMeError;
 
+/// Where to place a new chunk relative to the critical chunks of a PNG
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum ChunkPosition {
+    /// directly after the IHDR image header
+    AfterIhdr,
+    /// directly before the first IDAT chunk
+    BeforeIdat,
+    /// directly after the last consecutive IDAT chunk
+    AfterIdat,
+    /// directly before IEND; keeps the file fully conformant
+    #[default]
+    BeforeIend,
+    /// after IEND, at the very end of the file; flagged as trailing garbage by strict decoders
+    End,
+}
+
+#[derive(Debug, Clone)]
 pub struct Png {
     chunks: Vec<Chunk>,
 }
@@ -27,16 +46,78 @@ impl Png {
     pub fn append_chunk(&mut self, chunk: Chunk) {
         self.chunks.push(chunk);
     }
+    /// insert a chunk at the given index, shifting all following chunks back
+    pub fn insert_chunk(&mut self, index: usize, chunk: Chunk) -> Result<()> {
+        if index > self.chunks.len() {
+            return Err(anyhow!(PngMeError::PNGError::IndexOutOfBounds(index, self.chunks.len())));
+        }
+        self.chunks.insert(index, chunk);
+        Ok(())
+    }
+    /// insert a chunk at a spec-aware position; fails if the anchoring chunk is missing.
+    /// Without an IEND chunk, `BeforeIend` falls back to appending the chunk.
+    pub fn insert_chunk_at(&mut self, position: ChunkPosition, chunk: Chunk) -> Result<()> {
+        let index = self.index_at(position)?;
+        self.insert_chunk(index, chunk)
+    }
+    /// insert several chunks at a spec-aware position, keeping them consecutive and in order
+    pub fn insert_chunks_at(&mut self, position: ChunkPosition, chunks: Vec<Chunk>) -> Result<()> {
+        let index = self.index_at(position)?;
+        self.chunks.splice(index..index, chunks);
+        Ok(())
+    }
+    // index a new chunk at a spec-aware position gets
+    fn index_at(&self, position: ChunkPosition) -> Result<usize> {
+        let index = match position {
+            ChunkPosition::AfterIhdr => {
+                self.position_of("IHDR").ok_or(PngMeError::PNGError::ChunkNotFound)? + 1
+            },
+            ChunkPosition::BeforeIdat => {
+                self.position_of("IDAT").ok_or(PngMeError::PNGError::ChunkNotFound)?
+            },
+            ChunkPosition::AfterIdat => {
+                let first_idat = self.position_of("IDAT").ok_or(PngMeError::PNGError::ChunkNotFound)?;
+                first_idat + self.chunks[first_idat..]
+                    .iter()
+                    .take_while(|chunk| chunk.chunk_type().to_string() == "IDAT")
+                    .count()
+            },
+            ChunkPosition::BeforeIend => self.position_of("IEND").unwrap_or(self.chunks.len()),
+            ChunkPosition::End => self.chunks.len(),
+        };
+        Ok(index)
+    }
     /// remove the first Chunk matching a specific chunk_type from the PNG, and return this Chunk
     pub fn remove_chunk(&mut self, chunk_type: &str) -> Result<Chunk> {
+        self.remove_chunk_where(|chunk| chunk.chunk_type().to_string() == chunk_type)
+    }
+    /// remove the first Chunk a predicate holds for from the PNG, and return this Chunk
+    pub fn remove_chunk_where<F: FnMut(&Chunk) -> bool>(&mut self, predicate: F) -> Result<Chunk> {
         let index = self.chunks
             .iter()
-            .position(|chunk| chunk.chunk_type().to_string() == chunk_type)
+            .position(predicate)
             .ok_or(PngMeError::PNGError::ChunkNotFound)?;
 
         let removed = self.chunks.remove(index);
         Ok(removed)
     }
+    /// remove all Chunks matching a specific chunk_type from the PNG, and return them in their original order
+    pub fn remove_chunks(&mut self, chunk_type: &str) -> Result<Vec<Chunk>> {
+        let removed = self.remove_chunks_where(|chunk| chunk.chunk_type().to_string() == chunk_type);
+        if removed.is_empty() {
+            return Err(anyhow!(PngMeError::PNGError::ChunkNotFound));
+        }
+        Ok(removed)
+    }
+    /// remove all Chunks a predicate holds for from the PNG, and return them in their original order;
+    /// the predicate sees every chunk exactly once, in file order
+    pub fn remove_chunks_where<F: FnMut(&Chunk) -> bool>(&mut self, mut predicate: F) -> Vec<Chunk> {
+        let (removed, kept): (Vec<Chunk>, Vec<Chunk>) = self.chunks
+            .drain(..)
+            .partition(|chunk| predicate(chunk));
+        self.chunks = kept;
+        removed
+    }
     /// get the constant list of bytes storing PNG header
     pub fn header(&self) -> &[u8;8] {
         &Png::STANDARD_HEADER
@@ -50,6 +131,37 @@ impl Png {
             .iter()
             .find(|chunk| chunk.chunk_type().to_string() == chunk_type)
     }
+    /// iterate over all Chunks matching a specific chunk_type, together with their index in the PNG
+    pub fn chunks_by_type<'a>(&'a self, chunk_type: &'a str) -> impl Iterator<Item = (usize, &'a Chunk)> + 'a {
+        self.chunks
+            .iter()
+            .enumerate()
+            .filter(move |(_, chunk)| chunk.chunk_type().to_string() == chunk_type)
+    }
+    // index of the first chunk matching a specific chunk_type
+    fn position_of(&self, chunk_type: &str) -> Option<usize> {
+        self.chunks
+            .iter()
+            .position(|chunk| chunk.chunk_type().to_string() == chunk_type)
+    }
+    /// byte offset of every chunk from the start of the file, i.e. including the signature
+    pub fn chunk_offsets(&self) -> Vec<usize> {
+        self.chunks
+            .iter()
+            .scan(Png::STANDARD_HEADER.len(), |offset, chunk| {
+                let current = *offset;
+                *offset += chunk.length() as usize + Chunk::METADATA_LENGTH;
+                Some(current)
+            })
+            .collect()
+    }
+    /// interpret the first chunk as image header; fails if it is missing or not a valid IHDR
+    pub fn ihdr(&self) -> Result<Ihdr> {
+        let first_chunk = self.chunks
+            .first()
+            .ok_or(PngMeError::IhdrError::MissingIhdr)?;
+        Ihdr::try_from(first_chunk)
+    }
     pub fn as_bytes(&self) -> Vec<u8> {
         let chunks_as_bytes : Vec<u8> = self.chunks
             .iter()
@@ -63,20 +175,86 @@ impl Png {
             .collect()
     }
 
-    pub fn read_file(path: PathBuf) -> Result<Self> {
-        let file = read_file(path).map_err(|err| PngMeError::FsIoError::UnableToReadFileError(err.to_string()))?;
-        Png::try_from(file.as_slice())
+    /// read a PNG chunk by chunk from any reader, without buffering the whole input first
+    pub fn from_reader<R: Read>(reader: R) -> Result<Self> {
+        let chunks = ChunkReader::new(reader)?.collect::<Result<Vec<Chunk>>>()?;
+        Ok(Png { chunks })
+    }
+
+    /// write signature and chunks one after another to any writer, without building a copy of the file first
+    pub fn write_to<W: Write>(&self, mut writer: W) -> Result<()> {
+        writer.write_all(self.header())?;
+        for chunk in &self.chunks {
+            chunk.write_to(&mut writer)?;
+        }
+        Ok(())
     }
 
-    pub fn write_file(&self, path: &PathBuf) -> Result<()> {
-        let mut file = File::create(path).map_err(|err| PngMeError::FsIoError::UnableToCreateFileError(err.to_string()))?;
-        file.write(self.as_bytes().as_slice())
+    pub fn read_file<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let file = File::open(path).map_err(|err| PngMeError::FsIoError::UnableToReadFileError(err.to_string()))?;
+        Png::from_reader(BufReader::new(file))
+    }
+
+    pub fn write_file<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let file = File::create(path).map_err(|err| PngMeError::FsIoError::UnableToCreateFileError(err.to_string()))?;
+        let mut writer = BufWriter::new(file);
+        self.write_to(&mut writer)
+            .and_then(|_| writer.flush().map_err(anyhow::Error::from))
             .map_err(|err| PngMeError::FsIoError::UnableToWriteToOutputFileError(err.to_string()))?;
         Ok(())
     }
 
 }
 
+/// Streaming iterator over the chunks of a PNG, reading one chunk at a time from the underlying reader.
+/// The signature is verified on construction; iteration stops after the first error.
+pub struct ChunkReader<R: Read> {
+    reader: R,
+    offset: usize,
+    done: bool,
+}
+
+impl<R: Read> ChunkReader<R> {
+    /// consume and verify the PNG signature, then get ready to read chunks
+    pub fn new(mut reader: R) -> Result<Self> {
+        let mut signature = [0u8; 8];
+        reader.read_exact(&mut signature).map_err(|_| PngMeError::PNGError::TooSmall)?;
+        if signature != Png::STANDARD_HEADER {
+            return Err(anyhow!(PngMeError::PNGError::InvalidSignature));
+        }
+        Ok(ChunkReader { reader, offset: Png::STANDARD_HEADER.len(), done: false })
+    }
+
+    /// byte offset of the next chunk from the start of the input
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+}
+
+impl<R: Read> Iterator for ChunkReader<R> {
+    type Item = Result<Chunk>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        match Chunk::read_from(&mut self.reader, self.offset) {
+            Ok(Some(chunk)) => {
+                self.offset += chunk.length() as usize + Chunk::METADATA_LENGTH;
+                Some(Ok(chunk))
+            },
+            Ok(None) => {
+                self.done = true;
+                None
+            },
+            Err(err) => {
+                self.done = true;
+                Some(Err(err))
+            },
+        }
+    }
+}
+
 /// TryFrom trait for PNG to construct from a sequence of Bytes
 impl TryFrom<&[u8]> for Png {
     type Error = anyhow::Error;
@@ -99,8 +277,9 @@ impl TryFrom<&[u8]> for Png {
         // iterate over input and convert bytes into PNG Chunks, then combine them in Vec
         while index < bytes.len() {
             let bytes_slice = &bytes[index..];
-            let chunk = Chunk::try_from(bytes_slice)?;
-            index += (chunk.length() + 12) as usize; // 12 bytes for metadata + chunk length
+            // report offsets relative to the start of the file, i.e. including the signature
+            let chunk = Chunk::from_bytes_at(bytes_slice, index + Png::STANDARD_HEADER.len())?;
+            index += chunk.length() as usize + Chunk::METADATA_LENGTH; // 12 bytes for metadata + chunk length
 
             chunks.push(chunk);
         }
@@ -243,6 +422,63 @@ mod tests {
         assert_eq!(&chunk.data_as_string().unwrap(), "Message");
     }
 
+    fn chunk_types(png: &Png) -> Vec<String> {
+        png.chunks().iter().map(|chunk| chunk.chunk_type().to_string()).collect()
+    }
+
+    fn structured_png() -> Png {
+        Png::from_chunks(vec![
+            chunk_from_strings("IHDR", "header").unwrap(),
+            chunk_from_strings("IDAT", "first").unwrap(),
+            chunk_from_strings("IDAT", "second").unwrap(),
+            chunk_from_strings("IEND", "").unwrap(),
+        ])
+    }
+
+    #[test]
+    fn test_insert_chunk() {
+        let mut png = testing_png();
+        png.insert_chunk(1, chunk_from_strings("TeSt", "Message").unwrap()).unwrap();
+        assert_eq!(chunk_types(&png), vec!["FrSt", "TeSt", "miDl", "LASt"]);
+        assert!(png.insert_chunk(5, chunk_from_strings("TeSt", "Message").unwrap()).is_err());
+    }
+
+    #[test]
+    fn test_insert_chunk_at_positions() {
+        let positions = [
+            (ChunkPosition::AfterIhdr, ["IHDR", "TeSt", "IDAT", "IDAT", "IEND"]),
+            (ChunkPosition::BeforeIdat, ["IHDR", "TeSt", "IDAT", "IDAT", "IEND"]),
+            (ChunkPosition::AfterIdat, ["IHDR", "IDAT", "IDAT", "TeSt", "IEND"]),
+            (ChunkPosition::BeforeIend, ["IHDR", "IDAT", "IDAT", "TeSt", "IEND"]),
+            (ChunkPosition::End, ["IHDR", "IDAT", "IDAT", "IEND", "TeSt"]),
+        ];
+        for (position, expected) in positions {
+            let mut png = structured_png();
+            png.insert_chunk_at(position, chunk_from_strings("TeSt", "Message").unwrap()).unwrap();
+            assert_eq!(chunk_types(&png), expected);
+        }
+    }
+
+    #[test]
+    fn test_insert_chunks_at_keeps_order() {
+        let mut png = structured_png();
+        let chunks = vec![
+            chunk_from_strings("TeSt", "first").unwrap(),
+            chunk_from_strings("TeSt", "second").unwrap(),
+        ];
+        png.insert_chunks_at(ChunkPosition::AfterIhdr, chunks).unwrap();
+        assert_eq!(chunk_types(&png), ["IHDR", "TeSt", "TeSt", "IDAT", "IDAT", "IEND"]);
+        assert_eq!(png.chunks()[1].data_as_string().unwrap(), "first");
+    }
+
+    #[test]
+    fn test_insert_chunk_at_missing_anchor() {
+        let mut png = testing_png();
+        assert!(png.insert_chunk_at(ChunkPosition::BeforeIdat, chunk_from_strings("TeSt", "Message").unwrap()).is_err());
+        png.insert_chunk_at(ChunkPosition::BeforeIend, chunk_from_strings("TeSt", "Message").unwrap()).unwrap();
+        assert_eq!(chunk_types(&png).last().unwrap(), "TeSt");
+    }
+
     #[test]
     fn test_remove_first_chunk() {
         let mut png = testing_png();
@@ -252,6 +488,115 @@ mod tests {
         assert!(chunk.is_none());
     }
 
+    #[test]
+    fn test_ihdr() {
+        let png = Png::try_from(&PNG_FILE[..]).unwrap();
+        let ihdr = png.ihdr().unwrap();
+        assert_eq!(ihdr.width(), 50);
+        assert_eq!(ihdr.height(), 50);
+    }
+
+    #[test]
+    fn test_ihdr_not_first_chunk() {
+        let png = testing_png();
+        assert!(png.ihdr().is_err());
+    }
+
+    #[test]
+    fn test_truncated_png_never_panics() {
+        for end in 0..PNG_FILE.len() {
+            let png = Png::try_from(&PNG_FILE[..end]);
+            // a cut right at a chunk boundary still yields a (structurally incomplete) PNG
+            if let Err(err) = png {
+                assert!(!err.to_string().is_empty());
+            }
+        }
+    }
+
+    #[test]
+    fn test_truncated_png_reports_offset() {
+        let err = Png::try_from(&PNG_FILE[..40]).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<PngMeError::ChunkError>(),
+            Some(PngMeError::ChunkError::Truncated(33, 12, 7))
+        ));
+    }
+
+    #[test]
+    fn test_png_from_reader() {
+        let png = Png::from_reader(&PNG_FILE[..]).unwrap();
+        assert_eq!(png.as_bytes(), PNG_FILE.to_vec());
+    }
+
+    #[test]
+    fn test_truncated_png_from_reader() {
+        let err = Png::from_reader(&PNG_FILE[..40]).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<PngMeError::ChunkError>(),
+            Some(PngMeError::ChunkError::Truncated(33, 12, 7))
+        ));
+        assert!(Png::from_reader(&PNG_FILE[..5]).is_err());
+    }
+
+    #[test]
+    fn test_png_write_to() {
+        let png = Png::try_from(&PNG_FILE[..]).unwrap();
+        let mut written = Vec::new();
+        png.write_to(&mut written).unwrap();
+        assert_eq!(written, PNG_FILE.to_vec());
+    }
+
+    #[test]
+    fn test_chunk_reader() {
+        let mut reader = ChunkReader::new(&PNG_FILE[..]).unwrap();
+        let first = reader.next().unwrap().unwrap();
+        assert_eq!(&first.chunk_type().to_string(), "IHDR");
+        assert_eq!(reader.offset(), 33);
+        assert_eq!(reader.count(), 6);
+    }
+
+    #[test]
+    fn test_chunks_by_type() {
+        let mut png = testing_png();
+        png.append_chunk(chunk_from_strings("miDl", "I am a second middle chunk").unwrap());
+        let found: Vec<(usize, String)> = png
+            .chunks_by_type("miDl")
+            .map(|(index, chunk)| (index, chunk.data_as_string().unwrap()))
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                (1, String::from("I am another chunk")),
+                (3, String::from("I am a second middle chunk")),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_remove_chunks() {
+        let mut png = testing_png();
+        png.append_chunk(chunk_from_strings("miDl", "I am a second middle chunk").unwrap());
+        let removed = png.remove_chunks("miDl").unwrap();
+        assert_eq!(removed.len(), 2);
+        assert_eq!(&removed[1].data_as_string().unwrap(), "I am a second middle chunk");
+        assert_eq!(chunk_types(&png), vec!["FrSt", "LASt"]);
+        assert!(png.remove_chunks("miDl").is_err());
+    }
+
+    #[test]
+    fn test_remove_chunks_where() {
+        let mut png = testing_png();
+        png.append_chunk(chunk_from_strings("miDl", "I am a second middle chunk").unwrap());
+        let removed = png.remove_chunks_where(|chunk| chunk.chunk_type().is_critical());
+        assert_eq!(removed.iter().map(|chunk| chunk.chunk_type().to_string()).collect::<Vec<_>>(), vec!["FrSt", "LASt"]);
+        assert_eq!(chunk_types(&png), vec!["miDl", "miDl"]);
+        assert!(png.remove_chunks_where(|chunk| chunk.length() > 100).is_empty());
+
+        let removed = png.remove_chunk_where(|chunk| chunk.data_as_string().unwrap().contains("second")).unwrap();
+        assert_eq!(&removed.data_as_string().unwrap(), "I am a second middle chunk");
+        assert!(png.remove_chunk_where(|_| false).is_err());
+    }
+
     #[test]
     fn test_png_from_image_file() {
         let png = Png::try_from(&PNG_FILE[..]);
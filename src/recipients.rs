@@ -0,0 +1,162 @@
+use std::io::{Read, Write};
+use std::str::FromStr;
+
+use age::secrecy::ExposeSecret;
+use age::{x25519, DecryptError, Decryptor, Encryptor, IdentityFile};
+
+use anyhow::{anyhow, Result};
+
+use crate::error as PngMeError;
+
+/*
+    Public-key encryption of message payloads to one or more X25519 recipients.
+
+    Payloads are complete files in the binary age format (https://age-encryption.org/v1), so a
+    message extracted with `pngme decode --format raw` can be decrypted with the `age` tool itself,
+    and identities created by `age-keygen` work with `pngme decode --identity`, and vice versa.
+*/
+
+/// every binary age file starts with this header line
+pub const AGE_MAGIC: &[u8] = b"age-encryption.org/v1\n";
+
+/// A freshly generated X25519 key pair, both halves in their age string encoding
+pub struct Keypair {
+    /// secret key, `AGE-SECRET-KEY-1...`
+    pub identity: String,
+    /// public key to share with senders, `age1...`
+    pub recipient: String,
+}
+
+impl Keypair {
+    /// render the key pair as identity file, in the format written by `age-keygen`
+    pub fn to_identity_file(&self) -> String {
+        format!("# public key: {}\n{}\n", self.recipient, self.identity)
+    }
+}
+
+/// create a new random X25519 identity
+pub fn generate_keypair() -> Keypair {
+    let identity = x25519::Identity::generate();
+    Keypair {
+        recipient: identity.to_public().to_string(),
+        identity: identity.to_string().expose_secret().to_string(),
+    }
+}
+
+/// check whether a payload is an age file, i.e. encrypted to recipients
+pub fn is_encrypted(payload: &[u8]) -> bool {
+    payload.starts_with(AGE_MAGIC)
+}
+
+/// encrypt a payload to one or more `age1...` recipients; any of their identities can decrypt it
+pub fn encrypt(plaintext: &[u8], recipients: &[String]) -> Result<Vec<u8>> {
+    let recipients = recipients
+        .iter()
+        .map(|recipient| {
+            x25519::Recipient::from_str(recipient)
+                .map_err(|err| anyhow!(PngMeError::CryptoError::InvalidRecipient(format!("{}: {}", recipient, err))))
+        })
+        .collect::<Result<Vec<x25519::Recipient>>>()?;
+
+    let encryptor = Encryptor::with_recipients(recipients.iter().map(|recipient| recipient as &dyn age::Recipient))
+        .map_err(|err| PngMeError::CryptoError::InvalidRecipient(err.to_string()))?;
+    let mut encrypted = Vec::new();
+    let mut writer = encryptor.wrap_output(&mut encrypted)?;
+    writer.write_all(plaintext)?;
+    writer.finish()?;
+    Ok(encrypted)
+}
+
+/// decrypt an age payload with the identities found in the contents of an identity file;
+/// fails with `CryptoError::NoMatchingIdentity` if the payload was encrypted to somebody else
+pub fn decrypt(payload: &[u8], identity_file: &str) -> Result<Vec<u8>> {
+    if !is_encrypted(payload) {
+        return Err(anyhow!(PngMeError::CryptoError::NotEncrypted));
+    }
+    let identities = IdentityFile::from_buffer(identity_file.as_bytes())
+        .map_err(|err| PngMeError::CryptoError::InvalidIdentity(err.to_string()))?
+        .into_identities()
+        .map_err(|err| PngMeError::CryptoError::InvalidIdentity(err.to_string()))?;
+    if identities.is_empty() {
+        return Err(anyhow!(PngMeError::CryptoError::InvalidIdentity(String::from("no identities found"))));
+    }
+
+    let decryptor = Decryptor::new_buffered(payload)
+        .map_err(|err| PngMeError::CryptoError::InvalidHeader(err.to_string()))?;
+    let mut reader = decryptor
+        .decrypt(identities.iter().map(|identity| identity.as_ref()))
+        .map_err(|err| match err {
+            DecryptError::NoMatchingKeys => PngMeError::CryptoError::NoMatchingIdentity,
+            _ => PngMeError::CryptoError::DecryptionFailed,
+        })?;
+    let mut plaintext = Vec::new();
+    reader
+        .read_to_end(&mut plaintext)
+        .map_err(|_| PngMeError::CryptoError::DecryptionFailed)?;
+    Ok(plaintext)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_keypair_identity_file() {
+        let keypair = generate_keypair();
+        assert!(keypair.recipient.starts_with("age1"));
+        assert!(keypair.identity.starts_with("AGE-SECRET-KEY-1"));
+        let identity_file = keypair.to_identity_file();
+        assert!(identity_file.lines().any(|line| line == keypair.identity));
+    }
+
+    #[test]
+    fn test_encrypt_to_multiple_recipients() {
+        let alice = generate_keypair();
+        let bob = generate_keypair();
+        let encrypted = encrypt(b"team update", &[alice.recipient.clone(), bob.recipient.clone()]).unwrap();
+        assert!(is_encrypted(&encrypted));
+        assert_eq!(decrypt(&encrypted, &alice.to_identity_file()).unwrap(), b"team update");
+        assert_eq!(decrypt(&encrypted, &bob.to_identity_file()).unwrap(), b"team update");
+    }
+
+    #[test]
+    fn test_decrypt_with_foreign_identity() {
+        let alice = generate_keypair();
+        let mallory = generate_keypair();
+        let encrypted = encrypt(b"team update", &[alice.recipient]).unwrap();
+        assert!(matches!(
+            decrypt(&encrypted, &mallory.to_identity_file()).unwrap_err().downcast_ref::<PngMeError::CryptoError>(),
+            Some(PngMeError::CryptoError::NoMatchingIdentity)
+        ));
+    }
+
+    #[test]
+    fn test_tampered_payload() {
+        let alice = generate_keypair();
+        let mut encrypted = encrypt(b"team update", std::slice::from_ref(&alice.recipient)).unwrap();
+        let last = encrypted.len() - 1;
+        encrypted[last] ^= 1;
+        assert!(matches!(
+            decrypt(&encrypted, &alice.to_identity_file()).unwrap_err().downcast_ref::<PngMeError::CryptoError>(),
+            Some(PngMeError::CryptoError::DecryptionFailed)
+        ));
+    }
+
+    #[test]
+    fn test_invalid_recipient() {
+        assert!(matches!(
+            encrypt(b"team update", &[String::from("age1notakey")]).unwrap_err().downcast_ref::<PngMeError::CryptoError>(),
+            Some(PngMeError::CryptoError::InvalidRecipient(_))
+        ));
+    }
+
+    #[test]
+    fn test_invalid_identity_file() {
+        let alice = generate_keypair();
+        let encrypted = encrypt(b"team update", &[alice.recipient]).unwrap();
+        assert!(matches!(
+            decrypt(&encrypted, "# nothing to see here\n").unwrap_err().downcast_ref::<PngMeError::CryptoError>(),
+            Some(PngMeError::CryptoError::InvalidIdentity(_))
+        ));
+    }
+}
@@ -0,0 +1,387 @@
+use std::fmt;
+
+use anyhow::{anyhow, Result};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+
+use crate::bundle;
+use crate::chunk::Chunk;
+use crate::crypto;
+use crate::envelope;
+use crate::error as PngMeError;
+use crate::fragment;
+use crate::lsb;
+use crate::pixels;
+use crate::png::Png;
+use crate::recipients;
+use crate::seal;
+use crate::signature;
+
+/*
+    Detection of data hidden in a PNG without knowing where it was put.
+
+    The file is walked chunk by chunk, like a decoder would, and everything a decoder does not need
+    to display the image is a candidate: chunks of private or unknown types, chunks and raw bytes
+    after IEND, and text chunks far larger than metadata usually is. Payloads are further checked
+    for the formats written by pngme and for high entropy, which encrypted or compressed data shows.
+    Messages that `encode --method lsb` wrote into the pixels are found by their header,
+    unless they were scattered with a passphrase.
+
+    Each finding is scored from 0 to 100 by how strongly it suggests a hidden message;
+    the score of a file is the highest score of its findings.
+*/
+
+// chunk types of the PNG spec and its registered extensions
+const KNOWN_CHUNK_TYPES: [&str; 30] = [
+    "IHDR", "PLTE", "IDAT", "IEND", "cHRM", "gAMA", "iCCP", "sBIT", "sRGB", "bKGD", "hIST", "tRNS", "pHYs",
+    "sPLT", "tIME", "iTXt", "tEXt", "zTXt", "cICP", "mDCV", "cLLI", "eXIf", "acTL", "fcTL", "fdAT", "oFFs",
+    "pCAL", "sCAL", "gIFg", "sTER",
+];
+// chunk types whose data is compressed by definition, and therefore of high entropy
+const COMPRESSED_CHUNK_TYPES: [&str; 4] = ["IDAT", "fdAT", "iCCP", "zTXt"];
+const TEXT_CHUNK_TYPES: [&str; 3] = ["tEXt", "zTXt", "iTXt"];
+
+/// text chunks larger than this are reported
+pub const MAX_TEXT_LENGTH: usize = 4 << 10;
+/// payloads with at least this many bits of entropy per byte are reported
+pub const MIN_ENTROPY: f64 = 7.5;
+// shorter payloads are never close to 8 bits per byte, and tell too little
+const MIN_ENTROPY_LENGTH: usize = 256;
+
+/// Things that suggest a PNG hides data
+#[derive(Debug, Clone, PartialEq)]
+pub enum FindingKind {
+    // chunk of a private type, as used to carry messages
+    PrivateChunk(String),
+    // public chunk type defined neither by the spec nor by its extensions
+    UnknownChunk(String),
+    // chunk after IEND, ignored by decoders
+    ChunkAfterIend(String),
+    // bytes after IEND that do not form chunks: length
+    TrailingData(usize),
+    // text chunk far larger than metadata usually is: chunk type & data length
+    OversizedText(String, usize),
+    // payload close to random, as encrypted or compressed data is: chunk type & bits per byte
+    HighEntropy(String, f64),
+    // payload in one of the formats pngme writes: chunk type & format
+    PngmePayload(String, &'static str),
+    // message in the pixel samples, as written by `encode --method lsb`: payload length
+    LsbMessage(usize),
+}
+
+impl FindingKind {
+    /// how strongly the finding suggests a hidden message, from 0 to 100
+    pub fn score(&self) -> u8 {
+        match self {
+            FindingKind::PngmePayload(..) | FindingKind::LsbMessage(_) => 100,
+            FindingKind::TrailingData(_) => 90,
+            FindingKind::ChunkAfterIend(_) => 80,
+            FindingKind::HighEntropy(..) => 60,
+            FindingKind::PrivateChunk(_) => 50,
+            FindingKind::UnknownChunk(_) => 40,
+            FindingKind::OversizedText(..) => 30,
+        }
+    }
+}
+
+impl fmt::Display for FindingKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FindingKind::PrivateChunk(chunk_type) => write!(f, "private chunk {}", chunk_type),
+            FindingKind::UnknownChunk(chunk_type) => write!(f, "unknown chunk type {}", chunk_type),
+            FindingKind::ChunkAfterIend(chunk_type) => write!(f, "{} chunk after IEND", chunk_type),
+            FindingKind::TrailingData(length) => write!(f, "{} bytes of data after IEND", length),
+            FindingKind::OversizedText(chunk_type, length) => {
+                write!(f, "{} chunk of {} bytes", chunk_type, length)
+            },
+            FindingKind::HighEntropy(chunk_type, entropy) => {
+                write!(f, "{} chunk with {:.2} bits of entropy per byte", chunk_type, entropy)
+            },
+            FindingKind::PngmePayload(chunk_type, format) => write!(f, "{} chunk holds a pngme {}", chunk_type, format),
+            FindingKind::LsbMessage(length) => write!(f, "pixels hold a pngme message of {} bytes", length),
+        }
+    }
+}
+
+/// A single finding; findings in the pixels carry no chunk index
+#[derive(Debug, Clone, PartialEq)]
+pub struct Finding {
+    pub chunk_index: Option<usize>,
+    pub offset: usize,
+    pub kind: FindingKind,
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.chunk_index {
+            Some(index) => write!(f, "[{:3}] chunk {} at offset 0x{:05x}: {}", self.kind.score(), index, self.offset, self.kind),
+            None => write!(f, "[{:3}] offset 0x{:05x}: {}", self.kind.score(), self.offset, self.kind),
+        }
+    }
+}
+
+/// Everything found in one PNG
+#[derive(Debug, Clone, Default)]
+pub struct ScanReport {
+    pub findings: Vec<Finding>,
+}
+
+impl ScanReport {
+    /// the highest score of all findings; 0 if nothing was found
+    pub fn score(&self) -> u8 {
+        self.findings.iter().map(|finding| finding.kind.score()).max().unwrap_or(0)
+    }
+}
+
+/// Shannon entropy of some bytes, in bits per byte
+pub fn entropy(data: &[u8]) -> f64 {
+    let mut counts = [0usize; 256];
+    for &byte in data {
+        counts[byte as usize] += 1;
+    }
+    counts
+        .iter()
+        .filter(|&&count| count > 0)
+        .map(|&count| {
+            let p = count as f64 / data.len() as f64;
+            -p * p.log2()
+        })
+        .sum()
+}
+
+/// name the pngme format a payload is written in, if any; signed payloads are named by what is signed
+pub fn pngme_format(data: &[u8]) -> Option<&'static str> {
+    if signature::is_signed(data) {
+        // pngme signs envelopes and seals, older versions signed raw messages
+        let payload = signature::payload(data);
+        return Some(if envelope::is_envelope(payload) {
+            "signed envelope"
+        } else if seal::is_seal(payload) {
+            "signed seal"
+        } else {
+            "signed message"
+        });
+    }
+    if fragment::is_fragment(data) {
+        Some("fragment")
+    } else if envelope::is_envelope(data) {
+        Some("envelope")
+    } else if bundle::is_bundle(data) {
+        Some("bundle")
+    } else if seal::is_seal(data) {
+        Some("seal")
+    } else if crypto::is_encrypted(data) || recipients::is_encrypted(data) {
+        Some("encrypted message")
+    } else {
+        None
+    }
+}
+
+// the text of a tEXt chunk, decoded from base64 if possible
+fn text_payload(chunk: &Chunk) -> Option<Vec<u8>> {
+    let data = chunk.data();
+    let separator = data.iter().position(|&byte| byte == 0)?;
+    BASE64.decode(&data[separator + 1..]).ok()
+}
+
+fn scan_chunk(chunk: &Chunk, after_iend: bool) -> Vec<FindingKind> {
+    let chunk_type = chunk.chunk_type().to_string();
+    let data = chunk.data();
+    let mut findings = Vec::new();
+
+    if after_iend {
+        findings.push(FindingKind::ChunkAfterIend(chunk_type.clone()));
+    }
+    if !KNOWN_CHUNK_TYPES.contains(&chunk_type.as_str()) {
+        if chunk.chunk_type().is_public() {
+            findings.push(FindingKind::UnknownChunk(chunk_type.clone()));
+        } else {
+            findings.push(FindingKind::PrivateChunk(chunk_type.clone()));
+        }
+    }
+    if TEXT_CHUNK_TYPES.contains(&chunk_type.as_str()) && data.len() > MAX_TEXT_LENGTH {
+        findings.push(FindingKind::OversizedText(chunk_type.clone(), data.len()));
+    }
+
+    let format = match chunk_type.as_str() {
+        "tEXt" => text_payload(chunk).and_then(|payload| pngme_format(&payload)),
+        _ => pngme_format(data),
+    };
+    if let Some(format) = format {
+        findings.push(FindingKind::PngmePayload(chunk_type, format));
+    } else if !COMPRESSED_CHUNK_TYPES.contains(&chunk_type.as_str()) && data.len() >= MIN_ENTROPY_LENGTH {
+        let entropy = entropy(data);
+        if entropy >= MIN_ENTROPY {
+            findings.push(FindingKind::HighEntropy(chunk_type, entropy));
+        }
+    }
+    findings
+}
+
+/// scan the bytes of a PNG file for hidden data;
+/// fails only if the data is no PNG, or its chunks are corrupt before IEND
+pub fn scan(bytes: &[u8]) -> Result<ScanReport> {
+    if bytes.len() < Png::STANDARD_HEADER.len() {
+        return Err(anyhow!(PngMeError::PNGError::TooSmall));
+    }
+    if bytes[..Png::STANDARD_HEADER.len()] != Png::STANDARD_HEADER {
+        return Err(anyhow!(PngMeError::PNGError::InvalidSignature));
+    }
+
+    let mut report = ScanReport::default();
+    let mut chunks = Vec::new();
+    let mut offset = Png::STANDARD_HEADER.len();
+    let mut after_iend = false;
+    while offset < bytes.len() {
+        let chunk = match Chunk::from_bytes_at(&bytes[offset..], offset) {
+            Ok(chunk) => chunk,
+            // anything may follow IEND, decoders never read it
+            Err(_) if after_iend => {
+                report.findings.push(Finding { chunk_index: None, offset, kind: FindingKind::TrailingData(bytes.len() - offset) });
+                break;
+            },
+            Err(err) => return Err(err),
+        };
+        for kind in scan_chunk(&chunk, after_iend) {
+            report.findings.push(Finding { chunk_index: Some(chunks.len()), offset, kind });
+        }
+        after_iend |= chunk.chunk_type().to_string() == "IEND";
+        offset += chunk.length() as usize + Chunk::METADATA_LENGTH;
+        chunks.push(chunk);
+    }
+
+    // images the pixels cannot be read from simply hold no LSB message
+    let png = Png::from_chunks(chunks);
+    let pixels = png.ihdr()
+        .and_then(|ihdr| lsb::check_carrier(&ihdr))
+        .and_then(|_| pixels::decode(&png, pixels::DEFAULT_MAX_IMAGE_DATA_LENGTH));
+    if let Ok(message) = pixels.and_then(|pixels| lsb::extract(&pixels)) {
+        let offset = png.chunk_offsets()[png.chunks_by_type("IDAT").next().map_or(0, |(index, _)| index)];
+        report.findings.push(Finding { chunk_index: None, offset, kind: FindingKind::LsbMessage(message.len()) });
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::envelope::{ContentType, Envelope, EnvelopeFlags, EnvelopeHeader};
+    use crate::test_utils::{chunk, sample_png};
+
+    fn kinds(report: &ScanReport) -> Vec<&FindingKind> {
+        report.findings.iter().map(|finding| &finding.kind).collect()
+    }
+
+    #[test]
+    fn test_entropy() {
+        assert_eq!(entropy(&[7; 100]), 0.0);
+        let all_bytes: Vec<u8> = (0..=255).collect();
+        assert!((entropy(&all_bytes) - 8.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_sample_image_secret_after_iend() {
+        let png = sample_png();
+        let report = scan(&png.as_bytes()).unwrap();
+        let findings = kinds(&report);
+        assert!(findings.contains(&&FindingKind::ChunkAfterIend(String::from("RuSt"))));
+        assert!(findings.contains(&&FindingKind::PrivateChunk(String::from("RuSt"))));
+        assert_eq!(report.score(), 80);
+        assert!(report.findings.iter().all(|finding| finding.chunk_index == Some(20)));
+    }
+
+    #[test]
+    fn test_clean_image() {
+        let mut png = sample_png();
+        png.remove_chunk("RuSt").unwrap();
+        let report = scan(&png.as_bytes()).unwrap();
+        assert!(report.findings.is_empty());
+        assert_eq!(report.score(), 0);
+    }
+
+    #[test]
+    fn test_trailing_data() {
+        let mut png = sample_png();
+        png.remove_chunk("RuSt").unwrap();
+        let mut bytes = png.as_bytes();
+        bytes.extend_from_slice(b"not a chunk at all");
+        let report = scan(&bytes).unwrap();
+        assert_eq!(kinds(&report), vec![&FindingKind::TrailingData(18)]);
+        assert_eq!(report.findings[0].offset, png.as_bytes().len());
+    }
+
+    #[test]
+    fn test_pngme_envelope_and_entropy() {
+        let mut png = sample_png();
+        png.remove_chunk("RuSt").unwrap();
+        let envelope = Envelope { header: EnvelopeHeader::new(EnvelopeFlags::default(), ContentType::Text, None), body: b"hi".to_vec() };
+        png.insert_chunk(1, chunk("ruSt", envelope.to_bytes().unwrap())).unwrap();
+        let noise = crypto::encrypt(&[0; 1024], "pw").unwrap()[crypto::HEADER_LENGTH..].to_vec();
+        png.insert_chunk(1, chunk("noIz", noise)).unwrap();
+        let report = scan(&png.as_bytes()).unwrap();
+        let findings = kinds(&report);
+        assert!(findings.contains(&&FindingKind::PngmePayload(String::from("ruSt"), "envelope")));
+        assert!(findings.iter().any(|kind| matches!(kind, FindingKind::HighEntropy(chunk_type, _) if chunk_type == "noIz")));
+        assert_eq!(report.score(), 100);
+    }
+
+    #[test]
+    fn test_signed_payloads_are_named_by_what_is_signed() {
+        let signing_key = signature::parse_signing_key(&signature::generate_signing_key().to_key_file()).unwrap();
+        let png = sample_png();
+        let sealed = seal::seal(&png, &signing_key).unwrap();
+        assert_eq!(pngme_format(sealed.data()), Some("signed seal"));
+        assert_eq!(pngme_format(signature::payload(sealed.data())), Some("seal"));
+
+        let envelope = Envelope { header: EnvelopeHeader::new(EnvelopeFlags::default(), ContentType::Text, None), body: b"hi".to_vec() };
+        let signed = signature::sign(sealed.chunk_type(), &envelope.to_bytes().unwrap(), &signing_key);
+        assert_eq!(pngme_format(&signed), Some("signed envelope"));
+        let signed = signature::sign(sealed.chunk_type(), b"raw message", &signing_key);
+        assert_eq!(pngme_format(&signed), Some("signed message"));
+
+        // raw messages merely starting with the seal magic
+        assert_eq!(pngme_format(b"PMSL is where I keep my sleep log"), None);
+    }
+
+    #[test]
+    fn test_text_chunks() {
+        let mut png = sample_png();
+        png.remove_chunk("RuSt").unwrap();
+        let envelope = Envelope { header: EnvelopeHeader::new(EnvelopeFlags::default(), ContentType::Text, None), body: b"hi".to_vec() };
+        let payload = BASE64.encode(envelope.to_bytes().unwrap());
+        png.insert_chunk(1, chunk("tEXt", [b"Comment\0".as_slice(), payload.as_bytes()].concat())).unwrap();
+        png.insert_chunk(1, chunk("tEXt", [b"Comment\0".as_slice(), &[b'a'; MAX_TEXT_LENGTH]].concat())).unwrap();
+        let report = scan(&png.as_bytes()).unwrap();
+        assert_eq!(kinds(&report), vec![
+            &FindingKind::OversizedText(String::from("tEXt"), MAX_TEXT_LENGTH + 8),
+            &FindingKind::PngmePayload(String::from("tEXt"), "envelope"),
+        ]);
+    }
+
+    #[test]
+    fn test_unknown_public_chunk() {
+        let mut png = sample_png();
+        png.remove_chunk("RuSt").unwrap();
+        png.insert_chunk(1, chunk("hELo", b"public".to_vec())).unwrap();
+        let report = scan(&png.as_bytes()).unwrap();
+        assert_eq!(kinds(&report), vec![&FindingKind::UnknownChunk(String::from("hELo"))]);
+    }
+
+    #[test]
+    fn test_lsb_message() {
+        let mut png = sample_png();
+        png.remove_chunk("RuSt").unwrap();
+        let mut pixels = pixels::decode(&png, pixels::DEFAULT_MAX_IMAGE_DATA_LENGTH).unwrap();
+        lsb::embed(&mut pixels, b"in the pixels", 1).unwrap();
+        pixels::write_image_data(&mut png, &pixels).unwrap();
+        let report = scan(&png.as_bytes()).unwrap();
+        assert_eq!(kinds(&report), vec![&FindingKind::LsbMessage(13)]);
+    }
+
+    #[test]
+    fn test_corrupt_chunk_before_iend() {
+        let mut bytes = sample_png().as_bytes();
+        bytes[40] ^= 0xff;
+        assert!(scan(&bytes).is_err());
+        assert!(scan(b"GIF89a").is_err());
+    }
+}
@@ -0,0 +1,365 @@
+use std::fmt;
+use std::str::FromStr;
+
+use ed25519_dalek::SigningKey;
+use sha2::{Digest, Sha256};
+
+use anyhow::{anyhow, Result};
+
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::error as PngMeError;
+use crate::png::Png;
+use crate::signature::{self, SignatureStatus, TrustedKeys};
+
+/*
+    Whole-image integrity seals: a signed chunk recording a SHA-256 digest of every other chunk,
+    so any later change to the pixels or the metadata can be pinned down to single chunks.
+
+    All chunks except seal chunks are covered, in file order, each digested over length, type and data.
+    The seal payload is signed with `signature::sign`, so it carries the signer's public key in front:
+
+        magic "PMSL"         4 bytes
+        version              1 byte
+        chunk count          4 bytes, big endian
+        per sealed chunk    36 bytes: chunk type (4) and SHA-256 digest (32)
+        image digest        32 bytes: SHA-256 over all per-chunk entries
+
+    Chunks are matched between seal and file by chunk type and occurrence, i.e. the second IDAT
+    is compared to the second sealed IDAT, which keeps the report meaningful when chunks are
+    inserted or removed in between. Matching alone does not notice a chunk moved elsewhere,
+    e.g. a tEXt chunk moved behind IEND, so the image digest is recomputed as well; if it differs,
+    the matched chunks that left the sealed order are reported as moved.
+*/
+
+/// chunk type of the seal chunk: ancillary, private, safe to copy
+pub const SEAL_CHUNK_TYPE: &str = "seAl";
+/// identifies seal payloads
+pub const MAGIC: [u8; 4] = *b"PMSL";
+/// current version of the seal format
+pub const VERSION: u8 = 1;
+
+const DIGEST_LENGTH: usize = 32;
+const ENTRY_LENGTH: usize = 4 + DIGEST_LENGTH;
+const HEADER_LENGTH: usize = 4 + 1 + 4;
+
+// type & digest of a single sealed chunk
+type SealEntry = ([u8; 4], [u8; DIGEST_LENGTH]);
+
+// digest of a single chunk, covering length, type and data
+fn chunk_digest(chunk: &Chunk) -> [u8; DIGEST_LENGTH] {
+    Sha256::new()
+        .chain_update(chunk.length().to_be_bytes())
+        .chain_update(chunk.chunk_type().bytes())
+        .chain_update(chunk.data())
+        .finalize()
+        .into()
+}
+
+// the entries of all chunks covered by a seal, in canonical (file) order
+fn seal_entries(png: &Png) -> Vec<SealEntry> {
+    png.chunks()
+        .iter()
+        .filter(|chunk| chunk.chunk_type().to_string() != SEAL_CHUNK_TYPE)
+        .map(|chunk| (chunk.chunk_type().bytes(), chunk_digest(chunk)))
+        .collect()
+}
+
+// digest over all entries, identifying the sealed image as a whole
+fn image_digest(entries: &[SealEntry]) -> [u8; DIGEST_LENGTH] {
+    entries
+        .iter()
+        .fold(Sha256::new(), |hasher, (chunk_type, digest)| hasher.chain_update(chunk_type).chain_update(digest))
+        .finalize()
+        .into()
+}
+
+/// check whether a payload (without signature) starts with the header of a seal of this version
+pub fn is_seal(payload: &[u8]) -> bool {
+    payload.starts_with(&MAGIC) && payload.len() >= HEADER_LENGTH && payload[4] == VERSION
+}
+
+/// compute a seal over all chunks of a PNG and sign it; the PNG itself is left untouched
+pub fn seal(png: &Png, signing_key: &SigningKey) -> Result<Chunk> {
+    let entries = seal_entries(png);
+    let mut payload: Vec<u8> = MAGIC
+        .iter()
+        .chain([VERSION].iter())
+        .chain((entries.len() as u32).to_be_bytes().iter())
+        .copied()
+        .collect();
+    for (chunk_type, digest) in &entries {
+        payload.extend(chunk_type);
+        payload.extend(digest);
+    }
+    payload.extend(image_digest(&entries));
+
+    let chunk_type = ChunkType::from_str(SEAL_CHUNK_TYPE)?;
+    let signed = signature::sign(&chunk_type, &payload, signing_key);
+    Ok(Chunk::new(chunk_type, signed))
+}
+
+// read the entries recorded in a seal payload (without signature)
+fn parse_seal(payload: &[u8]) -> Result<Vec<SealEntry>> {
+    if !payload.starts_with(&MAGIC) || payload.len() < HEADER_LENGTH {
+        return Err(anyhow!(PngMeError::SealError::InvalidSeal(String::from("missing seal header"))));
+    }
+    if payload[4] != VERSION {
+        return Err(anyhow!(PngMeError::SealError::UnsupportedVersion(payload[4])));
+    }
+    let count = u32::from_be_bytes([payload[5], payload[6], payload[7], payload[8]]) as usize;
+    let body = &payload[HEADER_LENGTH..];
+    if count.checked_mul(ENTRY_LENGTH).and_then(|length| length.checked_add(DIGEST_LENGTH)) != Some(body.len()) {
+        return Err(anyhow!(PngMeError::SealError::InvalidSeal(format!(
+            "{} bytes do not hold {} chunk entries",
+            body.len(),
+            count
+        ))));
+    }
+    let (entries, recorded_digest) = body.split_at(count * ENTRY_LENGTH);
+    let entries: Vec<SealEntry> = entries
+        .chunks_exact(ENTRY_LENGTH)
+        .map(|entry| {
+            let (chunk_type, digest) = entry.split_at(4);
+            (
+                chunk_type.try_into().unwrap_or_default(),
+                digest.try_into().unwrap_or_default(),
+            )
+        })
+        .collect();
+    if image_digest(&entries) != recorded_digest {
+        return Err(anyhow!(PngMeError::SealError::InvalidSeal(String::from("image digest does not match the chunk entries"))));
+    }
+    Ok(entries)
+}
+
+/// A difference between the sealed chunks and the chunks found now
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ChunkChange {
+    // chunk not covered by the seal: current index & chunk type
+    Added(usize, String),
+    // sealed chunk missing now: sealed index & chunk type
+    Removed(usize, String),
+    // chunk data differs from the sealed digest: current index & chunk type
+    Modified(usize, String),
+    // chunk is out of its sealed order: current index & chunk type
+    Moved(usize, String),
+}
+
+impl fmt::Display for ChunkChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ChunkChange::Added(index, chunk_type) => write!(f, "added:    chunk {} ({})", index, chunk_type),
+            ChunkChange::Removed(index, chunk_type) => write!(f, "removed:  chunk {} ({}) of the sealed image", index, chunk_type),
+            ChunkChange::Modified(index, chunk_type) => write!(f, "modified: chunk {} ({})", index, chunk_type),
+            ChunkChange::Moved(index, chunk_type) => write!(f, "moved:    chunk {} ({})", index, chunk_type),
+        }
+    }
+}
+
+/// Outcome of checking a PNG against its seal
+#[derive(Debug, Clone)]
+pub struct SealReport {
+    /// who sealed the image; a valid signature, possibly by an untrusted key
+    pub signature: SignatureStatus,
+    /// every chunk added, removed, modified or moved since sealing, empty if the image is unchanged
+    pub changes: Vec<ChunkChange>,
+}
+
+impl SealReport {
+    /// the seal was made by a trusted key and nothing changed since
+    pub fn is_intact(&self) -> bool {
+        self.signature.is_trusted() && self.changes.is_empty()
+    }
+}
+
+// positions into `sequence` that are not part of its longest increasing subsequence,
+// i.e. the fewest elements to move to restore the order
+fn out_of_order(sequence: &[usize]) -> Vec<usize> {
+    // tails[k]: position of the smallest last element of an increasing subsequence of length k + 1
+    let mut tails: Vec<usize> = Vec::new();
+    let mut previous = vec![None; sequence.len()];
+    for (position, value) in sequence.iter().enumerate() {
+        let length = tails.partition_point(|&tail| sequence[tail] < *value);
+        previous[position] = length.checked_sub(1).map(|length| tails[length]);
+        if length == tails.len() {
+            tails.push(position);
+        } else {
+            tails[length] = position;
+        }
+    }
+    let mut in_order = vec![false; sequence.len()];
+    let mut position = tails.last().copied();
+    while let Some(current) = position {
+        in_order[current] = true;
+        position = previous[current];
+    }
+    (0..sequence.len()).filter(|&position| !in_order[position]).collect()
+}
+
+/// check a PNG against its seal chunk, reporting the signer and all changes since sealing
+pub fn verify(png: &Png, trusted_keys: &TrustedKeys) -> Result<SealReport> {
+    let mut seals = png.chunks_by_type(SEAL_CHUNK_TYPE);
+    let (_, seal_chunk) = seals.next().ok_or(PngMeError::SealError::MissingSeal)?;
+    if seals.next().is_some() {
+        return Err(anyhow!(PngMeError::SealError::MultipleSeals));
+    }
+    let signature = signature::verify(seal_chunk.chunk_type(), seal_chunk.data(), trusted_keys);
+    match signature {
+        SignatureStatus::Unsigned => return Err(anyhow!(PngMeError::SealError::InvalidSeal(String::from("seal is not signed")))),
+        // the recorded digests cannot be relied on
+        SignatureStatus::Invalid => return Err(anyhow!(PngMeError::SealError::InvalidSeal(String::from("bad signature")))),
+        SignatureStatus::Trusted(_) | SignatureStatus::Untrusted(_) => {},
+    }
+    let sealed = parse_seal(signature::payload(seal_chunk.data()))?;
+
+    // current chunks with their index in the PNG, seal chunks excluded
+    let current: Vec<(usize, [u8; 4], [u8; DIGEST_LENGTH])> = png.chunks()
+        .iter()
+        .enumerate()
+        .filter(|(_, chunk)| chunk.chunk_type().to_string() != SEAL_CHUNK_TYPE)
+        .map(|(index, chunk)| (index, chunk.chunk_type().bytes(), chunk_digest(chunk)))
+        .collect();
+
+    let type_name = |chunk_type: &[u8; 4]| String::from_utf8_lossy(chunk_type).into_owned();
+    let mut changes = Vec::new();
+    // (position in `current`, sealed index) of every chunk matched to a sealed one
+    let mut matched = Vec::new();
+    let mut chunk_types: Vec<[u8; 4]> = Vec::new();
+    for chunk_type in sealed.iter().map(|(chunk_type, _)| chunk_type).chain(current.iter().map(|(_, chunk_type, _)| chunk_type)) {
+        if !chunk_types.contains(chunk_type) {
+            chunk_types.push(*chunk_type);
+        }
+    }
+    for chunk_type in &chunk_types {
+        let mut sealed_of_type = sealed.iter().enumerate().filter(|(_, (sealed_type, _))| sealed_type == chunk_type);
+        let mut current_of_type = current.iter().enumerate().filter(|(_, (_, current_type, _))| current_type == chunk_type);
+        loop {
+            match (sealed_of_type.next(), current_of_type.next()) {
+                (Some((sealed_index, (_, sealed_digest))), Some((position, (index, _, digest)))) => {
+                    if sealed_digest != digest {
+                        changes.push(ChunkChange::Modified(*index, type_name(chunk_type)));
+                    }
+                    matched.push((position, sealed_index));
+                },
+                (Some((sealed_index, _)), None) => changes.push(ChunkChange::Removed(sealed_index, type_name(chunk_type))),
+                (None, Some((_, (index, _, _)))) => changes.push(ChunkChange::Added(*index, type_name(chunk_type))),
+                (None, None) => break,
+            }
+        }
+    }
+
+    // the per-type matching cannot see chunks moved across others, the image digest can
+    if image_digest(&seal_entries(png)) != image_digest(&sealed) {
+        matched.sort_unstable();
+        let sealed_order: Vec<usize> = matched.iter().map(|(_, sealed_index)| *sealed_index).collect();
+        for position in out_of_order(&sealed_order) {
+            let (index, chunk_type, _) = &current[matched[position].0];
+            changes.push(ChunkChange::Moved(*index, type_name(chunk_type)));
+        }
+    }
+    Ok(SealReport { signature, changes })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::png::ChunkPosition;
+    use crate::signature::{generate_signing_key, parse_signing_key};
+    use crate::test_utils::chunk;
+
+    fn testing_png() -> Png {
+        Png::from_chunks(vec![
+            chunk("IHDR", vec![0, 0, 0, 1, 0, 0, 0, 1, 8, 6, 0, 0, 0]),
+            chunk("tEXt", b"Comment\0original".to_vec()),
+            chunk("IDAT", b"first".to_vec()),
+            chunk("IDAT", b"second".to_vec()),
+            chunk("IEND", vec![]),
+        ])
+    }
+
+    // seal the testing PNG, returning it together with keys trusting its signer
+    fn sealed_png() -> (Png, TrustedKeys) {
+        let keypair = generate_signing_key();
+        let signing_key = parse_signing_key(&keypair.to_key_file()).unwrap();
+        let mut png = testing_png();
+        let seal_chunk = seal(&png, &signing_key).unwrap();
+        png.insert_chunk_at(ChunkPosition::BeforeIend, seal_chunk).unwrap();
+        let trusted_keys = TrustedKeys::parse(&format!("{} Alice", keypair.public_key)).unwrap();
+        (png, trusted_keys)
+    }
+
+    #[test]
+    fn test_unchanged_image_is_intact() {
+        let (png, trusted_keys) = sealed_png();
+        let report = verify(&png, &trusted_keys).unwrap();
+        assert_eq!(report.signature, SignatureStatus::Trusted(String::from("Alice")));
+        assert!(report.changes.is_empty());
+        assert!(report.is_intact());
+        assert!(!verify(&png, &TrustedKeys::default()).unwrap().is_intact());
+    }
+
+    #[test]
+    fn test_reports_added_removed_and_modified_chunks() {
+        let (png, trusted_keys) = sealed_png();
+        let mut chunks = png.chunks().to_vec();
+        // modify the second IDAT, drop the text chunk, add a message
+        chunks[3] = chunk("IDAT", b"tampered".to_vec());
+        chunks.remove(1);
+        chunks.insert(3, chunk("ruSt", b"sneaky".to_vec()));
+        let report = verify(&Png::from_chunks(chunks), &trusted_keys).unwrap();
+        assert_eq!(
+            report.changes,
+            vec![
+                ChunkChange::Removed(1, String::from("tEXt")),
+                ChunkChange::Modified(2, String::from("IDAT")),
+                ChunkChange::Added(3, String::from("ruSt")),
+            ]
+        );
+        assert!(!report.is_intact());
+    }
+
+    #[test]
+    fn test_reports_moved_chunks() {
+        let (png, trusted_keys) = sealed_png();
+        let mut chunks = png.chunks().to_vec();
+        // move the text chunk behind IEND
+        let text = chunks.remove(1);
+        chunks.push(text);
+        let report = verify(&Png::from_chunks(chunks), &trusted_keys).unwrap();
+        assert_eq!(report.changes, vec![ChunkChange::Moved(5, String::from("tEXt"))]);
+        assert!(!report.is_intact());
+
+        // seal chunks are not covered, moving the seal itself is no change
+        let mut chunks = png.chunks().to_vec();
+        chunks.swap(4, 5);
+        assert!(verify(&Png::from_chunks(chunks), &trusted_keys).unwrap().is_intact());
+    }
+
+    #[test]
+    fn test_out_of_order() {
+        assert!(out_of_order(&[]).is_empty());
+        assert!(out_of_order(&[0, 1, 2, 3]).is_empty());
+        assert_eq!(out_of_order(&[0, 2, 3, 4, 1]), vec![4]);
+        assert_eq!(out_of_order(&[3, 0, 1, 2]), vec![0]);
+    }
+
+    #[test]
+    fn test_tampered_seal() {
+        let (png, trusted_keys) = sealed_png();
+        let mut chunks = png.chunks().to_vec();
+        let seal_index = chunks.iter().position(|chunk| chunk.chunk_type().to_string() == SEAL_CHUNK_TYPE).unwrap();
+        let mut data = chunks[seal_index].data().clone();
+        let last = data.len() - 1;
+        data[last] ^= 1;
+        chunks[seal_index] = chunk(SEAL_CHUNK_TYPE, data);
+        let err = verify(&Png::from_chunks(chunks), &trusted_keys).unwrap_err();
+        assert!(matches!(err.downcast_ref::<PngMeError::SealError>(), Some(PngMeError::SealError::InvalidSeal(_))));
+    }
+
+    #[test]
+    fn test_missing_seal() {
+        let err = verify(&testing_png(), &TrustedKeys::default()).unwrap_err();
+        assert!(matches!(err.downcast_ref::<PngMeError::SealError>(), Some(PngMeError::SealError::MissingSeal)));
+    }
+}
@@ -0,0 +1,281 @@
+use std::fmt;
+
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use chacha20poly1305::aead::{rand_core::RngCore, OsRng};
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey, PUBLIC_KEY_LENGTH, SECRET_KEY_LENGTH, SIGNATURE_LENGTH};
+
+use anyhow::{anyhow, Result};
+
+use crate::chunk_type::ChunkType;
+use crate::error as PngMeError;
+
+/*
+    Ed25519 signatures over message chunks, telling who wrote a message.
+
+    A signed chunk carries a small header in front of its payload:
+
+        magic "PMES"     4 bytes
+        version          1 byte
+        public key      32 bytes
+        signature       64 bytes
+        payload          remaining bytes
+
+    The signature covers the chunk type followed by the payload, so a signed payload cannot be
+    moved into a chunk of another type unnoticed. Encrypted payloads are signed after encryption,
+    which allows verifying the signer without being able to read the message.
+
+    Key files are plain text: comment lines start with `#`, signing key files contain the base64
+    encoded 32 byte secret key, and trusted-keys files contain one `<base64 public key> <name>` per line.
+*/
+
+/// identifies payloads signed by pngme
+pub const MAGIC: [u8; 4] = *b"PMES";
+/// current version of the signed payload format
+pub const VERSION: u8 = 1;
+/// magic, version, public key and signature
+pub const HEADER_LENGTH: usize = 4 + 1 + PUBLIC_KEY_LENGTH + SIGNATURE_LENGTH;
+
+/// A freshly generated Ed25519 key pair, both halves base64 encoded
+pub struct SigningKeypair {
+    pub secret_key: String,
+    pub public_key: String,
+}
+
+impl SigningKeypair {
+    /// render the key pair as signing key file, as read by `parse_signing_key`
+    pub fn to_key_file(&self) -> String {
+        format!("# public key: {}\n{}\n", self.public_key, self.secret_key)
+    }
+}
+
+/// create a new random Ed25519 signing key
+pub fn generate_signing_key() -> SigningKeypair {
+    let mut secret = [0u8; SECRET_KEY_LENGTH];
+    OsRng.fill_bytes(&mut secret);
+    let signing_key = SigningKey::from_bytes(&secret);
+    SigningKeypair {
+        secret_key: BASE64.encode(signing_key.to_bytes()),
+        public_key: BASE64.encode(signing_key.verifying_key().to_bytes()),
+    }
+}
+
+// the lines of a key file that are neither empty nor comments
+fn key_lines(key_file: &str) -> impl Iterator<Item = &str> {
+    key_file
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+}
+
+// decode a base64 encoded public key
+fn parse_public_key(encoded: &str) -> Result<VerifyingKey> {
+    let bytes: [u8; PUBLIC_KEY_LENGTH] = BASE64
+        .decode(encoded)
+        .ok()
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or_else(|| PngMeError::SignatureError::InvalidKey(format!("not a base64 encoded public key: {}", encoded)))?;
+    VerifyingKey::from_bytes(&bytes)
+        .map_err(|err| anyhow!(PngMeError::SignatureError::InvalidKey(err.to_string())))
+}
+
+/// read the secret key from the contents of a signing key file
+pub fn parse_signing_key(key_file: &str) -> Result<SigningKey> {
+    let encoded = key_lines(key_file)
+        .next()
+        .ok_or_else(|| PngMeError::SignatureError::InvalidKey(String::from("no signing key found")))?;
+    let bytes: [u8; SECRET_KEY_LENGTH] = BASE64
+        .decode(encoded)
+        .ok()
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or_else(|| PngMeError::SignatureError::InvalidKey(String::from("not a base64 encoded signing key")))?;
+    Ok(SigningKey::from_bytes(&bytes))
+}
+
+/// Public keys whose signatures are trusted, each with the name of its owner
+#[derive(Debug, Clone, Default)]
+pub struct TrustedKeys {
+    keys: Vec<(VerifyingKey, String)>,
+}
+
+impl TrustedKeys {
+    /// read the contents of a trusted-keys file: one `<base64 public key> <name>` per line
+    pub fn parse(trusted_keys_file: &str) -> Result<Self> {
+        let keys = key_lines(trusted_keys_file)
+            .map(|line| {
+                let (encoded, name) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+                Ok((parse_public_key(encoded)?, name.trim().to_string()))
+            })
+            .collect::<Result<Vec<(VerifyingKey, String)>>>()?;
+        Ok(TrustedKeys { keys })
+    }
+
+    /// name of the owner of a public key, if it is trusted
+    pub fn name_of(&self, public_key: &VerifyingKey) -> Option<&str> {
+        self.keys
+            .iter()
+            .find(|(key, _)| key == public_key)
+            .map(|(_, name)| name.as_str())
+    }
+}
+
+/// Outcome of checking the signature of a chunk
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SignatureStatus {
+    // the chunk carries no signature
+    Unsigned,
+    // valid signature by a trusted key: name of its owner
+    Trusted(String),
+    // valid signature by a key not in the trusted keys: base64 encoded public key
+    Untrusted(String),
+    // signature present, but it does not match chunk type & payload
+    Invalid,
+}
+
+impl SignatureStatus {
+    /// only signatures by trusted keys count as verified
+    pub fn is_trusted(&self) -> bool {
+        matches!(self, SignatureStatus::Trusted(_))
+    }
+}
+
+impl fmt::Display for SignatureStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SignatureStatus::Unsigned => write!(f, "UNSIGNED"),
+            SignatureStatus::Trusted(name) => write!(f, "signed by {}", name),
+            SignatureStatus::Untrusted(public_key) => write!(f, "signed by UNTRUSTED key {}", public_key),
+            SignatureStatus::Invalid => write!(f, "BAD SIGNATURE"),
+        }
+    }
+}
+
+/// check whether a payload starts with the header of a signed pngme payload;
+/// raw messages merely starting with the magic, without a header of this version, are not
+pub fn is_signed(data: &[u8]) -> bool {
+    data.starts_with(&MAGIC) && data.len() >= HEADER_LENGTH && data[4] == VERSION
+}
+
+/// sign a payload for a chunk of the given type; returns signature header and payload
+pub fn sign(chunk_type: &ChunkType, payload: &[u8], signing_key: &SigningKey) -> Vec<u8> {
+    let signature = signing_key.sign(&signed_message(chunk_type, payload));
+    MAGIC
+        .iter()
+        .chain([VERSION].iter())
+        .chain(signing_key.verifying_key().as_bytes().iter())
+        .chain(signature.to_bytes().iter())
+        .chain(payload.iter())
+        .copied()
+        .collect()
+}
+
+/// the payload of a chunk, with the signature header removed if there is one
+pub fn payload(data: &[u8]) -> &[u8] {
+    if is_signed(data) { &data[HEADER_LENGTH..] } else { data }
+}
+
+/// check the signature of chunk data against a set of trusted keys
+pub fn verify(chunk_type: &ChunkType, data: &[u8], trusted_keys: &TrustedKeys) -> SignatureStatus {
+    if !is_signed(data) {
+        return SignatureStatus::Unsigned;
+    }
+    let public_key: [u8; PUBLIC_KEY_LENGTH] = data[5..5 + PUBLIC_KEY_LENGTH]
+        .try_into()
+        .unwrap_or([0; PUBLIC_KEY_LENGTH]);
+    let signature: [u8; SIGNATURE_LENGTH] = data[5 + PUBLIC_KEY_LENGTH..HEADER_LENGTH]
+        .try_into()
+        .unwrap_or([0; SIGNATURE_LENGTH]);
+    let Ok(public_key) = VerifyingKey::from_bytes(&public_key) else {
+        return SignatureStatus::Invalid;
+    };
+
+    let message = signed_message(chunk_type, payload(data));
+    if public_key.verify(&message, &Signature::from_bytes(&signature)).is_err() {
+        return SignatureStatus::Invalid;
+    }
+    match trusted_keys.name_of(&public_key) {
+        Some(name) => SignatureStatus::Trusted(name.to_string()),
+        None => SignatureStatus::Untrusted(BASE64.encode(public_key.as_bytes())),
+    }
+}
+
+// signatures cover chunk type & payload
+fn signed_message(chunk_type: &ChunkType, payload: &[u8]) -> Vec<u8> {
+    chunk_type
+        .bytes()
+        .iter()
+        .chain(payload.iter())
+        .copied()
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::chunk_type;
+
+    fn trusted(keypair: &SigningKeypair, name: &str) -> TrustedKeys {
+        TrustedKeys::parse(&format!("# team keys\n{} {}\n", keypair.public_key, name)).unwrap()
+    }
+
+    #[test]
+    fn test_sign_and_verify() {
+        let keypair = generate_signing_key();
+        let signing_key = parse_signing_key(&keypair.to_key_file()).unwrap();
+        let signed = sign(&chunk_type("ruSt"), b"signed, sealed, delivered", &signing_key);
+
+        assert!(is_signed(&signed));
+        assert_eq!(payload(&signed), b"signed, sealed, delivered");
+        assert_eq!(
+            verify(&chunk_type("ruSt"), &signed, &trusted(&keypair, "Alice Example")),
+            SignatureStatus::Trusted(String::from("Alice Example"))
+        );
+        assert_eq!(
+            verify(&chunk_type("ruSt"), &signed, &TrustedKeys::default()),
+            SignatureStatus::Untrusted(keypair.public_key)
+        );
+    }
+
+    #[test]
+    fn test_unsigned_payload() {
+        assert!(!is_signed(b"plain"));
+        assert_eq!(payload(b"plain"), b"plain");
+        assert_eq!(verify(&chunk_type("ruSt"), b"plain", &TrustedKeys::default()), SignatureStatus::Unsigned);
+
+        // as long as a header and starting with the magic, but no signature of this version
+        let legacy = [&MAGIC[..], &[b' '; HEADER_LENGTH]].concat();
+        assert!(!is_signed(&legacy));
+        assert_eq!(payload(&legacy), legacy.as_slice());
+        assert_eq!(verify(&chunk_type("ruSt"), &legacy, &TrustedKeys::default()), SignatureStatus::Unsigned);
+    }
+
+    #[test]
+    fn test_tampered_payload() {
+        let keypair = generate_signing_key();
+        let signing_key = parse_signing_key(&keypair.to_key_file()).unwrap();
+        let mut signed = sign(&chunk_type("ruSt"), b"signed, sealed, delivered", &signing_key);
+        let last = signed.len() - 1;
+        signed[last] ^= 1;
+        assert_eq!(
+            verify(&chunk_type("ruSt"), &signed, &trusted(&keypair, "Alice")),
+            SignatureStatus::Invalid
+        );
+    }
+
+    #[test]
+    fn test_signature_bound_to_chunk_type() {
+        let keypair = generate_signing_key();
+        let signing_key = parse_signing_key(&keypair.to_key_file()).unwrap();
+        let signed = sign(&chunk_type("ruSt"), b"signed, sealed, delivered", &signing_key);
+        assert_eq!(
+            verify(&chunk_type("ruST"), &signed, &trusted(&keypair, "Alice")),
+            SignatureStatus::Invalid
+        );
+    }
+
+    #[test]
+    fn test_invalid_key_files() {
+        assert!(parse_signing_key("# empty\n").is_err());
+        assert!(parse_signing_key("bm90IGEga2V5").is_err());
+        assert!(TrustedKeys::parse("bm90IGEga2V5 Alice").is_err());
+    }
+}
@@ -0,0 +1,374 @@
+use anyhow::{anyhow, Result};
+
+use crate::error as PngMeError;
+use crate::ihdr::{ColorType, Ihdr};
+use crate::lsb;
+use crate::pixels::{PixelBuffer, Samples};
+use crate::scan;
+
+/*
+    Statistical detection of messages hidden in the least significant bits of the pixel samples,
+    without knowing how or where they were embedded. Each channel is analyzed on its own.
+
+    Chi-square attack (Westfeld & Pfitzmann): replacing LSBs with message bits evens out the counts
+    of the value pairs 2k and 2k+1, which differ in natural images. The test yields the probability
+    that the pairs are as even as embedding makes them; it detects LSBs replaced throughout the image,
+    but hardly messages covering only a part of it.
+
+    RS analysis (Fridrich, Goljan & Du): groups of 4 neighbouring samples are classified as regular
+    or singular by whether flipping the LSBs of some of them makes the group more or less noisy.
+    Natural images keep these proportions when the LSBs are flipped in the opposite direction, LSB
+    embedding does not; the difference estimates the share of samples carrying message bits,
+    also for partial embedding.
+
+    LSB-plane entropy: the LSBs of a channel, packed into bytes, in bits per byte divided by 8.
+    Message bits, being encrypted or compressed, are close to 1; so is the sensor noise of photos,
+    while the LSBs of smooth or synthetic images are far from random.
+*/
+
+// flipping the LSBs of the middle samples of each group
+const RS_MASK: [bool; 4] = [false, true, true, false];
+
+/// Statistics of the LSBs of one channel
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChannelAnalysis {
+    pub channel: usize,
+    pub name: &'static str,
+    /// probability that the LSBs were replaced throughout the channel, from the chi-square attack
+    pub chi_square: f64,
+    /// estimated share of samples carrying message bits, from RS analysis
+    pub rs: f64,
+    /// entropy of the LSB plane, from 0 to 1
+    pub lsb_entropy: f64,
+}
+
+impl ChannelAnalysis {
+    /// how strongly the statistics suggest a hidden message, from 0 to 100
+    pub fn score(&self) -> u8 {
+        (self.chi_square.max(self.rs) * 100.0).round() as u8
+    }
+}
+
+/// name of a channel of a color type
+pub fn channel_name(color_type: ColorType, channel: usize) -> &'static str {
+    match (color_type, channel) {
+        (ColorType::Grayscale | ColorType::GrayscaleAlpha, 0) => "gray",
+        (ColorType::Indexed, 0) => "index",
+        (ColorType::Rgb | ColorType::Rgba, 0) => "red",
+        (ColorType::Rgb | ColorType::Rgba, 1) => "green",
+        (ColorType::Rgb | ColorType::Rgba, 2) => "blue",
+        _ => "alpha",
+    }
+}
+
+// the samples of one channel, row by row
+fn channel_samples(pixels: &PixelBuffer, channel: usize) -> impl Iterator<Item = u16> + '_ {
+    let channels = pixels.channels();
+    (channel..pixels.samples().len()).step_by(channels).map(|index| pixels.samples().get(index))
+}
+
+// natural logarithm of the gamma function, after Lanczos
+fn ln_gamma(x: f64) -> f64 {
+    const COEFFICIENTS: [f64; 6] = [
+        76.18009172947146, -86.50532032941677, 24.01409824083091,
+        -1.231739572450155, 0.1208650973866179e-2, -0.5395239384953e-5,
+    ];
+    let tmp = x + 5.5;
+    let series: f64 = COEFFICIENTS
+        .iter()
+        .enumerate()
+        .map(|(index, coefficient)| coefficient / (x + 1.0 + index as f64))
+        .sum();
+    (2.5066282746310005 * (1.000000000190015 + series) / x).ln() + (x + 0.5) * tmp.ln() - tmp
+}
+
+// regularized upper incomplete gamma function Q(a, x)
+fn gamma_q(a: f64, x: f64) -> f64 {
+    const EPSILON: f64 = 1e-12;
+    const MAX_ITERATIONS: usize = 1000;
+    if x <= 0.0 {
+        return 1.0;
+    }
+    let prefactor = (-x + a * x.ln() - ln_gamma(a)).exp();
+    if x < a + 1.0 {
+        // series for the lower function
+        let (mut term, mut sum, mut n) = (1.0 / a, 1.0 / a, a);
+        for _ in 0..MAX_ITERATIONS {
+            n += 1.0;
+            term *= x / n;
+            sum += term;
+            if term.abs() < sum.abs() * EPSILON {
+                break;
+            }
+        }
+        (1.0 - sum * prefactor).clamp(0.0, 1.0)
+    } else {
+        // continued fraction, by the modified Lentz method
+        let tiny = f64::MIN_POSITIVE / EPSILON;
+        let mut b = x + 1.0 - a;
+        let (mut c, mut d) = (1.0 / tiny, 1.0 / b);
+        let mut fraction = d;
+        for i in 1..MAX_ITERATIONS {
+            let an = -(i as f64) * (i as f64 - a);
+            b += 2.0;
+            d = an * d + b;
+            d = if d.abs() < tiny { 1.0 / tiny } else { 1.0 / d };
+            c = b + an / c;
+            if c.abs() < tiny {
+                c = tiny;
+            }
+            let delta = d * c;
+            fraction *= delta;
+            if (delta - 1.0).abs() < EPSILON {
+                break;
+            }
+        }
+        (fraction * prefactor).clamp(0.0, 1.0)
+    }
+}
+
+/// probability that the value pairs 2k and 2k+1 of some samples are evened out by LSB embedding
+pub fn chi_square(samples: impl Iterator<Item = u16>, bit_depth: u8) -> f64 {
+    let mut histogram = vec![0u64; 1 << bit_depth];
+    for sample in samples {
+        histogram[sample as usize] += 1;
+    }
+    let (mut statistic, mut categories) = (0.0, 0usize);
+    for pair in histogram.chunks_exact(2) {
+        let expected = (pair[0] + pair[1]) as f64 / 2.0;
+        // pairs this rare carry no evidence either way
+        if expected < 5.0 {
+            continue;
+        }
+        statistic += (pair[0] as f64 - expected).powi(2) / expected;
+        categories += 1;
+    }
+    if categories < 2 {
+        return 0.0;
+    }
+    gamma_q((categories - 1) as f64 / 2.0, statistic / 2.0)
+}
+
+// how noisy a group of samples is: the discrimination function of RS analysis
+fn variation(group: &[i32; 4]) -> i32 {
+    group.windows(2).map(|pair| (pair[1] - pair[0]).abs()).sum()
+}
+
+// flipping by 1 swaps 2k and 2k+1, flipping by -1 swaps 2k-1 and 2k
+fn flip(value: i32, negative: bool) -> i32 {
+    if negative { ((value + 1) ^ 1) - 1 } else { value ^ 1 }
+}
+
+// proportions of regular and singular groups, for the mask and the negative mask
+fn rs_proportions(groups: &[[i32; 4]]) -> [f64; 4] {
+    let mut counts = [0usize; 4];
+    for group in groups {
+        let before = variation(group);
+        for (offset, negative) in [(0, false), (2, true)] {
+            let mut flipped = *group;
+            for (value, _) in flipped.iter_mut().zip(RS_MASK).filter(|(_, masked)| *masked) {
+                *value = flip(*value, negative);
+            }
+            let after = variation(&flipped);
+            if after > before {
+                counts[offset] += 1;
+            } else if after < before {
+                counts[offset + 1] += 1;
+            }
+        }
+    }
+    counts.map(|count| count as f64 / groups.len().max(1) as f64)
+}
+
+/// estimated share of samples carrying message bits, from RS analysis of groups of 4 neighbouring samples
+pub fn rs_analysis(groups: &[[i32; 4]]) -> f64 {
+    let flipped: Vec<[i32; 4]> = groups.iter().map(|group| group.map(|value| value ^ 1)).collect();
+    let [r, s, r_neg, s_neg] = rs_proportions(groups);
+    let [r_flipped, s_flipped, r_neg_flipped, s_neg_flipped] = rs_proportions(&flipped);
+    let (d0, d1) = (r - s, r_flipped - s_flipped);
+    let (d_neg0, d_neg1) = (r_neg - s_neg, r_neg_flipped - s_neg_flipped);
+
+    // 2(d1 + d0)x² + (d-0 - d-1 - d1 - 3d0)x + d0 - d-0 = 0, its root of smaller magnitude
+    let (a, b, c) = (2.0 * (d1 + d0), d_neg0 - d_neg1 - d1 - 3.0 * d0, d0 - d_neg0);
+    let x = if a.abs() < f64::EPSILON {
+        if b.abs() < f64::EPSILON { return 0.0; }
+        -c / b
+    } else {
+        let discriminant = b * b - 4.0 * a * c;
+        if discriminant < 0.0 {
+            return 0.0;
+        }
+        let roots = [(-b + discriminant.sqrt()) / (2.0 * a), (-b - discriminant.sqrt()) / (2.0 * a)];
+        if roots[0].abs() < roots[1].abs() { roots[0] } else { roots[1] }
+    };
+    if (x - 0.5).abs() < f64::EPSILON {
+        return 1.0;
+    }
+    // adding 0 turns -0 into 0
+    (x / (x - 0.5)).clamp(0.0, 1.0) + 0.0
+}
+
+// groups of 4 horizontally neighbouring samples of a channel
+fn rs_groups(pixels: &PixelBuffer, channel: usize) -> Vec<[i32; 4]> {
+    (0..pixels.height())
+        .flat_map(|y| {
+            (0..pixels.width() / 4).map(move |group| {
+                std::array::from_fn(|offset| pixels.sample(group * 4 + offset as u32, y, channel) as i32)
+            })
+        })
+        .collect()
+}
+
+/// entropy of the LSBs of some samples, packed into bytes, from 0 to 1
+pub fn lsb_entropy(samples: impl Iterator<Item = u16>) -> f64 {
+    let mut packed = Vec::new();
+    let (mut byte, mut bits) = (0u8, 0);
+    for sample in samples {
+        byte = byte << 1 | (sample & 1) as u8;
+        bits += 1;
+        if bits == 8 {
+            packed.push(byte);
+            (byte, bits) = (0, 0);
+        }
+    }
+    if packed.is_empty() {
+        return 0.0;
+    }
+    scan::entropy(&packed) / 8.0
+}
+
+/// analyze the LSBs of every channel of an image that could carry a message in them
+pub fn analyze(pixels: &PixelBuffer) -> Result<Vec<ChannelAnalysis>> {
+    lsb::check_carrier(pixels.ihdr())?;
+    let analyses = (0..pixels.channels())
+        .map(|channel| ChannelAnalysis {
+            channel,
+            name: channel_name(pixels.color_type(), channel),
+            chi_square: chi_square(channel_samples(pixels, channel), pixels.bit_depth()),
+            rs: rs_analysis(&rs_groups(pixels, channel)),
+            lsb_entropy: lsb_entropy(channel_samples(pixels, channel)),
+        })
+        .collect();
+    Ok(analyses)
+}
+
+/// an 8-bit image of the LSBs, black for 0 and white for 1: of a single channel in grayscale,
+/// or of all color channels; alpha is left out then, as it would hide the colors
+pub fn lsb_plane(pixels: &PixelBuffer, channel: Option<usize>) -> Result<PixelBuffer> {
+    let channels: Vec<usize> = match channel {
+        Some(channel) if channel >= pixels.channels() => {
+            return Err(anyhow!(PngMeError::AnalysisError::InvalidChannel(channel, pixels.channels())));
+        },
+        Some(channel) => vec![channel],
+        None => match pixels.color_type() {
+            ColorType::Rgb | ColorType::Rgba => vec![0, 1, 2],
+            _ => vec![0],
+        },
+    };
+    let color_type = if channels.len() == 3 { ColorType::Rgb } else { ColorType::Grayscale };
+    let ihdr = Ihdr::new(pixels.width(), pixels.height(), 8, color_type)?;
+    let samples = (0..pixels.samples().len() / pixels.channels())
+        .flat_map(|pixel| channels.iter().map(move |channel| pixel * pixels.channels() + channel))
+        .map(|index| if pixels.samples().get(index) & 1 == 1 { u8::MAX } else { 0 })
+        .collect();
+    PixelBuffer::new(ihdr, Samples::Eight(samples))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::sample_pixels;
+    use rand_chacha::ChaCha20Rng;
+    use rand_chacha::rand_core::{RngCore, SeedableRng};
+
+    // replace the LSBs of the first `share` of all samples with random bits
+    fn embed_random(pixels: &mut PixelBuffer, share: f64) {
+        let mut rng = ChaCha20Rng::seed_from_u64(7);
+        let count = (pixels.samples().len() as f64 * share) as usize;
+        for index in 0..count {
+            let value = pixels.samples().get(index);
+            pixels.samples_mut().set(index, value & !1 | (rng.next_u32() & 1) as u16);
+        }
+    }
+
+    #[test]
+    fn test_gamma_q() {
+        // chi-square with 2 degrees of freedom: Q(1, x/2) = exp(-x/2)
+        for x in [0.5, 2.0, 10.0] {
+            assert!((gamma_q(1.0, x / 2.0) - (-x / 2.0f64).exp()).abs() < 1e-9);
+        }
+        // median of chi-square with 10 degrees of freedom
+        assert!((gamma_q(5.0, 9.341818 / 2.0) - 0.5).abs() < 1e-5);
+        assert_eq!(gamma_q(3.0, 0.0), 1.0);
+    }
+
+    #[test]
+    fn test_chi_square() {
+        // pairs far from even
+        let uneven = (0..256u16).flat_map(|value| std::iter::repeat_n(value, if value % 2 == 0 { 100 } else { 10 }));
+        assert!(chi_square(uneven, 8) < 1e-6);
+        let even = (0..256u16).flat_map(|value| std::iter::repeat_n(value, 50));
+        assert!(chi_square(even, 8) > 0.99);
+        assert_eq!(chi_square(std::iter::empty(), 16), 0.0);
+    }
+
+    #[test]
+    fn test_clean_and_embedded_image() {
+        let mut pixels = sample_pixels();
+        let clean = analyze(&pixels).unwrap();
+        assert_eq!(clean.iter().map(|analysis| analysis.name).collect::<Vec<_>>(), vec!["red", "green", "blue", "alpha"]);
+        for analysis in &clean[..3] {
+            assert!(analysis.chi_square < 0.5, "{:?}", analysis);
+            assert!(analysis.rs < 0.15, "{:?}", analysis);
+        }
+
+        embed_random(&mut pixels, 1.0);
+        for analysis in &analyze(&pixels).unwrap()[..3] {
+            assert!(analysis.chi_square > 0.9, "{:?}", analysis);
+            assert!(analysis.rs > 0.7, "{:?}", analysis);
+            assert!(analysis.lsb_entropy > 0.99, "{:?}", analysis);
+            assert!(analysis.score() > 90);
+        }
+    }
+
+    #[test]
+    fn test_rs_estimates_partial_embedding() {
+        let mut pixels = sample_pixels();
+        let clean: Vec<f64> = analyze(&pixels).unwrap().iter().map(|analysis| analysis.rs).collect();
+        embed_random(&mut pixels, 0.5);
+        for (analysis, clean) in analyze(&pixels).unwrap()[..3].iter().zip(clean) {
+            assert!(analysis.rs > clean + 0.25, "{:?}", analysis);
+        }
+    }
+
+    #[test]
+    fn test_lsb_entropy() {
+        assert_eq!(lsb_entropy([2u16; 800].into_iter()), 0.0);
+        assert_eq!(lsb_entropy([1u16; 7].into_iter()), 0.0);
+        let counting = (0..256u16 * 8).map(|index| (index / 8) >> (7 - index % 8));
+        assert!((lsb_entropy(counting) - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_lsb_plane() {
+        let ihdr = Ihdr::new(2, 1, 16, ColorType::Rgba).unwrap();
+        let pixels = PixelBuffer::new(ihdr, Samples::Sixteen(vec![1, 2, 3, 5, 4, 7, 6, 9])).unwrap();
+
+        let colors = lsb_plane(&pixels, None).unwrap();
+        assert_eq!(colors.color_type(), ColorType::Rgb);
+        assert_eq!(colors.samples(), &Samples::Eight(vec![255, 0, 255, 0, 255, 0]));
+
+        let alpha = lsb_plane(&pixels, Some(3)).unwrap();
+        assert_eq!(alpha.color_type(), ColorType::Grayscale);
+        assert_eq!(alpha.samples(), &Samples::Eight(vec![255, 255]));
+
+        assert!(lsb_plane(&pixels, Some(4)).is_err());
+    }
+
+    #[test]
+    fn test_indexed_image_unsupported() {
+        let ihdr = Ihdr::new(1, 1, 8, ColorType::Indexed).unwrap();
+        let pixels = PixelBuffer::new(ihdr, Samples::Eight(vec![0])).unwrap();
+        assert!(analyze(&pixels).is_err());
+    }
+}
@@ -0,0 +1,104 @@
+use std::str::FromStr;
+
+use anyhow::Result;
+
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::png::Png;
+
+/*
+    Removal of everything a PNG does not need to be displayed, before it is published:
+    text, EXIF data, timestamps, and hidden messages in chunks of their own.
+
+    The critical chunks of the spec are always kept, any other chunk only if it is on an allowlist.
+    The allowlist is given as chunk types and presets:
+        color   chunks that change how the pixels look: color space and transparency
+        none    nothing, i.e. only the critical chunks remain
+    Chunks after IEND are removed even if on the allowlist, as no decoder reads them.
+
+    Messages hidden in the pixels by `encode --method lsb` survive, as the image data is kept untouched.
+*/
+
+/// the preset `--keep` uses unless told otherwise
+pub const DEFAULT_PRESET: &str = "color";
+/// chunk types of the `color` preset
+pub const COLOR_CHUNK_TYPES: [&str; 9] = ["cHRM", "gAMA", "iCCP", "sBIT", "sRGB", "cICP", "mDCV", "cLLI", "tRNS"];
+// the critical chunks defined by the spec, needed to decode the image
+const CRITICAL_CHUNK_TYPES: [&str; 4] = ["IHDR", "PLTE", "IDAT", "IEND"];
+
+/// expand presets and chunk types into the chunk types to keep; fails for anything else
+pub fn allowlist<S: AsRef<str>>(keep: &[S]) -> Result<Vec<String>> {
+    let mut chunk_types = Vec::new();
+    for entry in keep {
+        match entry.as_ref() {
+            "color" => chunk_types.extend(COLOR_CHUNK_TYPES.iter().map(|chunk_type| chunk_type.to_string())),
+            "none" => {},
+            chunk_type => chunk_types.push(ChunkType::from_str(chunk_type)?.to_string()),
+        }
+    }
+    Ok(chunk_types)
+}
+
+/// remove every chunk that is neither critical nor on the allowlist, and every chunk after IEND;
+/// returns the removed chunks in their original order
+pub fn strip(png: &mut Png, allowlist: &[String]) -> Vec<Chunk> {
+    let mut after_iend = false;
+    png.remove_chunks_where(|chunk| {
+        let chunk_type = chunk.chunk_type().to_string();
+        let keep = !after_iend
+            && (CRITICAL_CHUNK_TYPES.contains(&chunk_type.as_str()) || allowlist.contains(&chunk_type));
+        after_iend |= chunk_type == "IEND";
+        !keep
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{chunk, sample_png};
+
+    fn chunk_types(chunks: &[Chunk]) -> Vec<String> {
+        chunks.iter().map(|chunk| chunk.chunk_type().to_string()).collect()
+    }
+
+    fn metadata_png() -> Png {
+        let chunks = ["IHDR", "gAMA", "iCCP", "tEXt", "eXIf", "PLTE", "tRNS", "IDAT", "tIME", "ruSt", "IDAT", "IEND", "gAMA"];
+        Png::from_chunks(chunks.iter().map(|chunk_type| chunk(chunk_type, vec![1, 2, 3])).collect())
+    }
+
+    #[test]
+    fn test_allowlist() {
+        assert_eq!(allowlist(&["none"]).unwrap(), Vec::<String>::new());
+        let chunk_types = allowlist(&["color", "pHYs"]).unwrap();
+        assert_eq!(chunk_types.len(), COLOR_CHUNK_TYPES.len() + 1);
+        assert!(chunk_types.contains(&String::from("sRGB")) && chunk_types.contains(&String::from("pHYs")));
+        assert!(allowlist(&["colour"]).is_err());
+        assert!(allowlist(&["pH1s"]).is_err());
+    }
+
+    #[test]
+    fn test_strip_keep_color() {
+        let mut png = metadata_png();
+        let removed = strip(&mut png, &allowlist(&[DEFAULT_PRESET]).unwrap());
+        assert_eq!(chunk_types(&removed), vec!["tEXt", "eXIf", "tIME", "ruSt", "gAMA"]);
+        assert_eq!(chunk_types(png.chunks()), vec!["IHDR", "gAMA", "iCCP", "PLTE", "tRNS", "IDAT", "IDAT", "IEND"]);
+    }
+
+    #[test]
+    fn test_strip_keep_none() {
+        let mut png = metadata_png();
+        let removed = strip(&mut png, &allowlist(&["none"]).unwrap());
+        assert_eq!(removed.len(), 8);
+        assert_eq!(chunk_types(png.chunks()), vec!["IHDR", "PLTE", "IDAT", "IDAT", "IEND"]);
+        assert!(strip(&mut png, &[]).is_empty());
+    }
+
+    #[test]
+    fn test_strip_sample_image() {
+        // the secret of the sample image is a critical chunk after IEND
+        let mut png = sample_png();
+        let removed = strip(&mut png, &allowlist(&["none"]).unwrap());
+        assert!(chunk_types(&removed).contains(&String::from("RuSt")));
+        assert_eq!(png.chunks().last().unwrap().chunk_type().to_string(), "IEND");
+    }
+}
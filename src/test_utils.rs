@@ -0,0 +1,42 @@
+use std::env;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::pixels::{self, PixelBuffer};
+use crate::png::Png;
+
+/*
+    Fixtures shared by the unit tests of all modules.
+*/
+
+/// the sample image shipped with the repository: RGBA, 727x684, with a secret in a RuSt chunk after IEND
+pub const SAMPLE_PNG_PATH: &str = "cat_with_a_secret.png";
+
+/// a chunk type, which must be valid
+pub fn chunk_type(chunk_type: &str) -> ChunkType {
+    ChunkType::from_str(chunk_type).unwrap()
+}
+
+/// a chunk of the given type, which must be valid
+pub fn chunk(chunk_type: &str, data: Vec<u8>) -> Chunk {
+    Chunk::new(ChunkType::from_str(chunk_type).unwrap(), data)
+}
+
+/// the sample image
+pub fn sample_png() -> Png {
+    Png::read_file(SAMPLE_PNG_PATH).unwrap()
+}
+
+/// the decoded pixels of the sample image
+pub fn sample_pixels() -> PixelBuffer {
+    pixels::decode(&sample_png(), pixels::DEFAULT_MAX_IMAGE_DATA_LENGTH).unwrap()
+}
+
+/// a copy of the sample image in the temporary directory, free to be modified by the test named `name`
+pub fn scratch_png(name: &str) -> PathBuf {
+    let path = env::temp_dir().join(format!("pngme_{}_{}.png", name, std::process::id()));
+    std::fs::copy(SAMPLE_PNG_PATH, &path).unwrap();
+    path
+}
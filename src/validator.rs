@@ -0,0 +1,316 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::ihdr::{ColorType, Ihdr};
+use crate::png::Png;
+
+/*
+    Chunk ordering and multiplicity rules of the PNG spec.
+    Reference: http://www.libpng.org/pub/png/spec/1.2/PNG-Chunks.html#C.Summary-of-standard-chunks
+*/
+
+// the critical chunks defined by the spec; any other critical chunk cannot be decoded
+const KNOWN_CRITICAL: [&str; 4] = ["IHDR", "PLTE", "IDAT", "IEND"];
+// chunks that MUST NOT appear more than once
+const UNIQUE: [&str; 13] = [
+    "IHDR", "PLTE", "IEND", "cHRM", "gAMA", "iCCP", "sBIT", "sRGB", "bKGD", "hIST", "tRNS", "pHYs", "tIME",
+];
+// chunks that MUST precede PLTE (and therefore IDAT)
+const BEFORE_PLTE: [&str; 5] = ["cHRM", "gAMA", "iCCP", "sBIT", "sRGB"];
+// chunks that MUST follow PLTE, if there is one
+const AFTER_PLTE: [&str; 3] = ["bKGD", "hIST", "tRNS"];
+// chunks that MUST precede the first IDAT
+const BEFORE_IDAT: [&str; 11] = [
+    "PLTE", "cHRM", "gAMA", "iCCP", "sBIT", "sRGB", "bKGD", "hIST", "tRNS", "pHYs", "sPLT",
+];
+
+/// The ways in which a PNG can break the chunk ordering and multiplicity rules
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ViolationKind {
+    // the PNG contains no chunks at all
+    MissingIhdr,
+    // the first chunk is something else than IHDR
+    FirstChunkNotIhdr(String),
+    // the first chunk is IHDR, but its contents are not valid
+    InvalidIhdr(String),
+    // a chunk that may only appear once was repeated
+    DuplicateChunk(String),
+    // the first chunk MUST come before the second one
+    OutOfOrder(String, String),
+    // IDAT chunks MUST be consecutive
+    NonContiguousIdat,
+    // there is no image data at all
+    MissingIdat,
+    // indexed-color images require a palette
+    MissingPlte,
+    // grayscale images MUST NOT carry a palette
+    UnexpectedPlte(ColorType),
+    // there is no IEND chunk
+    MissingIend,
+    // IEND MUST be the last chunk
+    ChunkAfterIend(String),
+    // critical chunk not defined by the spec; decoders are unable to display the image
+    UnknownCriticalChunk(String),
+    // chunks that SHOULD NOT appear together
+    ConflictingChunks(String, String),
+}
+
+impl fmt::Display for ViolationKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ViolationKind::MissingIhdr => write!(f, "missing IHDR chunk"),
+            ViolationKind::FirstChunkNotIhdr(chunk_type) => {
+                write!(f, "first chunk MUST be IHDR, found {}", chunk_type)
+            },
+            ViolationKind::InvalidIhdr(reason) => write!(f, "invalid IHDR: {}", reason),
+            ViolationKind::DuplicateChunk(chunk_type) => {
+                write!(f, "multiple {} chunks are not allowed", chunk_type)
+            },
+            ViolationKind::OutOfOrder(chunk_type, successor) => {
+                write!(f, "{} chunk MUST precede {}", chunk_type, successor)
+            },
+            ViolationKind::NonContiguousIdat => write!(f, "IDAT chunks MUST be consecutive"),
+            ViolationKind::MissingIdat => write!(f, "missing IDAT chunk"),
+            ViolationKind::MissingPlte => write!(f, "missing PLTE chunk required by indexed-color image"),
+            ViolationKind::UnexpectedPlte(color_type) => {
+                write!(f, "PLTE chunk is not allowed for color type {}", color_type)
+            },
+            ViolationKind::MissingIend => write!(f, "missing IEND chunk"),
+            ViolationKind::ChunkAfterIend(chunk_type) => {
+                write!(f, "{} chunk found after IEND", chunk_type)
+            },
+            ViolationKind::UnknownCriticalChunk(chunk_type) => {
+                write!(f, "unknown critical chunk {}", chunk_type)
+            },
+            ViolationKind::ConflictingChunks(first, second) => {
+                write!(f, "{} and {} chunks SHOULD NOT both be present", first, second)
+            },
+        }
+    }
+}
+
+/// A single rule violation; file-level violations (e.g. a missing chunk) carry no location
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Violation {
+    pub chunk_index: Option<usize>,
+    pub offset: Option<usize>,
+    pub kind: ViolationKind,
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.chunk_index, self.offset) {
+            (Some(index), Some(offset)) => {
+                write!(f, "chunk {} at offset 0x{:05x}: {}", index, offset, self.kind)
+            },
+            _ => write!(f, "{}", self.kind),
+        }
+    }
+}
+
+/// check a PNG against the chunk ordering and multiplicity rules of the spec;
+/// returns every violation found, an empty list means the structure is valid
+pub fn validate(png: &Png) -> Vec<Violation> {
+    let chunks = png.chunks();
+    let offsets = png.chunk_offsets();
+    let types: Vec<String> = chunks
+        .iter()
+        .map(|chunk| chunk.chunk_type().to_string())
+        .collect();
+
+    let mut violations = Vec::new();
+    let mut report = |index: usize, kind: ViolationKind| {
+        violations.push(Violation { chunk_index: Some(index), offset: Some(offsets[index]), kind });
+    };
+
+    // header
+    let mut header: Option<Ihdr> = None;
+    if let Some(first_chunk) = chunks.first() {
+        if types[0] != "IHDR" {
+            report(0, ViolationKind::FirstChunkNotIhdr(types[0].clone()));
+        } else {
+            match Ihdr::try_from(first_chunk) {
+                Ok(ihdr) => header = Some(ihdr),
+                Err(err) => report(0, ViolationKind::InvalidIhdr(err.to_string())),
+            }
+        }
+    }
+
+    let first_plte = types.iter().position(|chunk_type| chunk_type == "PLTE");
+    let first_idat = types.iter().position(|chunk_type| chunk_type == "IDAT");
+    let iend = types.iter().position(|chunk_type| chunk_type == "IEND");
+
+    let mut counts: HashMap<&str, usize> = HashMap::new();
+    for (index, (chunk, chunk_type)) in chunks.iter().zip(types.iter()).enumerate() {
+        let chunk_type = chunk_type.as_str();
+        let count = counts.entry(chunk_type).or_insert(0);
+        *count += 1;
+
+        if *count > 1 && UNIQUE.contains(&chunk_type) {
+            report(index, ViolationKind::DuplicateChunk(chunk_type.to_string()));
+        }
+        if index > 0 && chunk_type == "IHDR" && types[0] != "IHDR" {
+            report(index, ViolationKind::OutOfOrder(chunk_type.to_string(), types[0].clone()));
+        }
+        if chunk.chunk_type().is_critical() && !KNOWN_CRITICAL.contains(&chunk_type) {
+            report(index, ViolationKind::UnknownCriticalChunk(chunk_type.to_string()));
+        }
+        if iend.is_some_and(|iend| index > iend) {
+            report(index, ViolationKind::ChunkAfterIend(chunk_type.to_string()));
+        }
+        if let Some(first_plte) = first_plte {
+            if index > first_plte && BEFORE_PLTE.contains(&chunk_type) {
+                report(index, ViolationKind::OutOfOrder(chunk_type.to_string(), String::from("PLTE")));
+            }
+            if index < first_plte && AFTER_PLTE.contains(&chunk_type) {
+                report(index, ViolationKind::OutOfOrder(String::from("PLTE"), chunk_type.to_string()));
+            }
+        }
+        if let Some(first_idat) = first_idat {
+            if index > first_idat && BEFORE_IDAT.contains(&chunk_type) {
+                report(index, ViolationKind::OutOfOrder(chunk_type.to_string(), String::from("IDAT")));
+            }
+            if index > first_idat && chunk_type == "IDAT" && types[index - 1] != "IDAT" {
+                report(index, ViolationKind::NonContiguousIdat);
+            }
+        }
+    }
+
+    // palette requirements depend on the color type
+    if let Some(header) = header {
+        match (header.color_type(), first_plte) {
+            (ColorType::Indexed, None) => {
+                violations.push(Violation { chunk_index: None, offset: None, kind: ViolationKind::MissingPlte });
+            },
+            (color_type @ (ColorType::Grayscale | ColorType::GrayscaleAlpha), Some(first_plte)) => {
+                violations.push(Violation {
+                    chunk_index: Some(first_plte),
+                    offset: Some(offsets[first_plte]),
+                    kind: ViolationKind::UnexpectedPlte(color_type),
+                });
+            },
+            _ => {},
+        }
+    }
+
+    // required chunks
+    let missing = [
+        (types.is_empty(), ViolationKind::MissingIhdr),
+        (first_idat.is_none(), ViolationKind::MissingIdat),
+        (iend.is_none(), ViolationKind::MissingIend),
+    ];
+    for (is_missing, kind) in missing {
+        if is_missing {
+            violations.push(Violation { chunk_index: None, offset: None, kind });
+        }
+    }
+
+    if counts.contains_key("iCCP") && counts.contains_key("sRGB") {
+        violations.push(Violation {
+            chunk_index: None,
+            offset: None,
+            kind: ViolationKind::ConflictingChunks(String::from("iCCP"), String::from("sRGB")),
+        });
+    }
+
+    violations
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::Chunk;
+    use crate::test_utils::chunk;
+
+    fn minimal_chunk(chunk_type: &str) -> Chunk {
+        let data = match chunk_type {
+            "IHDR" => vec![0, 0, 0, 1, 0, 0, 0, 1, 8, 2, 0, 0, 0],
+            _ => vec![],
+        };
+        chunk(chunk_type, data)
+    }
+
+    fn png_of(chunk_types: &[&str]) -> Png {
+        Png::from_chunks(chunk_types.iter().map(|chunk_type| minimal_chunk(chunk_type)).collect())
+    }
+
+    fn kinds(png: &Png) -> Vec<ViolationKind> {
+        validate(png).into_iter().map(|violation| violation.kind).collect()
+    }
+
+    #[test]
+    fn test_valid_structure() {
+        let png = png_of(&["IHDR", "gAMA", "PLTE", "bKGD", "IDAT", "IDAT", "tEXt", "IEND"]);
+        assert!(validate(&png).is_empty());
+    }
+
+    #[test]
+    fn test_empty_png() {
+        let png = png_of(&[]);
+        assert_eq!(
+            kinds(&png),
+            vec![ViolationKind::MissingIhdr, ViolationKind::MissingIdat, ViolationKind::MissingIend]
+        );
+    }
+
+    #[test]
+    fn test_first_chunk_not_ihdr() {
+        let png = png_of(&["gAMA", "IHDR", "IDAT", "IEND"]);
+        let violations = validate(&png);
+        assert_eq!(violations[0].kind, ViolationKind::FirstChunkNotIhdr(String::from("gAMA")));
+        assert_eq!(violations[0].offset, Some(8));
+    }
+
+    #[test]
+    fn test_multiple_iend() {
+        let png = png_of(&["IHDR", "IDAT", "IEND", "IEND"]);
+        let violations = validate(&png);
+        assert_eq!(violations.len(), 2);
+        assert_eq!(violations[0].chunk_index, Some(3));
+        assert_eq!(violations[0].kind, ViolationKind::DuplicateChunk(String::from("IEND")));
+        assert_eq!(violations[1].kind, ViolationKind::ChunkAfterIend(String::from("IEND")));
+    }
+
+    #[test]
+    fn test_non_contiguous_idat() {
+        let png = png_of(&["IHDR", "IDAT", "tEXt", "IDAT", "IEND"]);
+        let violations = validate(&png);
+        assert_eq!(violations.len(), 1);
+        assert_eq!(violations[0].chunk_index, Some(3));
+        assert_eq!(violations[0].offset, Some(8 + 25 + 12 + 12));
+        assert_eq!(violations[0].kind, ViolationKind::NonContiguousIdat);
+    }
+
+    #[test]
+    fn test_plte_after_idat() {
+        let png = png_of(&["IHDR", "IDAT", "PLTE", "IEND"]);
+        assert_eq!(
+            kinds(&png),
+            vec![ViolationKind::OutOfOrder(String::from("PLTE"), String::from("IDAT"))]
+        );
+    }
+
+    #[test]
+    fn test_ancillary_chunks_out_of_order() {
+        let png = png_of(&["IHDR", "bKGD", "PLTE", "gAMA", "IDAT", "IEND"]);
+        assert_eq!(
+            kinds(&png),
+            vec![
+                ViolationKind::OutOfOrder(String::from("PLTE"), String::from("bKGD")),
+                ViolationKind::OutOfOrder(String::from("gAMA"), String::from("PLTE")),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_unknown_critical_chunk() {
+        let png = png_of(&["IHDR", "IDAT", "IEND", "RuSt"]);
+        assert_eq!(
+            kinds(&png),
+            vec![
+                ViolationKind::UnknownCriticalChunk(String::from("RuSt")),
+                ViolationKind::ChunkAfterIend(String::from("RuSt")),
+            ]
+        );
+    }
+}